@@ -0,0 +1,667 @@
+use crate::config::Config;
+use crate::dates::format_rfc3339;
+use crate::sheet::{
+    fetch_and_parse, http_client, FetchError, ParseStats, Parsed, SheetSource, Validators,
+};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::OnceCell;
+
+// ---------------------------------------------------------------------------
+// Backend: on-disk caching of the processed sheet data
+// ---------------------------------------------------------------------------
+
+/// Where a dataset was loaded from, reported in the `X-Data-Source` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataSource {
+    /// The on-disk cache of an earlier sheet fetch.
+    Cache,
+    /// A fresh fetch of the Google Sheet.
+    Sheet,
+    /// The last-known-good seed data, used when everything else failed.
+    Seed,
+    /// The snapshot compiled into the binary, served with `GKC_OFFLINE`.
+    Embedded,
+}
+
+impl DataSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DataSource::Cache => "cache",
+            DataSource::Sheet => "sheet",
+            DataSource::Seed => "seed",
+            DataSource::Embedded => "embedded",
+        }
+    }
+}
+
+/// Snapshot of the processed sheet data compiled into the binary, for
+/// environments with no network at all.
+const EMBEDDED_DATA: &str = include_str!("../assets/offline_data.json");
+
+/// How long each step of one load took, reported in `Server-Timing`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadTiming {
+    /// Checking and reading the disk cache.
+    pub cache: Duration,
+    /// Fetching and parsing the sheet (or the seed/embedded data).
+    pub fetch: Duration,
+    /// Building the index and serializing the `/data` body.
+    pub serialize: Duration,
+}
+
+impl LoadTiming {
+    /// The `Server-Timing` header value, in milliseconds.
+    pub fn header_value(&self) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        format!(
+            "cache;dur={:.1}, fetch;dur={:.1}, serialize;dur={:.1}",
+            ms(self.cache),
+            ms(self.fetch),
+            ms(self.serialize)
+        )
+    }
+}
+
+/// Processed sheet data along with when and where it was obtained.
+pub struct Fetched {
+    pub data: Value,
+    pub fetched_at: SystemTime,
+    pub source: DataSource,
+    /// Time spent on the cache and fetch steps (`serialize` is left at zero).
+    pub timing: LoadTiming,
+    /// Why a fresh fetch was refused in favor of the previous cache, if it was.
+    pub rejected: Option<String>,
+    /// Why the sheet couldn't be fetched, when the expired cache was served instead.
+    pub fetch_error: Option<String>,
+    /// What parsing the sheet saw, when it was fetched (even if refused).
+    pub parse_stats: Option<ParseStats>,
+    /// The records' keys in sheet order; empty when unknown (e.g. seed data).
+    pub columns: Vec<String>,
+}
+
+/// The disaster-recovery dataset from `GKC_SEED_FILE` or `GKC_SEED_URL`,
+/// read at most once per process.
+#[derive(Default)]
+pub struct Seed {
+    data: OnceCell<Value>,
+}
+
+impl Seed {
+    /// The seed data, loading it on first use. `Ok(None)` means no seed is configured.
+    async fn get(&self, config: &Config) -> Result<Option<&Value>, FetchError> {
+        if config.seed_file.is_none() && config.seed_url.is_none() {
+            return Ok(None);
+        }
+        let data = self
+            .data
+            .get_or_try_init(|| async {
+                let raw = match (&config.seed_file, &config.seed_url) {
+                    (Some(path), _) => fs::read_to_string(path).await?,
+                    (None, Some(url)) => {
+                        let client = http_client(&config.user_agent, config.proxy.as_deref());
+                        client.get(url).send().await?.text().await?
+                    }
+                    (None, None) => unreachable!("checked above"),
+                };
+                Ok::<_, FetchError>(serde_json::from_str(&raw)?)
+            })
+            .await?;
+        Ok(Some(data))
+    }
+}
+
+/// Where an existing cache may be: `cache_file`, and right after compression
+/// is switched on, the plain file written before (used until the next fetch
+/// replaces it).
+fn cache_candidates(cache_file: &str) -> Vec<&str> {
+    let mut candidates = vec![cache_file];
+    if let Some(plain) = cache_file.strip_suffix(".gz") {
+        candidates.push(plain);
+    }
+    candidates
+}
+
+/// The records in the existing cache, however old, and the file they were
+/// read from, if it can be read.
+async fn previous_cache(cache_file: &str) -> Option<(&str, Value)> {
+    for path in cache_candidates(cache_file) {
+        if let Ok(raw) = read_cache_file(path).await {
+            return serde_json::from_str(&raw).ok().map(|data| (path, data));
+        }
+    }
+    None
+}
+
+/// A file kept next to the cache: `cache{suffix}` beside `cache.json` (or
+/// `cache.json.gz`).
+fn sidecar_path(cache_file: &str, suffix: &str) -> String {
+    let stem = cache_file.strip_suffix(".gz").unwrap_or(cache_file);
+    let stem = stem.strip_suffix(".json").unwrap_or(stem);
+    format!("{}{}", stem, suffix)
+}
+
+/// Where the validators of the download behind the cache are kept:
+/// `cache.validators.json`.
+fn validators_path(cache_file: &str) -> String {
+    sidecar_path(cache_file, ".validators.json")
+}
+
+/// Where the sheet order of the cached records' keys is kept:
+/// `cache.columns.json`.
+fn columns_path(cache_file: &str) -> String {
+    sidecar_path(cache_file, ".columns.json")
+}
+
+/// The column order saved with the cache, or none if it is missing or
+/// unreadable.
+async fn read_columns(cache_file: &str) -> Vec<String> {
+    match fs::read_to_string(columns_path(cache_file)).await {
+        Ok(raw) => serde_json::from_str(&raw).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Where the last downloaded CSV is kept with `GKC_KEEP_RAW_CSV`: `cache.raw.csv`.
+pub fn raw_csv_path(cache_file: &str) -> String {
+    sidecar_path(cache_file, ".raw.csv")
+}
+
+/// Keep the CSV exactly as downloaded, for auditing the parse. Failing to
+/// save it doesn't fail the load.
+async fn save_raw_csv(cache_file: &str, raw: &[u8], config: &Config) {
+    let path = raw_csv_path(cache_file);
+    if let Some(dir) = &config.cache_dir {
+        let _ = fs::create_dir_all(dir).await;
+    }
+    if let Err(e) = fs::write(&path, raw).await {
+        println!("Could not save the raw CSV to {}: {}", path, e);
+    }
+}
+
+/// Validators as saved on disk, with the processing settings the cached
+/// records were produced under.
+#[derive(Serialize, Deserialize)]
+struct SavedValidators {
+    #[serde(flatten)]
+    validators: Validators,
+    processing: String,
+}
+
+/// The validators saved with the cache, or none if there are none or the
+/// cache was processed with different settings (so it must be re-parsed).
+async fn read_validators(cache_file: &str, config: &Config) -> Validators {
+    let saved = match fs::read_to_string(validators_path(cache_file)).await {
+        Ok(raw) => serde_json::from_str::<SavedValidators>(&raw).ok(),
+        Err(_) => None,
+    };
+    saved
+        .filter(|saved| saved.processing == config.processing_fingerprint())
+        .map(|saved| saved.validators)
+        .unwrap_or_default()
+}
+
+/// Save the validators that go with a freshly written cache, removing stale
+/// ones when the upstream sent none.
+async fn write_validators(
+    cache_file: &str,
+    validators: Validators,
+    config: &Config,
+) -> Result<(), FetchError> {
+    let path = validators_path(cache_file);
+    if validators.is_empty() {
+        let _ = fs::remove_file(path).await;
+    } else {
+        let saved = SavedValidators {
+            validators,
+            processing: config.processing_fingerprint(),
+        };
+        fs::write(path, serde_json::to_string(&saved)?).await?;
+    }
+    Ok(())
+}
+
+/// Mark the cache at `path` as fresh without rewriting it.
+async fn touch(path: &str) -> std::io::Result<()> {
+    let file = fs::OpenOptions::new().append(true).open(path).await?;
+    file.into_std().await.set_modified(SystemTime::now())
+}
+
+/// Why going from `before` to `after` records looks like a bad edit rather
+/// than a real change: more than `max_drop_pct` percent of them vanished.
+fn suspicious_drop(before: usize, after: usize, max_drop_pct: f64) -> Option<String> {
+    if before == 0 || after >= before {
+        return None;
+    }
+    let drop_pct = (before - after) as f64 * 100.0 / before as f64;
+    (drop_pct > max_drop_pct).then(|| {
+        format!(
+            "Refresh dropped from {} to {} records ({:.0}%, over the {}% limit)",
+            before, after, drop_pct, max_drop_pct
+        )
+    })
+}
+
+/// Why a parse should be refused for having more than `max_pct` percent of
+/// its rows misaligned with the header, if it should.
+fn too_misaligned(stats: &ParseStats, max_pct: f64) -> Option<String> {
+    if stats.records == 0 || stats.misaligned_rows == 0 {
+        return None;
+    }
+    let pct = stats.misaligned_rows as f64 * 100.0 / stats.records as f64;
+    (pct > max_pct).then(|| {
+        format!(
+            "{} of {} rows don't match the header ({:.0}%, over the {}% limit)",
+            stats.misaligned_rows, stats.records, pct, max_pct
+        )
+    })
+}
+
+/// Read a cache file, decompressing it if its name ends in `.gz`.
+pub async fn read_cache_file(path: &str) -> std::io::Result<String> {
+    let raw = fs::read(path).await?;
+    if !path.ends_with(".gz") {
+        return String::from_utf8(raw)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+    }
+    let mut text = String::new();
+    GzDecoder::new(raw.as_slice()).read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// Write a cache file, gzipping it if its name ends in `.gz`.
+pub async fn write_cache_file(path: &str, contents: &str) -> std::io::Result<()> {
+    let bytes = if path.ends_with(".gz") {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents.as_bytes())?;
+        encoder.finish()?
+    } else {
+        contents.as_bytes().to_vec()
+    };
+    let mut file = fs::File::create(path).await?;
+    file.write_all(&bytes).await?;
+    // tokio writes in the background; make sure it landed before returning.
+    file.flush().await
+}
+
+/// What a self-check of the disk cache against the served records found.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheCheck {
+    /// `ok`, `mismatch` (readable, but holding a different number of
+    /// records), `repaired` (missing or corrupt, rewritten from memory), or
+    /// `repair_failed`.
+    pub status: &'static str,
+    /// What was wrong; absent when the check passed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+    /// RFC 3339 time of the check.
+    pub checked_at: String,
+}
+
+/// Re-read the disk cache and compare its record count with `records`, the
+/// ones being served. A missing or corrupt cache is rewritten from them.
+pub async fn check_cache(config: &Config, records: &[Value], fetched_at: SystemTime) -> CacheCheck {
+    let path = &config.cache_path();
+    let parsed = match read_cache_file(path).await {
+        Ok(raw) => serde_json::from_str::<Value>(&raw)
+            .map_err(|e| format!("{} does not parse: {}", path, e)),
+        Err(e) => Err(format!("{} is unreadable: {}", path, e)),
+    };
+    let (status, problem) = match parsed {
+        Ok(data) => {
+            let on_disk = data.as_array().map_or(1, Vec::len);
+            if on_disk == records.len() {
+                ("ok", None)
+            } else {
+                let problem = format!(
+                    "{} holds {} records but {} are served",
+                    path,
+                    on_disk,
+                    records.len()
+                );
+                println!("Warning: cache check: {}", problem);
+                ("mismatch", Some(problem))
+            }
+        }
+        Err(problem) => match rewrite_cache(path, records, fetched_at, config).await {
+            Ok(()) => {
+                println!("Warning: cache check: {}; rewrote it from memory.", problem);
+                ("repaired", Some(problem))
+            }
+            Err(e) => {
+                println!(
+                    "Warning: cache check: {}; rewriting it failed: {}",
+                    problem, e
+                );
+                ("repair_failed", Some(problem))
+            }
+        },
+    };
+    CacheCheck {
+        status,
+        problem,
+        checked_at: format_rfc3339(SystemTime::now()),
+    }
+}
+
+/// Write the served `records` back to the cache as a fetch would have, minus
+/// their IDs, dated `fetched_at` so the cache's TTL is unchanged.
+async fn rewrite_cache(
+    path: &str,
+    records: &[Value],
+    fetched_at: SystemTime,
+    config: &Config,
+) -> Result<(), FetchError> {
+    let records: Vec<Value> = records
+        .iter()
+        .cloned()
+        .map(|mut record| {
+            if let Some(fields) = record.as_object_mut() {
+                fields.remove("id");
+            }
+            record
+        })
+        .collect();
+    let json_string = if config.cache_pretty {
+        serde_json::to_string_pretty(&records)?
+    } else {
+        serde_json::to_string(&records)?
+    };
+    if let Some(dir) = &config.cache_dir {
+        fs::create_dir_all(dir).await?;
+    }
+    write_cache_file(path, &json_string).await?;
+    let file = fs::OpenOptions::new().write(true).open(path).await?;
+    file.into_std().await.set_modified(fetched_at)?;
+    Ok(())
+}
+
+/// The `required` columns that appear in none of the records.
+fn missing_columns(data: &Value, required: &[String]) -> Vec<String> {
+    let records = data.as_array().map(Vec::as_slice).unwrap_or_default();
+    required
+        .iter()
+        .filter(|column| !records.iter().any(|r| r.get(column.as_str()).is_some()))
+        .cloned()
+        .collect()
+}
+
+/// Fetch the sheet data with caching, along with the time it was fetched.
+/// The disk cache is reused while it is younger than `ttl`, and past that
+/// whenever the sheet can't be fetched; if it is missing or corrupt too, the
+/// seed data is used.
+/// In offline mode only the embedded snapshot is used.
+pub async fn fetch_sheet_data(
+    source: &dyn SheetSource,
+    config: &Config,
+    ttl: Duration,
+    seed: &Seed,
+) -> Result<Fetched, FetchError> {
+    let started = Instant::now();
+    if config.offline {
+        println!("Offline mode: serving the embedded dataset.");
+        return Ok(Fetched {
+            data: serde_json::from_str(EMBEDDED_DATA)?,
+            fetched_at: SystemTime::now(),
+            source: DataSource::Embedded,
+            timing: LoadTiming {
+                fetch: started.elapsed(),
+                ..LoadTiming::default()
+            },
+            rejected: None,
+            fetch_error: None,
+            parse_stats: None,
+            columns: Vec::new(),
+        });
+    }
+
+    let cache_file = &config.cache_path();
+    for path in cache_candidates(cache_file) {
+        if let Ok(metadata) = fs::metadata(path).await {
+            if let Ok(modified) = metadata.modified() {
+                if let Ok(elapsed) = modified.elapsed() {
+                    if elapsed < ttl {
+                        println!("Using cached data (age: {:?})", elapsed);
+                        let cached_data = match read_cache_file(path).await {
+                            Ok(raw) => raw,
+                            Err(e) => {
+                                println!("Ignoring unreadable cache file {}: {}", path, e);
+                                continue;
+                            }
+                        };
+                        match serde_json::from_str(&cached_data) {
+                            Ok(json_data) => {
+                                return Ok(Fetched {
+                                    data: json_data,
+                                    fetched_at: modified,
+                                    source: DataSource::Cache,
+                                    timing: LoadTiming {
+                                        cache: started.elapsed(),
+                                        ..LoadTiming::default()
+                                    },
+                                    rejected: None,
+                                    fetch_error: None,
+                                    parse_stats: None,
+                                    columns: read_columns(cache_file).await,
+                                });
+                            }
+                            Err(e) => println!("Ignoring corrupt cache file {}: {}", path, e),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let cache_time = started.elapsed();
+    let started = Instant::now();
+    let previous = previous_cache(cache_file).await;
+    // Only ask whether the sheet changed when there is a cache to fall back on.
+    let validators = match &previous {
+        Some(_) if config.conditional_fetch => read_validators(cache_file, config).await,
+        _ => Validators::default(),
+    };
+    println!("Fetching fresh data from Google Sheets...");
+    let Parsed {
+        data: json_data,
+        stats: parse_stats,
+        validators,
+        raw,
+        columns,
+    } = match fetch_and_parse(source, config, &validators).await {
+        Ok(Some(parsed)) => parsed,
+        Ok(None) => {
+            let Some((path, data)) = previous else {
+                return Err(FetchError::Unexpected(
+                    "304 Not Modified without a cache to keep".into(),
+                ));
+            };
+            println!("Sheet unchanged since the last fetch; keeping the cached data.");
+            if let Err(e) = touch(path).await {
+                println!("Could not refresh the timestamp of {}: {}", path, e);
+            }
+            return Ok(Fetched {
+                data,
+                fetched_at: SystemTime::now(),
+                source: DataSource::Cache,
+                timing: LoadTiming {
+                    cache: cache_time,
+                    fetch: started.elapsed(),
+                    ..LoadTiming::default()
+                },
+                rejected: None,
+                fetch_error: None,
+                parse_stats: None,
+                columns: read_columns(cache_file).await,
+            });
+        }
+        Err(e) => match previous {
+            Some((path, data)) => {
+                // The sheet may be gone or private; the last good data beats none.
+                println!(
+                    "Fetching the sheet failed ({}); serving the expired cache.",
+                    e
+                );
+                let fetch_error = Some(e.to_string());
+                let modified = fs::metadata(path).await.and_then(|m| m.modified());
+                return Ok(Fetched {
+                    data,
+                    fetched_at: modified.unwrap_or_else(|_| SystemTime::now()),
+                    source: DataSource::Cache,
+                    timing: LoadTiming {
+                        cache: cache_time,
+                        fetch: started.elapsed(),
+                        ..LoadTiming::default()
+                    },
+                    rejected: None,
+                    fetch_error,
+                    parse_stats: None,
+                    columns: read_columns(cache_file).await,
+                });
+            }
+            None => match seed.get(config).await {
+                Ok(Some(seed_data)) => {
+                    println!("Fetching the sheet failed ({}); serving seed data.", e);
+                    return Ok(Fetched {
+                        data: seed_data.clone(),
+                        fetched_at: SystemTime::now(),
+                        source: DataSource::Seed,
+                        timing: LoadTiming {
+                            cache: cache_time,
+                            fetch: started.elapsed(),
+                            ..LoadTiming::default()
+                        },
+                        rejected: None,
+                        fetch_error: Some(e.to_string()),
+                        parse_stats: None,
+                        columns: Vec::new(),
+                    });
+                }
+                Ok(None) => return Err(e),
+                Err(seed_error) => {
+                    println!("Loading seed data failed: {}", seed_error);
+                    return Err(e);
+                }
+            },
+        },
+    };
+
+    let fetch_time = started.elapsed();
+    // Saved before the checks below, so a refused refresh can be inspected too.
+    if config.keep_raw_csv {
+        save_raw_csv(cache_file, &raw, config).await;
+    }
+
+    // Refuse data that lost a required column, so the old cache survives.
+    let missing = missing_columns(&json_data, &config.required_columns);
+    if !missing.is_empty() {
+        return Err(FetchError::Schema(format!(
+            "Sheet is missing required column(s): {}",
+            missing.join(", ")
+        )));
+    }
+
+    // Refuse a fetch with too many rows misaligned with the header (a broken
+    // export), or that lost most of the records (e.g. an edit emptied the
+    // sheet), and keep serving the previous cache, which stays on disk.
+    let count = |data: &Value| data.as_array().map_or(1, Vec::len);
+    let reason = too_misaligned(&parse_stats, config.max_misaligned_pct).or_else(|| {
+        let (_, previous) = previous.as_ref()?;
+        suspicious_drop(
+            count(previous),
+            count(&json_data),
+            config.max_record_drop_pct,
+        )
+    });
+    if let Some(reason) = reason {
+        let Some((_, previous)) = previous else {
+            return Err(FetchError::Schema(reason));
+        };
+        println!("Warning: {}; keeping the previous cache.", reason);
+        return Ok(Fetched {
+            data: previous,
+            // Counted as fresh so the sheet is rechecked after one TTL,
+            // not on every request.
+            fetched_at: SystemTime::now(),
+            source: DataSource::Cache,
+            timing: LoadTiming {
+                cache: cache_time,
+                fetch: fetch_time,
+                ..LoadTiming::default()
+            },
+            rejected: Some(reason),
+            fetch_error: None,
+            parse_stats: Some(parse_stats),
+            columns: read_columns(cache_file).await,
+        });
+    }
+
+    // Save fresh data to cache.
+    let json_string = if config.cache_pretty {
+        serde_json::to_string_pretty(&json_data)?
+    } else {
+        serde_json::to_string(&json_data)?
+    };
+    if let Some(dir) = &config.cache_dir {
+        fs::create_dir_all(dir).await?;
+    }
+    write_cache_file(cache_file, &json_string).await?;
+    if let Some(plain) = cache_file.strip_suffix(".gz") {
+        // Superseded by the compressed copy.
+        let _ = fs::remove_file(plain).await;
+    }
+    write_validators(cache_file, validators, config).await?;
+    fs::write(columns_path(cache_file), serde_json::to_string(&columns)?).await?;
+    println!("Saved new data to cache.");
+
+    Ok(Fetched {
+        data: json_data,
+        fetched_at: SystemTime::now(),
+        source: DataSource::Sheet,
+        timing: LoadTiming {
+            cache: cache_time,
+            fetch: fetch_time,
+            ..LoadTiming::default()
+        },
+        rejected: None,
+        fetch_error: None,
+        parse_stats: Some(parse_stats),
+        columns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_drops_past_the_limit() {
+        assert!(suspicious_drop(100, 0, 90.0).is_some());
+        assert!(suspicious_drop(100, 5, 90.0).is_some());
+        assert!(suspicious_drop(100, 10, 90.0).is_none());
+        assert!(suspicious_drop(100, 150, 90.0).is_none());
+        assert!(suspicious_drop(0, 0, 90.0).is_none());
+        assert!(suspicious_drop(100, 0, 100.0).is_none());
+    }
+
+    #[test]
+    fn flags_misaligned_rows_past_the_limit() {
+        let stats = |misaligned_rows| ParseStats {
+            records: 20,
+            misaligned_rows,
+            ..ParseStats::default()
+        };
+        assert!(too_misaligned(&stats(0), 0.0).is_none());
+        assert!(too_misaligned(&stats(2), 10.0).is_none());
+        let reason = too_misaligned(&stats(3), 10.0).unwrap();
+        assert!(reason.starts_with("3 of 20 rows"), "{}", reason);
+        assert!(too_misaligned(&stats(20), 100.0).is_none());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::filter::SORT_FIELDS;
+use crate::search::SEARCH_FIELDS;
+use crate::status::BanStatus;
+use crate::tree::GROUP_FIELDS;
+use crate::{DEFAULT_RADIUS_MI, DEFAULT_RECENT_LIMIT};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde_json::{json, Value};
+
+// ---------------------------------------------------------------------------
+// Query parameters accepted by each endpoint (`/capabilities`)
+// ---------------------------------------------------------------------------
+
+/// The query parameters a `web::Query<T>` accepts: the field names `T`'s
+/// `Deserialize` impl asks for, so the list follows the struct handlers use.
+pub fn fields_of<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    // The recorder always fails once it has seen the fields.
+    let _ = T::deserialize(FieldRecorder(&mut fields));
+    fields
+}
+
+/// A deserializer that only notes the fields of the struct asking for them.
+struct FieldRecorder<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldRecorder<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only structs have fields"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields recorded"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// The values a parameter is validated against, taken from the same lists
+/// the handlers check.
+fn allowed_values(name: &str) -> Option<Vec<&'static str>> {
+    match name {
+        "sort" => Some(SORT_FIELDS.iter().map(|(param, _)| *param).collect()),
+        "order" => Some(vec!["asc", "desc"]),
+        "mode" => Some(vec!["and", "or"]),
+        "fields" => Some(SEARCH_FIELDS.to_vec()),
+        "status" => Some(BanStatus::ALL.iter().map(|s| s.as_str()).collect()),
+        "group_by" => Some(GROUP_FIELDS.iter().map(|(param, _)| *param).collect()),
+        _ => None,
+    }
+}
+
+/// `{ name, type, default, description[, values] }` for a parameter, or
+/// `None` for one nobody has described yet.
+pub fn describe(name: &str) -> Option<Value> {
+    let (kind, default, description) = match name {
+        "state" => (
+            "string",
+            Value::Null,
+            "State code, or several separated by commas.",
+        ),
+        "county" => ("string", Value::Null, "Keep records in this county."),
+        "effective_before" => (
+            "date",
+            Value::Null,
+            "Keep records effective strictly before this YYYY-MM-DD date.",
+        ),
+        "effective_after" => (
+            "date",
+            Value::Null,
+            "Keep records effective strictly after this YYYY-MM-DD date.",
+        ),
+        "include_undated" => (
+            "boolean",
+            json!(false),
+            "With a date bound, also keep records without a valid effective date.",
+        ),
+        "status" => (
+            "string",
+            Value::Null,
+            "Keep records with this ban_status, or any of several separated by commas.",
+        ),
+        "sort" => (
+            "string",
+            Value::Null,
+            "Field to sort by; without it records keep sheet (or relevance) order.",
+        ),
+        "order" => ("string", json!("asc"), "Sort direction."),
+        "envelope" => ("boolean", json!(false), "Wrap the list as {data, meta}."),
+        "group_by" => (
+            "string",
+            Value::Null,
+            "Return an object of record arrays keyed by state or city, leaving out blank keys.",
+        ),
+        "q" => (
+            "string",
+            Value::Null,
+            "Whitespace-separated terms; prefix a term with - to exclude it.",
+        ),
+        "mode" => (
+            "string",
+            json!("and"),
+            "Whether every term or any term must match.",
+        ),
+        "regex" => (
+            "string",
+            Value::Null,
+            "Case-insensitive regular expression used instead of q.",
+        ),
+        "fields" => (
+            "string",
+            Value::Null,
+            "Comma-separated fields the regex is matched against (default: all).",
+        ),
+        "highlight" => (
+            "boolean",
+            json!(false),
+            "Wrap each result as {record, matches}.",
+        ),
+        "near" => (
+            "zip",
+            Value::Null,
+            "Keep records within radius_mi of this zip, nearest first.",
+        ),
+        "radius_mi" => (
+            "number",
+            json!(DEFAULT_RADIUS_MI),
+            "Radius in miles for near.",
+        ),
+        "debug_score" => (
+            "boolean",
+            json!(false),
+            "Add each result's relevance score.",
+        ),
+        "zips" => (
+            "string",
+            Value::Null,
+            "Comma-separated zips to check (required).",
+        ),
+        "zip" => ("zip", Value::Null, "The zip to check (required)."),
+        "include_invalid" => (
+            "boolean",
+            json!(false),
+            "Also list zips that aren't five digits, as written.",
+        ),
+        "seed" => ("integer", Value::Null, "Makes the pick repeatable."),
+        "limit" => (
+            "integer",
+            json!(DEFAULT_RECENT_LIMIT),
+            "How many of the newest records to return.",
+        ),
+        "by_country" => (
+            "boolean",
+            json!(false),
+            "Group by country as well as state.",
+        ),
+        "tag" => ("string", Value::Null, "Only entries carrying this tag."),
+        "pretty" => ("boolean", json!(false), "Indent the JSON response."),
+        _ => return None,
+    };
+    let mut param = json!({
+        "name": name,
+        "type": kind,
+        "default": default,
+        "description": description,
+    });
+    if let Some(values) = allowed_values(name) {
+        param["values"] = json!(values);
+    }
+    Some(param)
+}
+
+/// The `/capabilities` document: each endpoint with the parameters it
+/// accepts, plus the ones every endpoint accepts.
+pub fn document(endpoints: &[(&str, Vec<&'static str>)], global: &[&'static str]) -> Value {
+    let params = |names: &[&'static str]| -> Vec<Value> {
+        names
+            .iter()
+            .map(|name| describe(name).unwrap_or_else(|| json!({ "name": name })))
+            .collect()
+    };
+    json!({
+        "endpoints": endpoints
+            .iter()
+            .map(|(path, names)| json!({ "path": path, "params": params(names) }))
+            .collect::<Vec<_>>(),
+        "global_params": params(global),
+    })
+}
@@ -0,0 +1,875 @@
+use crate::schedule;
+use crate::sheet::DEFAULT_SHEET_URL;
+use crate::state::{fnv1a64, sanitize_error};
+use crate::text::snippet;
+use cron::Schedule;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+// ---------------------------------------------------------------------------
+// Runtime configuration (read from `GKC_*` environment variables)
+// ---------------------------------------------------------------------------
+
+/// `User-Agent` used for upstream requests unless `GKC_USER_AGENT` is set.
+const DEFAULT_USER_AGENT: &str = concat!("GKCSearch/", env!("CARGO_PKG_VERSION"));
+
+/// What to do with cells past the end of the header row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraFields {
+    /// Drop them, so ragged rows can't add columns to the schema.
+    Truncate,
+    /// Keep them under generated `column_N` keys.
+    Keep,
+}
+
+impl FromStr for ExtraFields {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "truncate" => Ok(ExtraFields::Truncate),
+            "keep" => Ok(ExtraFields::Keep),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings that control how the sheet is fetched and processed.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `KEY=value` file read before the environment, and re-read by
+    /// `/admin/reload-config`.
+    pub config_file: Option<String>,
+    /// Addresses the server listens on, e.g. `127.0.0.1:7001` and `[::1]:7001`.
+    pub bind: Vec<SocketAddr>,
+    /// CSV export URL of the source sheet.
+    pub sheet_url: String,
+    /// `User-Agent` sent with sheet fetches, so our traffic is identifiable.
+    pub user_agent: String,
+    /// Proxy for sheet and seed fetches; unset means `HTTP_PROXY` /
+    /// `HTTPS_PROXY` from the environment apply, if any.
+    pub proxy: Option<String>,
+    /// Largest sheet response accepted, in bytes; bigger ones fail the fetch.
+    pub max_response_bytes: u64,
+    /// Most sheet fetches allowed in flight at once; more wait their turn.
+    pub max_concurrent_fetches: usize,
+    /// Days of daily data snapshots kept for `/diff`; 0 (the default)
+    /// turns archiving off.
+    pub snapshot_retention_days: u64,
+    /// Remember when each record ID first appeared, so `/recent` can list
+    /// the newest records by that rather than by effective date.
+    pub first_seen: bool,
+    /// Where the processed sheet data is cached on disk.
+    pub cache_file: String,
+    /// Directory holding one cache file per source, named after a hash of the
+    /// source URL. Takes precedence over `cache_file` when set.
+    pub cache_dir: Option<String>,
+    /// Gzip the cache file (adding `.gz` to its name).
+    pub cache_gzip: bool,
+    /// Pretty-print the cache file for debugging instead of writing compact JSON.
+    pub cache_pretty: bool,
+    /// Keep the last downloaded CSV next to the cache, served at `/data/raw.csv`.
+    pub keep_raw_csv: bool,
+    /// Indent JSON responses unless a request passes `pretty=false`.
+    pub pretty_json: bool,
+    /// Last-known-good JSON dataset used when both the sheet and the disk
+    /// cache are unavailable.
+    pub seed_file: Option<String>,
+    /// Like `seed_file`, but fetched over HTTP (the file wins if both are set).
+    pub seed_url: Option<String>,
+    /// Serve the snapshot compiled into the binary and never contact the
+    /// sheet or touch the disk cache.
+    pub offline: bool,
+    /// Local JSON file served by `/supplemental`.
+    pub supplemental_file: String,
+    /// File served as `/robots.txt`, re-read on every request; the built-in
+    /// policy is used when unset or unreadable.
+    pub robots_file: Option<String>,
+    /// Directory served under `/static/`; its `index.html`, if any, replaces
+    /// the built-in page at `/`.
+    pub static_dir: Option<String>,
+    /// Hosts (`*.example.com` for subdomains) allowed to serve supplemental
+    /// preview images. When set, they make up the page's CSP `img-src` and
+    /// previews from other hosts are dropped; unset allows any host.
+    pub img_hosts: Option<Vec<String>>,
+    /// How long fetched data is considered fresh.
+    pub cache_ttl: Duration,
+    /// Random ± percentage applied to the TTL of each load, so refreshes
+    /// across instances don't line up.
+    pub cache_ttl_jitter_pct: f64,
+    /// Refresh at these times (UTC) instead of after `cache_ttl`: data stays
+    /// fresh until the next one, and a background task refreshes then.
+    pub refresh_cron: Option<Schedule>,
+    /// How long past the cache TTL stale data may still be served while a
+    /// background refresh runs.
+    pub stale_while_revalidate: Duration,
+    /// Age past which the data is reported as stale (`X-Data-Stale`,
+    /// `/health` `degraded`) when refreshes keep failing; the cache TTL when
+    /// unset. It is still served; zero turns the warning off.
+    pub max_stale_age: Option<Duration>,
+    /// `max-age` sent with `/data` and `/supplemental` so browsers and CDNs
+    /// can reuse them (never more than `cache_ttl`).
+    pub http_max_age: Duration,
+    /// Accept HTTP/2 over cleartext (h2c with prior knowledge) alongside
+    /// HTTP/1.1, e.g. from a proxy that speaks h2 to the backend.
+    pub http2: bool,
+    /// Send the `ETag` / `Last-Modified` of the last download with each
+    /// refresh and keep the cache, without parsing, when the sheet is unchanged.
+    pub conditional_fetch: bool,
+    /// Load the data in the background at startup instead of on the first
+    /// request; `/data` answers 503 until that load succeeds.
+    pub background_load: bool,
+    /// How often to check the disk cache against the served records; zero
+    /// disables the check.
+    pub cache_check_interval: Duration,
+    /// Columns (by header name, or `column_N` for unnamed ones) that are never
+    /// written into the output records.
+    pub drop_columns: Vec<String>,
+    /// `(from, to)` renames applied to record keys when they are served,
+    /// e.g. `City` → `city`. Records keep the sheet's names in memory.
+    pub column_aliases: Vec<(String, String)>,
+    /// 1-based CSV row holding the header, not counting comment rows. When
+    /// set it is used as-is instead of searching for the row whose second
+    /// cell is "Zip".
+    pub header_row: Option<u64>,
+    /// Rows whose first non-empty cell starts with this marker (e.g. `#`) are
+    /// treated as notes and skipped.
+    pub comment_prefix: Option<String>,
+    /// Handling of data rows that are wider than the header.
+    pub extra_fields: ExtraFields,
+    /// Leave out columns with a blank header that are empty in every record.
+    pub trim_empty_columns: bool,
+    /// Largest share of the records (in percent) a refresh may lose before it
+    /// is refused as a likely bad edit; 100 accepts any drop.
+    pub max_record_drop_pct: f64,
+    /// Largest share of the rows (in percent) that may have a different
+    /// number of fields than the header before a fetch is refused as a
+    /// broken export; 100 accepts any.
+    pub max_misaligned_pct: f64,
+    /// Columns a refresh must contain; if one disappears the refresh fails and
+    /// the previous data is kept.
+    pub required_columns: Vec<String>,
+    /// Group "Pinellas" and "Pinellas County" together by appending "County".
+    pub normalize_county: bool,
+    /// Title-case City and County values ("CLEARWATER" → "Clearwater").
+    pub title_case: bool,
+    /// With `title_case`, keep the originals as `City_raw` / `County_raw`.
+    pub title_case_keep_raw: bool,
+    /// Sheet column holding each ban's effective date, parsed into `effective_date`.
+    pub date_column: String,
+    /// Sheet column holding each row's status, parsed into `ban_status`.
+    pub status_column: String,
+    /// Add `region` and `timezone` to records from their state, and serve
+    /// `/regions`.
+    pub regions: bool,
+    /// Mirror each refresh into SQLite and answer filters and searches from it
+    /// (only with the `sqlite` cargo feature).
+    pub sqlite: bool,
+    /// Where to write a copy of the SQLite database after each refresh.
+    pub sqlite_file: Option<String>,
+    /// Add a `Server-Timing` breakdown of the work done to `/data` responses.
+    pub server_timing: bool,
+    /// Log parsing details (the start of the raw CSV, delimiter, header row,
+    /// parse stats). On by default only in debug builds.
+    pub debug: bool,
+    /// How many distinct `/search` results to keep per refresh (0 disables).
+    pub search_cache_size: usize,
+    /// Distinct list responses (by path and query) kept serialized per
+    /// dataset; 0 disables the response cache.
+    pub response_cache_size: usize,
+    /// Most records `/data` and `/search` return in one response (0 disables the cap).
+    pub max_records: usize,
+    /// Most zips `/compare` checks in one request (0 disables the cap).
+    pub max_compare_zips: usize,
+    /// Longest query string accepted, in bytes (0 disables the limit).
+    pub max_query_bytes: usize,
+    /// Largest request body accepted, in bytes (0 disables the limit).
+    pub max_body_bytes: usize,
+    /// Requests slower than this are logged with a warning (zero disables).
+    pub slow_request: Duration,
+    /// How long a graceful shutdown waits for in-flight requests before
+    /// closing their connections.
+    pub shutdown_timeout: Duration,
+    /// Bearer token for the `/admin/*` endpoints; unset disables them.
+    pub admin_token: Option<String>,
+    /// Requests per second allowed per client IP (0 disables rate limiting).
+    pub rate_limit: f64,
+    /// Requests a client IP may make back to back before being limited.
+    pub rate_burst: f64,
+    /// Where each variable that was set came from (`file` for
+    /// `GKC_CONFIG_FILE`, or `environment`); the rest are defaults.
+    pub origins: BTreeMap<String, &'static str>,
+}
+
+impl Default for Config {
+    /// The historical behavior, used for anything not overridden.
+    fn default() -> Self {
+        Config {
+            config_file: None,
+            bind: vec![SocketAddr::from(([127, 0, 0, 1], 7001))],
+            sheet_url: DEFAULT_SHEET_URL.to_string(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            max_response_bytes: 25 * 1024 * 1024,
+            snapshot_retention_days: 0,
+            first_seen: false,
+            max_concurrent_fetches: 2,
+            cache_file: "data_cache.json".to_string(),
+            cache_dir: None,
+            cache_gzip: false,
+            cache_pretty: false,
+            keep_raw_csv: false,
+            pretty_json: false,
+            seed_file: None,
+            seed_url: None,
+            offline: false,
+            supplemental_file: "supplemental.json".to_string(),
+            robots_file: None,
+            static_dir: None,
+            img_hosts: None,
+            cache_ttl: Duration::from_secs(12 * 60 * 60),
+            cache_ttl_jitter_pct: 0.0,
+            refresh_cron: None,
+            stale_while_revalidate: Duration::from_secs(60 * 60),
+            max_stale_age: None,
+            http_max_age: Duration::from_secs(5 * 60),
+            http2: true,
+            conditional_fetch: true,
+            background_load: false,
+            cache_check_interval: Duration::ZERO,
+            drop_columns: vec!["Country".to_string(), "column_0".to_string()],
+            column_aliases: Vec::new(),
+            header_row: None,
+            comment_prefix: None,
+            extra_fields: ExtraFields::Truncate,
+            trim_empty_columns: true,
+            max_record_drop_pct: 90.0,
+            max_misaligned_pct: 100.0,
+            required_columns: Vec::new(),
+            normalize_county: false,
+            title_case: false,
+            title_case_keep_raw: false,
+            date_column: "Effective Date".to_string(),
+            status_column: "Status".to_string(),
+            regions: false,
+            sqlite: false,
+            sqlite_file: None,
+            server_timing: false,
+            debug: cfg!(debug_assertions),
+            search_cache_size: 64,
+            response_cache_size: 0,
+            max_records: 10_000,
+            max_compare_zips: 100,
+            max_query_bytes: 4096,
+            max_body_bytes: 64 * 1024,
+            slow_request: Duration::from_millis(2000),
+            shutdown_timeout: Duration::from_secs(30),
+            admin_token: None,
+            rate_limit: 0.0,
+            rate_burst: 30.0,
+            origins: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration from the environment, falling back to the
+    /// defaults for anything that is unset.
+    pub fn from_env() -> Self {
+        let config_file = env::var("GKC_CONFIG_FILE").ok();
+        Config::load(config_file.clone()).unwrap_or_else(|e| {
+            eprintln!("Ignoring GKC_CONFIG_FILE: {}", e);
+            Config {
+                config_file,
+                ..Config::from_vars(&Vars::default())
+            }
+        })
+    }
+
+    /// Read `config_file` (if any) and the environment, failing if the file
+    /// can't be read. Used again to reload a running server.
+    pub fn load(config_file: Option<String>) -> std::io::Result<Self> {
+        let vars = Vars::load(config_file.as_deref())?;
+        let mut config = Config {
+            config_file,
+            ..Config::from_vars(&vars)
+        };
+        if config.config_file.is_some() {
+            config
+                .origins
+                .insert("GKC_CONFIG_FILE".to_string(), "environment");
+        }
+        Ok(config)
+    }
+
+    fn from_vars(vars: &Vars) -> Self {
+        let defaults = Config::default();
+        Config {
+            config_file: defaults.config_file,
+            bind: vars
+                .list("GKC_BIND")
+                .map(|addrs| {
+                    addrs
+                        .iter()
+                        .filter_map(|addr| match addr.parse() {
+                            Ok(addr) => Some(addr),
+                            Err(_) => {
+                                eprintln!("Ignoring invalid address in GKC_BIND: {:?}", addr);
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .filter(|addrs| {
+                    if addrs.is_empty() {
+                        vars.reject("GKC_BIND", "GKC_BIND has no valid address".to_string());
+                    }
+                    !addrs.is_empty()
+                })
+                .unwrap_or(defaults.bind),
+            sheet_url: vars.get("GKC_SHEET_URL").unwrap_or(defaults.sheet_url),
+            user_agent: vars
+                .get("GKC_USER_AGENT")
+                .filter(|ua| !ua.trim().is_empty())
+                .unwrap_or(defaults.user_agent),
+            proxy: vars
+                .get("GKC_PROXY")
+                .filter(|url| !url.trim().is_empty())
+                .or(defaults.proxy),
+            snapshot_retention_days: vars
+                .parse("GKC_SNAPSHOT_RETENTION_DAYS")
+                .unwrap_or(defaults.snapshot_retention_days),
+            first_seen: vars.bool("GKC_FIRST_SEEN").unwrap_or(defaults.first_seen),
+            max_response_bytes: vars
+                .parse("GKC_MAX_RESPONSE_BYTES")
+                .filter(|&n| n > 0)
+                .unwrap_or(defaults.max_response_bytes),
+            max_concurrent_fetches: vars
+                .parse("GKC_MAX_CONCURRENT_FETCHES")
+                .filter(|&n| n > 0)
+                .unwrap_or(defaults.max_concurrent_fetches),
+            cache_file: vars.get("GKC_CACHE_FILE").unwrap_or(defaults.cache_file),
+            cache_dir: vars.get("GKC_CACHE_DIR").or(defaults.cache_dir),
+            cache_gzip: vars.bool("GKC_CACHE_GZIP").unwrap_or(defaults.cache_gzip),
+            cache_pretty: vars
+                .bool("GKC_CACHE_PRETTY")
+                .unwrap_or(defaults.cache_pretty),
+            keep_raw_csv: vars
+                .bool("GKC_KEEP_RAW_CSV")
+                .unwrap_or(defaults.keep_raw_csv),
+            pretty_json: vars.bool("GKC_PRETTY_JSON").unwrap_or(defaults.pretty_json),
+            seed_file: vars.get("GKC_SEED_FILE").or(defaults.seed_file),
+            seed_url: vars.get("GKC_SEED_URL").or(defaults.seed_url),
+            offline: vars.bool("GKC_OFFLINE").unwrap_or(defaults.offline),
+            supplemental_file: vars
+                .get("GKC_SUPPLEMENTAL_FILE")
+                .unwrap_or(defaults.supplemental_file),
+            robots_file: vars.get("GKC_ROBOTS_FILE").or(defaults.robots_file),
+            static_dir: vars.get("GKC_STATIC_DIR").or(defaults.static_dir),
+            img_hosts: vars
+                .list("GKC_IMG_HOSTS")
+                .map(|hosts| {
+                    hosts
+                        .into_iter()
+                        .map(|host| host.to_ascii_lowercase())
+                        .filter(|host| {
+                            // Anything but a plain host name could inject CSP directives.
+                            let name = host.strip_prefix("*.").unwrap_or(host);
+                            let valid = !name.is_empty()
+                                && name
+                                    .chars()
+                                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+                            if !valid {
+                                eprintln!(
+                                    "Ignoring invalid GKC_IMG_HOSTS entry {:?}",
+                                    snippet(host)
+                                );
+                            }
+                            valid
+                        })
+                        .collect()
+                })
+                .or(defaults.img_hosts),
+            cache_ttl: vars
+                .parse("GKC_CACHE_TTL_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.cache_ttl),
+            cache_ttl_jitter_pct: vars
+                .number("GKC_CACHE_TTL_JITTER_PCT", 0.0..=100.0)
+                .unwrap_or(defaults.cache_ttl_jitter_pct),
+            refresh_cron: vars
+                .get("GKC_REFRESH_CRON")
+                .filter(|expr| !expr.trim().is_empty())
+                .and_then(|expr| match schedule::parse(&expr) {
+                    Ok(schedule) => Some(schedule),
+                    Err(e) => {
+                        vars.reject(
+                            "GKC_REFRESH_CRON",
+                            format!(
+                                "Ignoring invalid GKC_REFRESH_CRON {:?}: {}",
+                                snippet(&expr),
+                                e
+                            ),
+                        );
+                        None
+                    }
+                })
+                .or(defaults.refresh_cron),
+            stale_while_revalidate: vars
+                .parse("GKC_STALE_WHILE_REVALIDATE_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.stale_while_revalidate),
+            max_stale_age: vars
+                .parse("GKC_MAX_STALE_AGE_SECS")
+                .map(Duration::from_secs)
+                .or(defaults.max_stale_age),
+            http_max_age: vars
+                .parse("GKC_HTTP_MAX_AGE_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.http_max_age),
+            http2: vars.bool("GKC_HTTP2").unwrap_or(defaults.http2),
+            conditional_fetch: vars
+                .bool("GKC_CONDITIONAL_FETCH")
+                .unwrap_or(defaults.conditional_fetch),
+            background_load: vars
+                .bool("GKC_BACKGROUND_LOAD")
+                .unwrap_or(defaults.background_load),
+            cache_check_interval: vars
+                .parse("GKC_CACHE_CHECK_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.cache_check_interval),
+            drop_columns: vars
+                .list("GKC_DROP_COLUMNS")
+                .unwrap_or(defaults.drop_columns),
+            column_aliases: vars
+                .list("GKC_COLUMN_ALIASES")
+                .map(|pairs| {
+                    pairs
+                        .iter()
+                        .filter_map(|pair| pair.split_once('='))
+                        .map(|(from, to)| (from.trim().to_string(), to.trim().to_string()))
+                        .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+                        .collect()
+                })
+                .unwrap_or(defaults.column_aliases),
+            header_row: vars
+                .parse("GKC_HEADER_ROW")
+                .and_then(|row| match row {
+                    0 => {
+                        vars.reject(
+                            "GKC_HEADER_ROW",
+                            "Ignoring GKC_HEADER_ROW=0: rows are numbered from 1".to_string(),
+                        );
+                        None
+                    }
+                    row => Some(row),
+                })
+                .or(defaults.header_row),
+            comment_prefix: vars
+                .get("GKC_COMMENT_PREFIX")
+                .filter(|p| !p.trim().is_empty())
+                .or(defaults.comment_prefix),
+            extra_fields: vars
+                .parse("GKC_EXTRA_FIELDS")
+                .unwrap_or(defaults.extra_fields),
+            trim_empty_columns: vars
+                .bool("GKC_TRIM_EMPTY_COLUMNS")
+                .unwrap_or(defaults.trim_empty_columns),
+            max_record_drop_pct: vars
+                .number("GKC_MAX_RECORD_DROP_PCT", 0.0..=100.0)
+                .unwrap_or(defaults.max_record_drop_pct),
+            max_misaligned_pct: vars
+                .number("GKC_MAX_MISALIGNED_PCT", 0.0..=100.0)
+                .unwrap_or(defaults.max_misaligned_pct),
+            required_columns: vars
+                .list("GKC_REQUIRED_COLUMNS")
+                .unwrap_or(defaults.required_columns),
+            normalize_county: vars
+                .bool("GKC_NORMALIZE_COUNTY")
+                .unwrap_or(defaults.normalize_county),
+            title_case: vars.bool("GKC_TITLE_CASE").unwrap_or(defaults.title_case),
+            title_case_keep_raw: vars
+                .bool("GKC_TITLE_CASE_KEEP_RAW")
+                .unwrap_or(defaults.title_case_keep_raw),
+            date_column: vars.get("GKC_DATE_COLUMN").unwrap_or(defaults.date_column),
+            status_column: vars
+                .get("GKC_STATUS_COLUMN")
+                .unwrap_or(defaults.status_column),
+            regions: vars.bool("GKC_REGIONS").unwrap_or(defaults.regions),
+            sqlite: vars.bool("GKC_SQLITE").unwrap_or(defaults.sqlite),
+            sqlite_file: vars.get("GKC_SQLITE_FILE").or(defaults.sqlite_file),
+            server_timing: vars
+                .bool("GKC_SERVER_TIMING")
+                .unwrap_or(defaults.server_timing),
+            debug: vars.bool("GKC_DEBUG").unwrap_or(defaults.debug),
+            search_cache_size: vars
+                .parse("GKC_SEARCH_CACHE_SIZE")
+                .unwrap_or(defaults.search_cache_size),
+            response_cache_size: vars
+                .parse("GKC_RESPONSE_CACHE_SIZE")
+                .unwrap_or(defaults.response_cache_size),
+            max_records: vars
+                .parse("GKC_MAX_RECORDS")
+                .unwrap_or(defaults.max_records),
+            max_compare_zips: vars
+                .parse("GKC_MAX_COMPARE_ZIPS")
+                .unwrap_or(defaults.max_compare_zips),
+            max_query_bytes: vars
+                .parse("GKC_MAX_QUERY_BYTES")
+                .unwrap_or(defaults.max_query_bytes),
+            max_body_bytes: vars
+                .parse("GKC_MAX_BODY_BYTES")
+                .unwrap_or(defaults.max_body_bytes),
+            slow_request: vars
+                .parse("GKC_SLOW_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.slow_request),
+            shutdown_timeout: vars
+                .parse("GKC_SHUTDOWN_TIMEOUT")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.shutdown_timeout),
+            admin_token: vars
+                .get("GKC_ADMIN_TOKEN")
+                .filter(|token| !token.trim().is_empty())
+                .or(defaults.admin_token),
+            rate_limit: vars
+                .number("GKC_RATE_LIMIT", 0.0..=f64::MAX)
+                .unwrap_or(defaults.rate_limit),
+            rate_burst: vars
+                .number("GKC_RATE_BURST", 0.0..=f64::MAX)
+                .unwrap_or(defaults.rate_burst),
+            // Last, once every variable has been read.
+            origins: vars.origins.take(),
+        }
+    }
+
+    /// Carry over from `running` the settings that only take effect at startup
+    /// (the sheet client, fetch limiter, rate limiter, and listener are built
+    /// once), returning the variables whose new values were ignored.
+    pub fn keep_startup_settings(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        macro_rules! keep {
+            ($($field:ident => $var:literal),* $(,)?) => {$(
+                if self.$field != running.$field {
+                    ignored.push($var);
+                    self.$field = running.$field.clone();
+                }
+                match running.origins.get($var) {
+                    Some(origin) => self.origins.insert($var.to_string(), origin),
+                    None => self.origins.remove($var),
+                };
+            )*};
+        }
+        keep!(
+            bind => "GKC_BIND",
+            sheet_url => "GKC_SHEET_URL",
+            user_agent => "GKC_USER_AGENT",
+            proxy => "GKC_PROXY",
+            max_response_bytes => "GKC_MAX_RESPONSE_BYTES",
+            max_concurrent_fetches => "GKC_MAX_CONCURRENT_FETCHES",
+            rate_limit => "GKC_RATE_LIMIT",
+            rate_burst => "GKC_RATE_BURST",
+            http2 => "GKC_HTTP2",
+            background_load => "GKC_BACKGROUND_LOAD",
+            cache_check_interval => "GKC_CACHE_CHECK_SECS",
+            refresh_cron => "GKC_REFRESH_CRON",
+            max_body_bytes => "GKC_MAX_BODY_BYTES",
+            static_dir => "GKC_STATIC_DIR",
+            shutdown_timeout => "GKC_SHUTDOWN_TIMEOUT",
+        );
+        ignored
+    }
+
+    /// Every setting by variable name, as `{ "value", "source" }` where the
+    /// source is `default`, `environment`, `file`, or `default (invalid)` for
+    /// a value that was set but couldn't be used. The admin token and any
+    /// credentials or query strings in URLs are redacted.
+    pub fn effective(&self) -> Map<String, Value> {
+        let secs = |d: Duration| d.as_secs();
+        let mut settings = Map::new();
+        macro_rules! settings {
+            ($($var:literal => $value:expr),* $(,)?) => {$(
+                let source = self.origins.get($var).copied().unwrap_or("default");
+                settings.insert($var.to_string(), json!({ "value": $value, "source": source }));
+            )*};
+        }
+        settings!(
+            "GKC_CONFIG_FILE" => self.config_file,
+            "GKC_BIND" => self.bind.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "GKC_SHEET_URL" => sanitize_error(&self.sheet_url),
+            "GKC_USER_AGENT" => self.user_agent,
+            "GKC_PROXY" => self.proxy.as_deref().map(sanitize_error),
+            "GKC_SNAPSHOT_RETENTION_DAYS" => self.snapshot_retention_days,
+            "GKC_FIRST_SEEN" => self.first_seen,
+            "GKC_MAX_RESPONSE_BYTES" => self.max_response_bytes,
+            "GKC_MAX_CONCURRENT_FETCHES" => self.max_concurrent_fetches,
+            "GKC_CACHE_FILE" => self.cache_file,
+            "GKC_CACHE_DIR" => self.cache_dir,
+            "GKC_CACHE_GZIP" => self.cache_gzip,
+            "GKC_CACHE_PRETTY" => self.cache_pretty,
+            "GKC_KEEP_RAW_CSV" => self.keep_raw_csv,
+            "GKC_PRETTY_JSON" => self.pretty_json,
+            "GKC_SEED_FILE" => self.seed_file,
+            "GKC_SEED_URL" => self.seed_url.as_deref().map(sanitize_error),
+            "GKC_OFFLINE" => self.offline,
+            "GKC_SUPPLEMENTAL_FILE" => self.supplemental_file,
+            "GKC_ROBOTS_FILE" => self.robots_file,
+            "GKC_STATIC_DIR" => self.static_dir,
+            "GKC_IMG_HOSTS" => self.img_hosts,
+            "GKC_CACHE_TTL_SECS" => secs(self.cache_ttl),
+            "GKC_CACHE_TTL_JITTER_PCT" => self.cache_ttl_jitter_pct,
+            "GKC_REFRESH_CRON" => self.refresh_cron.as_ref().map(Schedule::source),
+            "GKC_STALE_WHILE_REVALIDATE_SECS" => secs(self.stale_while_revalidate),
+            "GKC_MAX_STALE_AGE_SECS" => self.max_stale_age.map(secs),
+            "GKC_HTTP_MAX_AGE_SECS" => secs(self.http_max_age),
+            "GKC_HTTP2" => self.http2,
+            "GKC_CONDITIONAL_FETCH" => self.conditional_fetch,
+            "GKC_BACKGROUND_LOAD" => self.background_load,
+            "GKC_CACHE_CHECK_SECS" => secs(self.cache_check_interval),
+            "GKC_DROP_COLUMNS" => self.drop_columns,
+            "GKC_COLUMN_ALIASES" => self
+                .column_aliases
+                .iter()
+                .map(|(from, to)| format!("{}={}", from, to))
+                .collect::<Vec<_>>(),
+            "GKC_HEADER_ROW" => self.header_row,
+            "GKC_COMMENT_PREFIX" => self.comment_prefix,
+            "GKC_EXTRA_FIELDS" => format!("{:?}", self.extra_fields).to_lowercase(),
+            "GKC_TRIM_EMPTY_COLUMNS" => self.trim_empty_columns,
+            "GKC_MAX_RECORD_DROP_PCT" => self.max_record_drop_pct,
+            "GKC_MAX_MISALIGNED_PCT" => self.max_misaligned_pct,
+            "GKC_REQUIRED_COLUMNS" => self.required_columns,
+            "GKC_NORMALIZE_COUNTY" => self.normalize_county,
+            "GKC_TITLE_CASE" => self.title_case,
+            "GKC_TITLE_CASE_KEEP_RAW" => self.title_case_keep_raw,
+            "GKC_DATE_COLUMN" => self.date_column,
+            "GKC_STATUS_COLUMN" => self.status_column,
+            "GKC_REGIONS" => self.regions,
+            "GKC_SQLITE" => self.sqlite,
+            "GKC_SQLITE_FILE" => self.sqlite_file,
+            "GKC_SERVER_TIMING" => self.server_timing,
+            "GKC_DEBUG" => self.debug,
+            "GKC_SEARCH_CACHE_SIZE" => self.search_cache_size,
+            "GKC_RESPONSE_CACHE_SIZE" => self.response_cache_size,
+            "GKC_MAX_RECORDS" => self.max_records,
+            "GKC_MAX_COMPARE_ZIPS" => self.max_compare_zips,
+            "GKC_MAX_QUERY_BYTES" => self.max_query_bytes,
+            "GKC_MAX_BODY_BYTES" => self.max_body_bytes,
+            "GKC_SLOW_MS" => self.slow_request.as_millis() as u64,
+            "GKC_SHUTDOWN_TIMEOUT" => secs(self.shutdown_timeout),
+            "GKC_ADMIN_TOKEN" => self.admin_token.as_ref().map(|_| "[redacted]"),
+            "GKC_RATE_LIMIT" => self.rate_limit,
+            "GKC_RATE_BURST" => self.rate_burst,
+        );
+        settings
+    }
+
+    /// Hash of the settings that shape the processed records, saved with the
+    /// cache so that changing them forces the next fetch to re-parse the sheet.
+    pub fn processing_fingerprint(&self) -> String {
+        let settings = format!(
+            "{:?}",
+            (
+                &self.drop_columns,
+                self.header_row,
+                &self.comment_prefix,
+                self.extra_fields,
+                self.trim_empty_columns,
+                &self.date_column,
+                &self.status_column,
+                self.regions,
+                self.title_case,
+                self.title_case_keep_raw,
+            )
+        );
+        format!("{:016x}", fnv1a64(settings.as_bytes()))
+    }
+
+    /// The cache file for the source identified by `key` (e.g. its URL):
+    /// `cache_file`, or a file in `cache_dir` named after a hash of the key
+    /// so different sources never share one. Ends in `.gz` with `cache_gzip`.
+    pub fn cache_path_for(&self, key: &str) -> String {
+        let path = match &self.cache_dir {
+            Some(dir) => Path::new(dir)
+                .join(format!("sheet-{:016x}.json", fnv1a64(key.as_bytes())))
+                .to_string_lossy()
+                .into_owned(),
+            None => self.cache_file.clone(),
+        };
+        if self.cache_gzip {
+            format!("{}.gz", path)
+        } else {
+            path
+        }
+    }
+
+    /// Age past which data counts as degraded: `max_stale_age`, or when that
+    /// is unset the cache TTL (with `refresh_cron`, the time since the last
+    /// scheduled time more than [`schedule::REFRESH_GRACE`] ago, so data that
+    /// missed one counts).
+    pub fn degraded_after(&self) -> Duration {
+        let missed = || {
+            let cron = self.refresh_cron.as_ref()?;
+            schedule::missed_after(cron, SystemTime::now(), schedule::REFRESH_GRACE)
+        };
+        self.max_stale_age.or_else(missed).unwrap_or(self.cache_ttl)
+    }
+
+    /// Where files kept alongside the cache go: `cache_dir`, or the directory
+    /// of `cache_file`.
+    fn data_dir(&self) -> &Path {
+        match &self.cache_dir {
+            Some(dir) => Path::new(dir),
+            None => Path::new(&self.cache_file)
+                .parent()
+                .unwrap_or_else(|| Path::new("")),
+        }
+    }
+
+    /// Directory of the dated snapshots: `snapshots` under `cache_dir`, or
+    /// next to `cache_file`.
+    pub fn snapshot_dir(&self) -> String {
+        self.data_dir()
+            .join("snapshots")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The file of first-seen times (`GKC_FIRST_SEEN`), next to the snapshots.
+    pub fn first_seen_path(&self) -> String {
+        self.data_dir()
+            .join("first_seen.json")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The cache file for the configured sheet.
+    pub fn cache_path(&self) -> String {
+        self.cache_path_for(&self.sheet_url)
+    }
+
+    /// Whether a row is a note rather than data, per `comment_prefix`.
+    pub fn is_comment_row<'a>(&self, mut fields: impl Iterator<Item = &'a str>) -> bool {
+        match &self.comment_prefix {
+            Some(prefix) => fields
+                .find(|f| !f.trim().is_empty())
+                .is_some_and(|f| f.trim_start().starts_with(prefix.trim())),
+            None => false,
+        }
+    }
+
+    /// Whether a column with this key should be skipped during processing.
+    pub fn is_dropped(&self, key: &str) -> bool {
+        self.drop_columns.iter().any(|c| c == key)
+    }
+}
+
+/// Where settings are read from: `GKC_CONFIG_FILE` (`KEY=value` lines, `#`
+/// comments), which wins so edits to it can be reloaded, then the environment.
+#[derive(Default)]
+struct Vars {
+    file: HashMap<String, String>,
+    /// Where each variable read so far was found.
+    origins: RefCell<BTreeMap<String, &'static str>>,
+}
+
+impl Vars {
+    fn load(path: Option<&str>) -> std::io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Vars::default());
+        };
+        let file = std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Ok(Vars {
+            file,
+            ..Vars::default()
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let (value, origin) = match self.file.get(name) {
+            Some(value) => (Some(value.clone()), "file"),
+            None => (env::var(name).ok(), "environment"),
+        };
+        if value.is_some() {
+            self.origins.borrow_mut().insert(name.to_string(), origin);
+        }
+        value
+    }
+
+    /// Read a comma-separated list, trimming entries and ignoring empty ones.
+    /// Returns `None` when the variable is unset so callers can apply a default.
+    fn list(&self, name: &str) -> Option<Vec<String>> {
+        self.get(name).map(|raw| {
+            raw.split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    }
+
+    /// Parse a variable into `T`, warning about (and ignoring) malformed values.
+    fn parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        let raw = self.get(name)?;
+        match raw.trim().parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.reject(
+                    name,
+                    format!("Ignoring invalid value for {}: {:?}", name, snippet(&raw)),
+                );
+                None
+            }
+        }
+    }
+
+    /// Parse a number within `range`, warning about (and ignoring) anything
+    /// else, NaN and infinities included.
+    fn number(&self, name: &str, range: RangeInclusive<f64>) -> Option<f64> {
+        let value: f64 = self.parse(name)?;
+        if range.contains(&value) {
+            return Some(value);
+        }
+        let expected = match *range.end() {
+            f64::MAX => format!("a finite number of at least {}", range.start()),
+            end => format!("a number from {} to {}", range.start(), end),
+        };
+        self.reject(
+            name,
+            format!("Ignoring {}={}: expected {}", name, value, expected),
+        );
+        None
+    }
+
+    /// Warn (on stderr, so `--check-config` output stays JSON) that `name`'s
+    /// value is unusable, and report the setting as left at its default.
+    fn reject(&self, name: &str, warning: String) {
+        eprintln!("{}", warning);
+        self.origins
+            .borrow_mut()
+            .insert(name.to_string(), "default (invalid)");
+    }
+
+    /// Read a boolean flag ("1"/"true"/"yes"/"on" are true, anything else false).
+    fn bool(&self, name: &str) -> Option<bool> {
+        self.get(name).map(|raw| {
+            matches!(
+                raw.trim().to_ascii_lowercase().as_str(),
+                "1" | "true" | "yes" | "on"
+            )
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::{Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ---------------------------------------------------------------------------
+// Date parsing for the sheet's effective-date column
+// ---------------------------------------------------------------------------
+
+fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+/// Format a calendar date as ISO-8601 (`YYYY-MM-DD`), or `None` if it doesn't exist.
+fn iso_date(year: u32, month: u32, day: u32) -> Option<String> {
+    if !(1..=9999).contains(&year) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// Parse an ISO-8601 date (`YYYY-MM-DD`), returning it re-formatted.
+pub fn parse_iso_date(raw: &str) -> Option<String> {
+    let parts: Vec<&str> = raw.trim().split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 {
+        return None;
+    }
+    let year = parts[0].parse().ok()?;
+    let month = parts[1].parse().ok()?;
+    let day = parts[2].parse().ok()?;
+    iso_date(year, month, day)
+}
+
+/// Normalize a sheet date to ISO-8601. Accepts ISO input as well as the common
+/// US `M/D/YYYY` form (also `M-D-YYYY` and two-digit years, taken as 20YY).
+pub fn normalize_date(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if let Some(iso) = parse_iso_date(raw) {
+        return Some(iso);
+    }
+    let parts: Vec<&str> = raw.split(['/', '-']).collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let month = parts[0].parse().ok()?;
+    let day = parts[1].parse().ok()?;
+    let year: u32 = match parts[2].len() {
+        2 => 2000 + parts[2].parse::<u32>().ok()?,
+        4 => parts[2].parse().ok()?,
+        _ => return None,
+    };
+    iso_date(year, month, day)
+}
+
+/// Add `effective_date` to a record from its `column` value. Unparseable dates
+/// keep the raw string and are flagged with `effective_date_valid: false`;
+/// records with no date in that column are left untouched.
+pub fn annotate_effective_date(record: &mut Map<String, Value>, column: &str) {
+    let raw = match record.get(column).and_then(Value::as_str) {
+        Some(raw) if !raw.trim().is_empty() => raw.trim().to_string(),
+        _ => return,
+    };
+    match normalize_date(&raw) {
+        Some(iso) => {
+            record.insert("effective_date".to_string(), Value::String(iso));
+        }
+        None => {
+            record.insert("effective_date".to_string(), Value::String(raw));
+            record.insert("effective_date_valid".to_string(), Value::Bool(false));
+        }
+    }
+}
+
+/// Format a timestamp as RFC 3339 in UTC (`2025-01-05T13:45:00Z`), to the second.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil-from-days conversion (Howard Hinnant's algorithm), days since 1970-01-01.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_timestamps_as_rfc3339() {
+        assert_eq!(format_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(format_rfc3339(leap_day), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn normalizes_us_and_iso_dates() {
+        assert_eq!(normalize_date("2/29/24").as_deref(), Some("2024-02-29"));
+        assert_eq!(normalize_date("2025-1-5").as_deref(), Some("2025-01-05"));
+        assert_eq!(normalize_date("2/29/2023"), None);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::config::Config;
+use crate::filter::{canonical_county, field_str, RecordFilter};
+use crate::search::{split_terms, MatchMode, SEARCH_FIELDS};
+use crate::status::BanStatus;
+use actix_web::web;
+use rusqlite::{params_from_iter, Connection};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+// ---------------------------------------------------------------------------
+// Optional SQLite mirror of the dataset (`--features sqlite`, `GKC_SQLITE`)
+// ---------------------------------------------------------------------------
+
+/// Derived columns the queries run against, computed in Rust so matching is
+/// identical to the in-memory path (Unicode lowercasing, county normalization).
+const KEY_COLUMNS: [&str; 4] = [
+    "_state_key",
+    "_county_key",
+    "_effective_date",
+    "_ban_status",
+];
+
+/// Lowercased copy of each searchable field, e.g. `_search_city`.
+fn search_column(field: &str) -> String {
+    format!("_search_{}", field.to_ascii_lowercase())
+}
+
+/// Quote a record field name for use as a column identifier.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// An in-memory SQLite database holding one refresh of the data in a `bans`
+/// table: one TEXT column per record field plus the derived key columns, with
+/// `idx` pointing back into `Dataset::records`. Queries run on the blocking
+/// thread pool, so they never hold up the async workers.
+pub struct SqlIndex {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqlIndex {
+    /// Load `records` into a fresh in-memory database, and copy it to
+    /// `config.sqlite_file` when set so other tools can query it. This blocks;
+    /// call it from the blocking thread pool.
+    pub fn build(records: &[Value], config: &Config) -> rusqlite::Result<Self> {
+        let mut fields = BTreeSet::new();
+        for record in records {
+            if let Some(object) = record.as_object() {
+                fields.extend(object.keys().cloned());
+            }
+        }
+        let fields: Vec<String> = fields
+            .into_iter()
+            .filter(|f| !f.starts_with('_') && f != "idx")
+            .collect();
+        let search_columns: Vec<String> = SEARCH_FIELDS.iter().map(|f| search_column(f)).collect();
+
+        let mut columns = vec!["idx INTEGER PRIMARY KEY".to_string()];
+        columns.extend(fields.iter().map(|f| format!("{} TEXT", quote_ident(f))));
+        columns.extend(KEY_COLUMNS.iter().map(|c| format!("{} TEXT", c)));
+        columns.extend(search_columns.iter().map(|c| format!("{} TEXT", c)));
+
+        let mut conn = Connection::open_in_memory()?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE bans ({});
+             CREATE INDEX bans_state ON bans (_state_key);
+             CREATE INDEX bans_county ON bans (_county_key);
+             CREATE INDEX bans_effective_date ON bans (_effective_date);",
+            columns.join(", ")
+        ))?;
+
+        let names: Vec<String> = std::iter::once("idx".to_string())
+            .chain(fields.iter().map(|f| quote_ident(f)))
+            .chain(KEY_COLUMNS.iter().map(|c| c.to_string()))
+            .chain(search_columns.iter().cloned())
+            .collect();
+        let placeholders = vec!["?"; names.len()].join(", ");
+        let insert = format!(
+            "INSERT INTO bans ({}) VALUES ({})",
+            names.join(", "),
+            placeholders
+        );
+
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(&insert)?;
+            for (i, record) in records.iter().enumerate() {
+                let mut values: Vec<Option<String>> = vec![Some(i.to_string())];
+                values.extend(fields.iter().map(|f| {
+                    record
+                        .get(f)
+                        .map(|v| v.as_str().map_or_else(|| v.to_string(), String::from))
+                }));
+                values.extend(row_keys(record, config));
+                values.extend(
+                    SEARCH_FIELDS.iter().map(|f| {
+                        Some(field_str(record, f).to_lowercase()).filter(|v| !v.is_empty())
+                    }),
+                );
+                stmt.execute(params_from_iter(values))?;
+            }
+        }
+        tx.commit()?;
+
+        if let Some(path) = &config.sqlite_file {
+            let tmp = format!("{}.tmp", path);
+            let _ = std::fs::remove_file(&tmp);
+            conn.execute("VACUUM INTO ?1", [&tmp])?;
+            if let Err(e) = std::fs::rename(&tmp, path) {
+                println!("Failed to write SQLite copy to {}: {}", path, e);
+            }
+        }
+
+        Ok(SqlIndex {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Indices (in sheet order) of the records matching `filter` and, when
+    /// given, the substring `query` combined according to `mode`. Mirrors
+    /// `TrigramIndex::search` followed by `RecordFilter::matches`.
+    pub async fn query(
+        &self,
+        query: Option<(&str, MatchMode)>,
+        filter: &RecordFilter,
+    ) -> Result<Vec<usize>, Box<dyn Error + Send + Sync>> {
+        let mut clauses: Vec<String> = Vec::new();
+        let mut params: Vec<String> = Vec::new();
+
+        if let Some(states) = &filter.states {
+            if states.is_empty() {
+                clauses.push("0".to_string());
+            } else {
+                clauses.push(format!(
+                    "_state_key IN ({})",
+                    vec!["?"; states.len()].join(", ")
+                ));
+                params.extend(states.iter().map(|state| state.to_ascii_lowercase()));
+            }
+        }
+        if let Some(county) = &filter.county {
+            clauses.push("_county_key = ?".to_string());
+            params.push(county.to_ascii_lowercase());
+        }
+        let (before, after) = (filter.before.clone(), filter.after.clone());
+        if before.is_some() || after.is_some() {
+            let mut bounds = Vec::new();
+            if let Some(before) = before {
+                bounds.push("_effective_date < ?");
+                params.push(before);
+            }
+            if let Some(after) = after {
+                bounds.push("_effective_date > ?");
+                params.push(after);
+            }
+            let dated = format!("(_effective_date IS NOT NULL AND {})", bounds.join(" AND "));
+            clauses.push(if filter.include_undated {
+                format!("(_effective_date IS NULL OR {})", dated)
+            } else {
+                dated
+            });
+        }
+
+        if let Some(statuses) = &filter.statuses {
+            if statuses.is_empty() {
+                clauses.push("0".to_string());
+            } else {
+                clauses.push(format!(
+                    "_ban_status IN ({})",
+                    vec!["?"; statuses.len()].join(", ")
+                ));
+                params.extend(statuses.iter().map(|status| status.as_str().to_string()));
+            }
+        }
+
+        if let Some((query, mode)) = query {
+            let (include, exclude) = split_terms(query);
+            let mut term_clause = |term: String| {
+                let any_field: Vec<String> = SEARCH_FIELDS
+                    .iter()
+                    .map(|f| {
+                        params.push(term.clone());
+                        format!("instr({}, ?) > 0", search_column(f))
+                    })
+                    .collect();
+                format!("({})", any_field.join(" OR "))
+            };
+            let included: Vec<String> = include.into_iter().map(&mut term_clause).collect();
+            let excluded: Vec<String> = exclude.into_iter().map(&mut term_clause).collect();
+            if !included.is_empty() {
+                let joiner = match mode {
+                    MatchMode::All => " AND ",
+                    MatchMode::Any => " OR ",
+                };
+                clauses.push(format!("({})", included.join(joiner)));
+            }
+            // instr() on a NULL column is NULL, so coalesce before negating.
+            clauses.extend(
+                excluded
+                    .into_iter()
+                    .map(|c| format!("NOT coalesce({}, 0)", c)),
+            );
+        }
+
+        let sql = if clauses.is_empty() {
+            "SELECT idx FROM bans ORDER BY idx".to_string()
+        } else {
+            format!(
+                "SELECT idx FROM bans WHERE {} ORDER BY idx",
+                clauses.join(" AND ")
+            )
+        };
+        let conn = Arc::clone(&self.conn);
+        let rows = web::block(move || -> rusqlite::Result<Vec<usize>> {
+            let conn = conn.lock().unwrap();
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params_from_iter(params), |row| row.get::<_, i64>(0))?;
+            rows.map(|idx| idx.map(|idx| idx as usize)).collect()
+        })
+        .await?;
+        Ok(rows?)
+    }
+}
+
+/// Values for [`KEY_COLUMNS`]: the state and county keys as the filters
+/// compare them, the effective date only when it is valid, and the ban status.
+fn row_keys(record: &Value, config: &Config) -> [Option<String>; 4] {
+    let state = field_str(record, "State").trim().to_ascii_lowercase();
+    let county =
+        canonical_county(field_str(record, "County"), config.normalize_county).to_ascii_lowercase();
+    let valid = record.get("effective_date_valid").and_then(Value::as_bool) != Some(false);
+    let date = record
+        .get("effective_date")
+        .and_then(Value::as_str)
+        .filter(|_| valid)
+        .map(String::from);
+    let status = BanStatus::of_record(record).as_str().to_string();
+    [Some(state), Some(county), date, Some(status)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterParams;
+    use crate::search::TrigramIndex;
+    use serde_json::json;
+
+    fn records() -> Vec<Value> {
+        vec![
+            json!({"City": "Clearwater", "County": "Pinellas", "Zip": "33755", "State": "FL", "effective_date": "2024-07-01", "ban_status": "restricted"}),
+            json!({"City": "St. Petersburg", "County": "Pinellas County", "Zip": "33701", "State": "FL"}),
+            json!({"City": "Lowell", "County": "Middlesex", "Zip": "01850", "State": "MA", "effective_date": "soon", "effective_date_valid": false, "ban_status": "pending"}),
+            json!({"City": "Ñandú", "Zip": "00000", "State": "pr", "effective_date": "2023-01-01"}),
+            json!({"City": "Tampa", "County": "Hillsborough", "Zip": "33602", "State": " FL "}),
+        ]
+    }
+
+    /// The in-memory answer the SQL path must reproduce.
+    fn expected(
+        records: &[Value],
+        query: Option<(&str, MatchMode)>,
+        filter: &RecordFilter,
+    ) -> Vec<usize> {
+        let candidates = match query {
+            Some((q, mode)) => TrigramIndex::build(records).search(records, q, mode),
+            None => (0..records.len()).collect(),
+        };
+        candidates
+            .into_iter()
+            .filter(|&i| filter.matches(&records[i]))
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn sql_results_match_the_in_memory_path() {
+        let records = records();
+        let config = Config {
+            normalize_county: true,
+            ..Config::default()
+        };
+        let sql = SqlIndex::build(&records, &config).unwrap();
+
+        let filter = |state: Option<&str>, county: Option<&str>, before: Option<&str>, undated| {
+            FilterParams {
+                state: state.map(String::from),
+                county: county.map(String::from),
+                effective_before: before.map(String::from),
+                include_undated: undated,
+                ..FilterParams::default()
+            }
+        };
+        let cases = [
+            (None, filter(None, None, None, None)),
+            (None, filter(Some("fl"), None, None, None)),
+            (None, filter(None, Some("pinellas"), None, None)),
+            (None, filter(None, None, Some("2024-01-01"), None)),
+            (None, filter(None, None, Some("2025-01-01"), Some(true))),
+            (
+                None,
+                FilterParams {
+                    status: Some("banned,pending".to_string()),
+                    ..FilterParams::default()
+                },
+            ),
+            (
+                Some(("pinellas", MatchMode::All)),
+                filter(None, None, None, None),
+            ),
+            (
+                Some(("clearwater 33755", MatchMode::All)),
+                filter(None, None, None, None),
+            ),
+            (
+                Some(("lowell 33701", MatchMode::Any)),
+                filter(None, None, None, None),
+            ),
+            (
+                Some(("-pinellas", MatchMode::All)),
+                filter(None, None, None, None),
+            ),
+            (
+                Some(("ÑANDÚ", MatchMode::All)),
+                filter(None, None, None, None),
+            ),
+            (
+                Some(("fl -clear", MatchMode::All)),
+                filter(Some("FL"), None, None, None),
+            ),
+        ];
+        let known: BTreeSet<String> = records
+            .iter()
+            .map(|record| field_str(record, "State").trim().to_ascii_uppercase())
+            .collect();
+        for (query, filters) in &cases {
+            let filter = filters.resolve(&known, &config);
+            assert_eq!(
+                sql.query(*query, &filter).await.unwrap(),
+                expected(&records, *query, &filter),
+                "query {:?}, filters {:?}",
+                query,
+                filters
+            );
+        }
+    }
+
+    #[test]
+    fn copies_the_database_to_disk_when_configured() {
+        let path = std::env::temp_dir().join(format!("gkcsearch-sqlite-{}.db", std::process::id()));
+        let config = Config {
+            sqlite_file: Some(path.to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+        SqlIndex::build(&records(), &config).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        let count: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM bans WHERE \"State\" = 'FL'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::sheet::FetchError;
+use crate::state::sanitize_error;
+use crate::supplemental::SupplementalError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde_json::{json, Value};
+use std::fmt;
+
+// ---------------------------------------------------------------------------
+// JSON error bodies: `{ "error": { "code", "message" } }`
+// ---------------------------------------------------------------------------
+
+/// A query parameter is malformed or out of range.
+pub const INVALID_PARAMETER: &str = "invalid_parameter";
+/// A required query parameter is missing.
+pub const MISSING_PARAMETER: &str = "missing_parameter";
+/// The requested item doesn't exist (or nothing matched).
+pub const NOT_FOUND: &str = "not_found";
+
+/// The error body: a stable machine-readable `code` and a human-readable `message`.
+pub fn error_body(code: &str, message: impl fmt::Display) -> Value {
+    json!({ "error": { "code": code, "message": message.to_string() } })
+}
+
+/// A response with `status` and an error body.
+pub fn error_response(status: StatusCode, code: &str, message: impl fmt::Display) -> HttpResponse {
+    HttpResponse::build(status).json(error_body(code, message))
+}
+
+/// `400 Bad Request` for an invalid query or path parameter.
+pub fn bad_request(message: impl fmt::Display) -> HttpResponse {
+    error_response(StatusCode::BAD_REQUEST, INVALID_PARAMETER, message)
+}
+
+/// `404 Not Found`.
+pub fn not_found(message: impl fmt::Display) -> HttpResponse {
+    error_response(StatusCode::NOT_FOUND, NOT_FOUND, message)
+}
+
+impl FetchError {
+    /// Stable identifier of the kind of failure, for clients to branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FetchError::Http(_) => "upstream_unreachable",
+            FetchError::Csv(_) => "sheet_unparseable",
+            FetchError::Json(_) => "invalid_json",
+            FetchError::Io(_) => "io_error",
+            FetchError::Schema(_) => "sheet_schema_mismatch",
+            FetchError::Truncated(_) => "sheet_truncated",
+            FetchError::TooLarge(_) => "sheet_too_large",
+            FetchError::Unexpected(_) => "sheet_unexpected_response",
+        }
+    }
+}
+
+impl ResponseError for FetchError {
+    /// `502 Bad Gateway` when the sheet itself failed us, `500` for local trouble
+    /// (reading the cache or seed).
+    fn status_code(&self) -> StatusCode {
+        match self {
+            FetchError::Json(_) | FetchError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        error_response(
+            self.status_code(),
+            self.code(),
+            sanitize_error(&self.to_string()),
+        )
+    }
+}
+
+impl SupplementalError {
+    /// Stable identifier of the kind of failure, for clients to branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SupplementalError::Read(_) => "supplemental_unreadable",
+            SupplementalError::Parse(_) => "supplemental_invalid",
+        }
+    }
+}
+
+impl ResponseError for SupplementalError {
+    fn error_response(&self) -> HttpResponse {
+        error_response(self.status_code(), self.code(), self)
+    }
+}
@@ -0,0 +1,95 @@
+use serde_json::Value;
+use std::borrow::Borrow;
+use std::collections::BTreeSet;
+
+// ---------------------------------------------------------------------------
+// CSV export of the dataset
+// ---------------------------------------------------------------------------
+
+/// Header used when there are no records to take the columns from.
+const DEFAULT_COLUMNS: [&str; 4] = ["Zip", "City", "County", "State"];
+
+/// The header row: `id`, then the records' keys in `sheet_order`, then any
+/// other keys they have by name (so all of them by name when the sheet order
+/// isn't known).
+pub fn csv_columns<R: Borrow<Value>>(records: &[R], sheet_order: &[String]) -> Vec<String> {
+    let present: BTreeSet<&str> = records
+        .iter()
+        .filter_map(|record| record.borrow().as_object())
+        .flat_map(|record| record.keys().map(String::as_str))
+        .collect();
+    let mut columns: Vec<String> = Vec::new();
+    let candidates = std::iter::once("id")
+        .chain(sheet_order.iter().map(String::as_str))
+        .chain(present.iter().copied());
+    for key in candidates {
+        if present.contains(key) && !columns.iter().any(|column| column == key) {
+            columns.push(key.to_string());
+        }
+    }
+    if columns.is_empty() {
+        columns = match sheet_order {
+            [] => DEFAULT_COLUMNS.iter().map(|c| c.to_string()).collect(),
+            known => known.to_vec(),
+        };
+    }
+    columns
+}
+
+/// Serialize `records` as CSV with a header row from [`csv_columns`]. Missing
+/// fields are left empty and non-string values are written as JSON.
+pub fn records_to_csv<R: Borrow<Value>>(
+    records: &[R],
+    sheet_order: &[String],
+) -> Result<Vec<u8>, csv::Error> {
+    let columns = csv_columns(records, sheet_order);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&columns)?;
+    for record in records {
+        let record = record.borrow();
+        writer.write_record(columns.iter().map(|column| match record.get(column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        }))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn columns_follow_the_sheet_then_the_rest_by_name() {
+        let records = vec![
+            json!({"id": "a", "Zip": "33701", "City": "St. Petersburg"}),
+            json!({"id": "b", "Zip": "01850", "County": "Middlesex", "effective_date_valid": false}),
+        ];
+        let sheet_order = ["Zip", "County", "City", "Country"].map(String::from);
+        let csv = String::from_utf8(records_to_csv(&records, &sheet_order).unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "id,Zip,County,City,effective_date_valid\n\
+             a,33701,,St. Petersburg,\n\
+             b,01850,Middlesex,,false\n"
+        );
+        // Without a sheet order, by name.
+        assert_eq!(
+            csv_columns(&records, &[]),
+            ["id", "City", "County", "Zip", "effective_date_valid"]
+        );
+    }
+
+    #[test]
+    fn empty_datasets_still_get_a_header() {
+        let csv = String::from_utf8(records_to_csv::<Value>(&[], &[]).unwrap()).unwrap();
+        assert_eq!(csv, "Zip,City,County,State\n");
+        let sheet_order = ["Zip", "State"].map(String::from);
+        let csv = String::from_utf8(records_to_csv::<Value>(&[], &sheet_order).unwrap()).unwrap();
+        assert_eq!(csv, "Zip,State\n");
+    }
+}
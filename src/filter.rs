@@ -0,0 +1,365 @@
+use crate::config::Config;
+use crate::dates::parse_iso_date;
+use crate::status::BanStatus;
+use crate::text::snippet;
+use serde::Deserialize;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+
+// ---------------------------------------------------------------------------
+// Record filters shared by the list endpoints (/data, /search)
+// ---------------------------------------------------------------------------
+
+/// String value of a record field, or "" when absent.
+pub fn field_str<'a>(record: &'a Value, field: &str) -> &'a str {
+    record.get(field).and_then(Value::as_str).unwrap_or("")
+}
+
+/// Canonical form of a county name used for grouping and filtering. With
+/// normalization enabled, "Pinellas" and "Pinellas County" both become
+/// "Pinellas County".
+pub fn canonical_county(name: &str, normalize: bool) -> String {
+    let name = name.trim();
+    if !normalize || name.is_empty() {
+        return name.to_string();
+    }
+    const SUFFIX: &str = " county";
+    let split = name.len().saturating_sub(SUFFIX.len());
+    let base = match name.get(split..) {
+        Some(tail) if split > 0 && tail.eq_ignore_ascii_case(SUFFIX) => name[..split].trim_end(),
+        _ => name,
+    };
+    format!("{} County", base)
+}
+
+/// Query parameters that narrow the set of records returned.
+#[derive(Debug, Default, Deserialize)]
+pub struct FilterParams {
+    /// One state code, or several separated by commas (any of them matches).
+    pub state: Option<String>,
+    pub county: Option<String>,
+    /// Keep records whose effective date is strictly before this ISO date.
+    pub effective_before: Option<String>,
+    /// Keep records whose effective date is strictly after this ISO date.
+    pub effective_after: Option<String>,
+    /// With a date bound set, also keep records that have no valid effective date.
+    pub include_undated: Option<bool>,
+    /// One `ban_status`, or several separated by commas (any of them matches).
+    pub status: Option<String>,
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|s| !s.is_empty())
+}
+
+impl FilterParams {
+    /// Whether no filter was requested (so the full dataset applies).
+    pub fn is_empty(&self) -> bool {
+        non_empty(&self.state).is_none()
+            && non_empty(&self.county).is_none()
+            && !self.has_date_bounds()
+            && non_empty(&self.status).is_none()
+    }
+
+    /// Requested state codes that appear in none of the records (`known`
+    /// holds the uppercased codes that do), and so match nothing.
+    pub fn unknown_states(&self, known: &BTreeSet<String>) -> Vec<&str> {
+        self.listed_states()
+            .into_iter()
+            .flatten()
+            .filter(|code| !known.contains(&code.to_ascii_uppercase()))
+            .collect()
+    }
+
+    /// Resolve the parameters once for matching many records. `known`
+    /// holds the uppercased state codes present in the records; requested
+    /// codes outside it are left out, so a list of only those matches nothing.
+    pub fn resolve(&self, known: &BTreeSet<String>, config: &Config) -> RecordFilter {
+        let states = self.listed_states().map(|list| {
+            let mut states: Vec<String> = list
+                .map(str::to_ascii_uppercase)
+                .filter(|code| known.contains(code))
+                .collect();
+            states.sort();
+            states.dedup();
+            states
+        });
+        let (before, after) = self.date_bounds();
+        RecordFilter {
+            states,
+            county: self
+                .county_filter()
+                .map(|county| canonical_county(county, config.normalize_county)),
+            normalize_county: config.normalize_county,
+            statuses: self.statuses(),
+            dated: self.has_date_bounds(),
+            before,
+            after,
+            include_undated: self.include_undated.unwrap_or(false),
+        }
+    }
+
+    fn listed_states(&self) -> Option<impl Iterator<Item = &str>> {
+        non_empty(&self.state).map(|list| list.split(',').map(str::trim).filter(|s| !s.is_empty()))
+    }
+
+    /// The requested county, if any.
+    pub fn county_filter(&self) -> Option<&str> {
+        non_empty(&self.county)
+    }
+
+    /// The requested statuses, leaving out unknown names; `None` when no
+    /// status was requested.
+    pub fn statuses(&self) -> Option<Vec<BanStatus>> {
+        let list = non_empty(&self.status)?;
+        let statuses = BanStatus::ALL
+            .into_iter()
+            .filter(|status| {
+                list.split(',')
+                    .any(|name| BanStatus::from_name(name) == Some(*status))
+            })
+            .collect();
+        Some(statuses)
+    }
+
+    /// The validated (before, after) effective-date bounds as ISO dates.
+    pub fn date_bounds(&self) -> (Option<String>, Option<String>) {
+        (
+            non_empty(&self.effective_before).and_then(parse_iso_date),
+            non_empty(&self.effective_after).and_then(parse_iso_date),
+        )
+    }
+
+    fn has_date_bounds(&self) -> bool {
+        non_empty(&self.effective_before).is_some() || non_empty(&self.effective_after).is_some()
+    }
+
+    /// Check parameters that need more than deserialization, returning a
+    /// message suitable for a 400 response.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [
+            ("effective_before", &self.effective_before),
+            ("effective_after", &self.effective_after),
+        ] {
+            if let Some(raw) = non_empty(value) {
+                if parse_iso_date(raw).is_none() {
+                    return Err(format!(
+                        "Invalid {}: expected a YYYY-MM-DD date, got \"{}\"",
+                        name,
+                        snippet(raw)
+                    ));
+                }
+            }
+        }
+        if let Some(list) = non_empty(&self.status) {
+            let unknown = list
+                .split(',')
+                .map(str::trim)
+                .find(|name| !name.is_empty() && BanStatus::from_name(name).is_none());
+            if let Some(name) = unknown {
+                let known: Vec<&str> = BanStatus::ALL.iter().map(|s| s.as_str()).collect();
+                return Err(format!(
+                    "Invalid status \"{}\": expected one of {}",
+                    snippet(name),
+                    known.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [`FilterParams`] resolved against a dataset, ready to test each record.
+#[derive(Debug)]
+pub struct RecordFilter {
+    /// Uppercased codes to keep; `None` when no state was requested.
+    pub states: Option<Vec<String>>,
+    /// The requested county in canonical form.
+    pub county: Option<String>,
+    normalize_county: bool,
+    /// The statuses to keep; `None` when no status was requested.
+    pub statuses: Option<Vec<BanStatus>>,
+    /// Whether either date bound was given (even one that didn't parse).
+    dated: bool,
+    /// The (before, after) effective-date bounds as ISO dates.
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// With date bounds, also keep records that have no valid effective date.
+    pub include_undated: bool,
+}
+
+impl RecordFilter {
+    /// A normalized description of the filters, for keying cached results.
+    pub fn cache_key(&self) -> String {
+        format!(
+            "state={:?}|county={:?}|before={:?}|after={:?}|undated={}|status={:?}",
+            self.states,
+            self.county.as_deref().map(str::to_lowercase),
+            self.before,
+            self.after,
+            self.include_undated,
+            self.statuses
+        )
+    }
+
+    fn matches_dates(&self, record: &Value) -> bool {
+        if !self.dated {
+            return true;
+        }
+        let valid = record.get("effective_date_valid").and_then(Value::as_bool) != Some(false);
+        let date = match record.get("effective_date").and_then(Value::as_str) {
+            Some(date) if valid => date,
+            _ => return self.include_undated,
+        };
+        // ISO dates compare correctly as strings.
+        let before_ok = self
+            .before
+            .as_ref()
+            .is_none_or(|bound| date < bound.as_str());
+        let after_ok = self
+            .after
+            .as_ref()
+            .is_none_or(|bound| date > bound.as_str());
+        before_ok && after_ok
+    }
+
+    pub fn matches(&self, record: &Value) -> bool {
+        if let Some(states) = &self.states {
+            let state = field_str(record, "State").trim();
+            if !states.iter().any(|s| s.eq_ignore_ascii_case(state)) {
+                return false;
+            }
+        }
+        if let Some(wanted) = &self.county {
+            let actual = canonical_county(field_str(record, "County"), self.normalize_county);
+            if !actual.eq_ignore_ascii_case(wanted) {
+                return false;
+            }
+        }
+        if let Some(statuses) = &self.statuses {
+            if !statuses.contains(&BanStatus::of_record(record)) {
+                return false;
+            }
+        }
+        self.matches_dates(record)
+    }
+}
+
+/// Columns that list endpoints can be sorted by, as (parameter, record field).
+pub const SORT_FIELDS: [(&str, &str); 5] = [
+    ("state", "State"),
+    ("city", "City"),
+    ("county", "County"),
+    ("zip", "Zip"),
+    ("effective_date", "effective_date"),
+];
+
+/// `?sort=<field>&order=asc|desc`; without `sort` records keep sheet order.
+#[derive(Debug, Default, Deserialize)]
+pub struct SortParams {
+    pub sort: Option<String>,
+    pub order: Option<String>,
+}
+
+impl SortParams {
+    /// Resolve the parameters to (record field, descending), `None` meaning
+    /// sheet order. Unknown fields or orders produce a 400-ready message.
+    pub fn resolve(&self) -> Result<Option<(&'static str, bool)>, String> {
+        let descending = match non_empty(&self.order)
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => {
+                return Err(format!(
+                    "Invalid order \"{}\": expected asc or desc",
+                    snippet(other)
+                ));
+            }
+        };
+        let sort = match non_empty(&self.sort) {
+            Some(sort) => sort,
+            None => return Ok(None),
+        };
+        SORT_FIELDS
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(sort))
+            .map(|&(_, field)| Some((field, descending)))
+            .ok_or_else(|| {
+                let known: Vec<&str> = SORT_FIELDS.iter().map(|(param, _)| *param).collect();
+                format!(
+                    "Invalid sort \"{}\": expected one of {}",
+                    snippet(sort),
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Stable, case-insensitive sort of records by one field.
+pub fn sort_records(records: &mut [&Value], field: &str, descending: bool) {
+    if descending {
+        records.sort_by_cached_key(|record| Reverse(field_str(record, field).to_lowercase()));
+    } else {
+        records.sort_by_cached_key(|record| field_str(record, field).to_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn matches_any_of_several_states_in_the_data() {
+        let known: BTreeSet<String> = ["AL", "FL", "MA", "MS", "ON"].map(String::from).into();
+        let filters = FilterParams {
+            state: Some("fl, AL,ms,XX,on".to_string()),
+            ..Default::default()
+        };
+        let config = Config::default();
+        let filter = filters.resolve(&known, &config);
+        assert_eq!(filter.states.as_deref().unwrap(), ["AL", "FL", "MS", "ON"]);
+        assert_eq!(filters.unknown_states(&known), ["XX"]);
+        let kept: Vec<&str> = ["FL", "MA", "al", "MS", "TX", "ON"]
+            .into_iter()
+            .filter(|state| filter.matches(&json!({ "State": state })))
+            .collect();
+        assert_eq!(kept, ["FL", "al", "MS", "ON"]);
+
+        let unknown_only = FilterParams {
+            state: Some("XX".to_string()),
+            ..Default::default()
+        };
+        let filter = unknown_only.resolve(&known, &config);
+        assert!(!filter.matches(&json!({ "State": "XX" })));
+    }
+
+    #[test]
+    fn filters_by_ban_status() {
+        let filters = FilterParams {
+            status: Some("Pending, banned".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filters.statuses().unwrap(),
+            [BanStatus::Banned, BanStatus::Pending]
+        );
+        let filter = filters.resolve(&BTreeSet::new(), &Config::default());
+        assert!(filter.matches(&json!({ "ban_status": "pending" })));
+        assert!(!filter.matches(&json!({ "ban_status": "restricted" })));
+        // Without a status column every row counts as a ban.
+        assert!(filter.matches(&json!({ "State": "FL" })));
+
+        let invalid = FilterParams {
+            status: Some("banned,maybe".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid.validate().unwrap_err(),
+            "Invalid status \"maybe\": expected one of banned, restricted, pending, unknown"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::filter::field_str;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// ---------------------------------------------------------------------------
+// Zip code coordinates (bundled via the `zipcodes` crate) and distances
+// ---------------------------------------------------------------------------
+
+/// A point as (latitude, longitude) in decimal degrees.
+pub type LatLon = (f64, f64);
+
+/// What the bundled database knows about one zip code.
+pub struct Place {
+    pub city: &'static str,
+    pub county: &'static str,
+    pub state: &'static str,
+    pub coordinates: LatLon,
+}
+
+/// Zip → place, built from the embedded database on first use.
+fn table() -> &'static HashMap<&'static str, Place> {
+    static TABLE: OnceLock<HashMap<&'static str, Place>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        zipcodes::database()
+            .iter()
+            .filter_map(|z| {
+                let lat = z.lat.parse().ok()?;
+                let lon = z.long.parse().ok()?;
+                let place = Place {
+                    city: &z.city,
+                    county: &z.county,
+                    state: &z.state,
+                    coordinates: (lat, lon),
+                };
+                Some((z.zip_code.as_str(), place))
+            })
+            .collect()
+    })
+}
+
+/// Normalize a sheet zip to five digits: ZIP+4 suffixes are dropped and
+/// leading zeros that a spreadsheet stripped ("1850") are restored. Fewer
+/// than three digits can't be a stripped zip (the lowest is 00501), so those
+/// are rejected.
+pub fn normalize_zip(raw: &str) -> Option<String> {
+    let digits = raw.trim().split(['-', ' ']).next().unwrap_or("");
+    if !(3..=5).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{:0>5}", digits))
+}
+
+/// The city, county, state, and centroid of a zip code, if it is known.
+pub fn lookup(zip: &str) -> Option<&'static Place> {
+    table().get(normalize_zip(zip)?.as_str())
+}
+
+/// Comparison key for city names, so the sheet's "St. Petersburg" matches the
+/// database's "Saint Petersburg" (likewise Ft./Fort and Mt./Mount).
+pub fn city_key(name: &str) -> String {
+    let lower = name.trim().to_lowercase();
+    for (short, long) in [("st", "saint"), ("ft", "fort"), ("mt", "mount")] {
+        for prefix in [format!("{}. ", short), format!("{} ", short)] {
+            if let Some(rest) = lower.strip_prefix(&prefix) {
+                return format!("{} {}", long, rest.trim_start());
+            }
+        }
+    }
+    lower
+}
+
+/// Coordinates of a zip code's centroid, if it is known.
+pub fn coordinates(zip: &str) -> Option<LatLon> {
+    lookup(zip).map(|place| place.coordinates)
+}
+
+/// Great-circle distance in miles.
+pub fn distance_mi(a: LatLon, b: LatLon) -> f64 {
+    zipcodes::haversine(a.1, a.0, b.1, b.0)
+}
+
+/// Records whose zip lies within `radius_mi` of `center`, paired with their
+/// distance, plus how many records were skipped because their zip has no
+/// known coordinates.
+pub fn within_radius<'a>(
+    records: &[&'a Value],
+    center: LatLon,
+    radius_mi: f64,
+) -> (Vec<(f64, &'a Value)>, usize) {
+    let mut ungeocoded = 0;
+    let mut hits = Vec::new();
+    for &record in records {
+        match coordinates(field_str(record, "Zip")) {
+            Some(point) => {
+                let distance = distance_mi(center, point);
+                if distance <= radius_mi {
+                    hits.push((distance, record));
+                }
+            }
+            None => ungeocoded += 1,
+        }
+    }
+    (hits, ungeocoded)
+}
+
+/// Round a distance to one decimal place for display.
+pub fn round_mi(distance: f64) -> f64 {
+    (distance * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_sheet_zips() {
+        assert_eq!(normalize_zip(" 33701 ").as_deref(), Some("33701"));
+        assert_eq!(normalize_zip("1850").as_deref(), Some("01850"));
+        assert_eq!(normalize_zip("33701-1234").as_deref(), Some("33701"));
+        assert_eq!(normalize_zip("501").as_deref(), Some("00501"));
+        assert_eq!(normalize_zip("1"), None);
+        assert_eq!(normalize_zip("85"), None);
+        assert_eq!(normalize_zip("n/a"), None);
+        assert_eq!(normalize_zip(""), None);
+    }
+
+    #[test]
+    fn city_keys_expand_abbreviations() {
+        assert_eq!(city_key("St. Petersburg"), city_key("Saint Petersburg"));
+        assert_eq!(city_key("FT MYERS"), "fort myers");
+        assert_eq!(city_key("Stuart"), "stuart");
+    }
+
+    #[test]
+    fn measures_distances_between_known_zips() {
+        let st_pete = coordinates("33701").unwrap();
+        let clearwater = coordinates("33755").unwrap();
+        let lowell = coordinates("01850").unwrap();
+        let near = distance_mi(st_pete, clearwater);
+        assert!((10.0..25.0).contains(&near), "{}", near);
+        assert!(distance_mi(st_pete, lowell) > 1000.0);
+        assert_eq!(coordinates("00000"), None);
+    }
+}
@@ -0,0 +1,53 @@
+use serde::Serialize;
+
+// ---------------------------------------------------------------------------
+// Localized page strings, selected from the request's Accept-Language
+// ---------------------------------------------------------------------------
+
+/// User-facing strings rendered into the index page for one language.
+#[derive(Debug, Serialize)]
+pub struct Strings {
+    #[serde(skip)]
+    pub lang: &'static str,
+    pub header: &'static str,
+    pub disclaimer: &'static str,
+}
+
+/// Supported languages; the first entry is the default.
+pub const LANGUAGES: [Strings; 2] = [
+    Strings {
+        lang: "en",
+        header: "GKC Kratom Bans 🌌",
+        disclaimer: "This service is provided for entertainment purposes only and is not a substitute for legal advice. Please consult a lawyer for the most up-to-date legal information.",
+    },
+    Strings {
+        lang: "es",
+        header: "Prohibiciones de Kratom GKC 🌌",
+        disclaimer: "Este servicio se ofrece solo con fines de entretenimiento y no sustituye el asesoramiento legal. Consulte a un abogado para obtener la información legal más actualizada.",
+    },
+];
+
+/// Pick the best supported language for an `Accept-Language` header value,
+/// honoring q-values and matching on the primary subtag ("es-MX" → "es").
+/// Falls back to English when nothing matches.
+pub fn negotiate(accept_language: Option<&str>) -> &'static Strings {
+    let mut best: Option<(&'static Strings, f32)> = None;
+    for entry in accept_language.unwrap_or("").split(',') {
+        let mut parts = entry.split(';');
+        let tag = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let primary = tag.split('-').next().unwrap_or("");
+        if let Some(strings) = LANGUAGES
+            .iter()
+            .find(|s| s.lang.eq_ignore_ascii_case(primary))
+        {
+            if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                best = Some((strings, quality));
+            }
+        }
+    }
+    best.map(|(strings, _)| strings).unwrap_or(&LANGUAGES[0])
+}
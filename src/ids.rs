@@ -0,0 +1,123 @@
+use crate::filter::field_str;
+use crate::state::fnv1a64;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// ---------------------------------------------------------------------------
+// Stable per-record IDs
+// ---------------------------------------------------------------------------
+
+/// The fields a record's ID is derived from.
+pub const ID_FIELDS: [&str; 4] = ["State", "City", "County", "Zip"];
+
+/// The ID of a record with these key fields: 12 hex digits of a hash of the
+/// trimmed, lowercased `ID_FIELDS`, so it survives refreshes, reordering and
+/// edits to any other column.
+pub fn record_id(record: &Value) -> String {
+    let key = ID_FIELDS
+        .iter()
+        .map(|field| field_str(record, field).trim().to_lowercase())
+        .collect::<Vec<_>>()
+        .join("\u{1f}");
+    format!("{:012x}", fnv1a64(key.as_bytes()) & 0xffff_ffff_ffff)
+}
+
+/// Give every record an `id`, replacing any it had. Records sharing an ID
+/// are told apart by the rest of their content: each gets a suffix hashed
+/// from its other fields, so the IDs don't depend on sheet order. Rows that
+/// are identical throughout get `-2`, `-3`, ... appended as well.
+pub fn assign_ids(records: &mut [Value]) {
+    let bases: Vec<String> = records.iter().map(record_id).collect();
+    let mut shared: HashMap<&str, usize> = HashMap::new();
+    for base in &bases {
+        *shared.entry(base).or_default() += 1;
+    }
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (record, base) in records.iter_mut().zip(&bases) {
+        let id = match shared[base.as_str()] {
+            1 => base.clone(),
+            _ => format!("{}-{:06x}", base, content_hash(record)),
+        };
+        let Some(fields) = record.as_object_mut() else {
+            continue;
+        };
+        let count = seen.entry(id.clone()).or_default();
+        *count += 1;
+        let id = match *count {
+            1 => id,
+            n => format!("{}-{}", id, n),
+        };
+        fields.insert("id".to_string(), Value::String(id));
+    }
+}
+
+/// 24 bits of a hash of every field but `id`, in key order.
+fn content_hash(record: &Value) -> u64 {
+    let key = record
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| key.as_str() != "id")
+        .map(|(key, value)| format!("{}\u{1f}{}", key, value))
+        .collect::<Vec<_>>()
+        .join("\u{1e}");
+    fnv1a64(key.as_bytes()) & 0xff_ffff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn ids_are_stable_and_unique() {
+        let mut first = vec![
+            json!({"Zip": "33701", "City": "St. Petersburg", "State": "FL", "County": "Pinellas"}),
+            json!({"Zip": "01850", "City": "Lowell", "State": "MA", "County": "Middlesex"}),
+        ];
+        // A later refresh: rows moved, casing and other columns changed.
+        let mut second = vec![
+            json!({"Zip": "01850", "City": "LOWELL", "State": "MA", "County": "Middlesex", "Notes": "x"}),
+            json!({"City": "St. Petersburg ", "Zip": "33701", "County": "Pinellas", "State": "fl"}),
+        ];
+        assign_ids(&mut first);
+        assign_ids(&mut second);
+        let id = |record: &Value| record["id"].as_str().unwrap().to_string();
+
+        assert_eq!(id(&first[0]), id(&second[1]));
+        assert_eq!(id(&first[1]), id(&second[0]));
+        assert_eq!(id(&first[0]).len(), 12);
+
+        // Assigning again (e.g. to cached records) changes nothing.
+        let again = {
+            let mut records = first.clone();
+            assign_ids(&mut records);
+            records
+        };
+        assert_eq!(again, first);
+        assert_ne!(
+            record_id(&json!({"State": "FL", "City": "Tampa"})),
+            record_id(&json!({"State": "FL", "County": "Tampa"}))
+        );
+    }
+
+    #[test]
+    fn rows_sharing_key_fields_are_told_apart_by_content() {
+        let lowell =
+            |notes: &str| json!({"Zip": "01850", "City": "Lowell", "State": "MA", "Notes": notes});
+        let mut records = vec![lowell("city ban"), lowell("county ban"), lowell("city ban")];
+        let mut reordered = vec![lowell("county ban"), lowell("city ban")];
+        assign_ids(&mut records);
+        assign_ids(&mut reordered);
+        let id = |record: &Value| record["id"].as_str().unwrap().to_string();
+
+        let base = record_id(&records[0]);
+        assert!(id(&records[0]).starts_with(&format!("{}-", base)));
+        assert_ne!(id(&records[0]), id(&records[1]));
+        // Identical rows are numbered in order; the others keep their IDs
+        // whatever the order.
+        assert_eq!(id(&records[2]), format!("{}-2", id(&records[0])));
+        assert_eq!(id(&reordered[0]), id(&records[1]));
+        assert_eq!(id(&reordered[1]), id(&records[0]));
+    }
+}
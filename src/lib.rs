@@ -0,0 +1,7 @@
+//! Types for Rust clients of the GKC Kratom Bans service. The server itself
+//! is the `gkcsearch` binary; this library holds what another crate needs to
+//! consume its JSON endpoints with typed records.
+
+pub mod record;
+
+pub use record::BanRecord;
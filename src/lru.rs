@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// ---------------------------------------------------------------------------
+// Bounded LRU of computed results
+// ---------------------------------------------------------------------------
+
+/// A cached value and the clock value of its last use.
+type CacheEntry<V> = (u64, V);
+
+/// Up to `capacity` values keyed by string, evicting the least recently used
+/// one when full. Values are cloned out, so they are usually `Arc`s.
+pub struct LruCache<V> {
+    capacity: usize,
+    entries: Mutex<HashMap<String, CacheEntry<V>>>,
+    /// Incremented on every access; the entry with the lowest stamp is the
+    /// least recently used.
+    clock: AtomicU64,
+    hits: AtomicU64,
+}
+
+impl<V: Clone> Default for LruCache<V> {
+    fn default() -> Self {
+        LruCache::new(0)
+    }
+}
+
+impl<V: Clone> LruCache<V> {
+    /// A cache holding up to `capacity` values (0 disables it).
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached value for `key`, marking it as recently used.
+    pub fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        let (stamp, value) = entries.get_mut(key)?;
+        *stamp = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(value.clone())
+    }
+
+    /// Remember `value` for `key`, evicting the least recently used entry
+    /// when full.
+    pub fn insert(&self, key: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (stamp, _))| *stamp)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let stamp = self.clock.fetch_add(1, Ordering::Relaxed);
+        entries.insert(key, (stamp, value));
+    }
+
+    /// How many lookups were answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
@@ -1,263 +1,2136 @@
-use actix_web::{get, App, HttpResponse, HttpServer, Responder};
+// The OpenAPI document is one large `json!` literal.
+#![recursion_limit = "256"]
+
+mod cache;
+mod capabilities;
+mod config;
+mod dates;
+#[cfg(feature = "sqlite")]
+mod db;
+mod errors;
+mod export;
+mod filter;
+mod geo;
+mod i18n;
+mod ids;
+mod lru;
+mod metrics;
+mod names;
+mod openapi;
+mod rate_limit;
+mod recent;
+mod regions;
+mod response_cache;
+mod schedule;
+mod search;
+mod sheet;
+mod snapshot;
+mod state;
+mod status;
+mod supplemental;
+mod text;
+mod tree;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::{from_fn, Next, NormalizePath};
+use actix_web::{
+    error, get, post, web, App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer,
+    Responder, ResponseError,
+};
+use config::Config;
+use filter::{canonical_county, field_str, sort_records, FilterParams, RecordFilter, SortParams};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use search::MatchMode;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use csv::{ReaderBuilder, StringRecord};
-use std::cmp::min;
-use std::time::Duration;
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
-use std::error::Error;
+use socket2::{Domain, Protocol, Socket, Type};
+use state::AppState;
+use status::BanStatus;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // ---------------------------------------------------------------------------
-// Backend: CSV fetching, processing, and caching
+// API endpoints
 // ---------------------------------------------------------------------------
 
-/// Fetch the CSV data from Google Sheets and convert it to JSON.
-async fn fetch_sheet_data_from_google() -> Result<Value, Box<dyn Error>> {
-    // Google Sheet CSV export URL – ensure your sheet is publicly accessible.
-    let sheet_url = "https://docs.google.com/spreadsheets/d/18kCz2igidQVgqwLdpsDA15kYXLxqX99r/export?format=csv&gid=1370952005";
-    let response = reqwest::get(sheet_url).await?.text().await?;
-    
-    println!(
-        "Raw CSV response (first 500 chars): {}",
-        &response[..min(response.len(), 500)]
-    );
-    
-    // Remove any potential BOM.
-    let response = response.trim_start_matches('\u{feff}');
-    
-    // Auto-detect delimiter by comparing commas and semicolons in the first line.
-    let first_line = response.lines().next().unwrap_or("");
-    let comma_count = first_line.matches(',').count();
-    let semicolon_count = first_line.matches(';').count();
-    let delimiter = if semicolon_count > comma_count { b';' } else { b',' };
-    println!("Detected delimiter: '{}'", delimiter as char);
-    
-    // Build CSV reader without headers.
-    let mut rdr = ReaderBuilder::new()
-        .delimiter(delimiter)
-        .has_headers(false)
-        .flexible(true)
-        .from_reader(response.as_bytes());
-    
-    let mut header_record: Option<StringRecord> = None;
-    let mut records = Vec::new();
-    
-    for result in rdr.records() {
-        let record = result?;
-        // Skip empty rows.
-        if record.iter().all(|f| f.trim().is_empty()) {
-            continue;
-        }
-        // Look for the header row (the proper header appears when the second field is "Zip").
-        if header_record.is_none() {
-            if record.len() >= 2 && record.get(1).map(|s| s.trim()) == Some("Zip") {
-                header_record = Some(record);
-                println!("Found header row: {:?}", header_record);
+/// Response header telling clients whether `/data` was fresh or stale.
+const CACHE_STATUS_HEADER: &str = "X-Cache-Status";
+
+/// Response header naming where the served data came from (`cache`, `sheet`,
+/// `seed` when the disaster-recovery dataset is in use, or `embedded` offline).
+const DATA_SOURCE_HEADER: &str = "X-Data-Source";
+
+/// `Retry-After` for `/data` while the startup background load is pending.
+const NOT_READY_RETRY_SECS: u64 = 5;
+
+/// `Cache-Control` for the sheet data and supplemental info: shared caches may
+/// keep them for `http_max_age` (capped at the TTL) and serve them stale for
+/// as long as the server itself would.
+fn data_cache_control(config: &Config) -> String {
+    format!(
+        "public, max-age={}, stale-while-revalidate={}",
+        config.http_max_age.min(config.cache_ttl).as_secs(),
+        config.stale_while_revalidate.as_secs()
+    )
+}
+
+/// `Cache-Control` for live status endpoints, which must never be reused.
+const NO_STORE: &str = "no-store";
+
+/// Whether the request's `If-None-Match` header already names `etag`.
+fn etag_matches(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        })
+        .unwrap_or(false)
+}
+
+/// Response header listing `state=` codes that were ignored as unknown.
+const IGNORED_STATES_HEADER: &str = "X-Ignored-States";
+
+/// Report codes in the `state=` filter that aren't in the data, which match
+/// nothing.
+fn report_ignored_states(
+    response: &mut HttpResponseBuilder,
+    filters: &FilterParams,
+    dataset: &state::Dataset,
+) {
+    let unknown = filters.unknown_states(&dataset.states);
+    if !unknown.is_empty() {
+        response.insert_header((IGNORED_STATES_HEADER, unknown.join(",")));
+    }
+}
+
+/// Response headers set when a list was cut down to `Config::max_records`.
+const TRUNCATED_HEADER: &str = "X-Truncated";
+const TOTAL_COUNT_HEADER: &str = "X-Total-Count";
+
+/// Cut `records` down to the configured cap, returning a response builder that
+/// flags the truncation (total count plus a hint to narrow the query).
+fn capped_response<T>(records: &mut Vec<T>, config: &Config) -> HttpResponseBuilder {
+    let mut builder = HttpResponse::Ok();
+    let total = records.len();
+    if config.max_records > 0 && total > config.max_records {
+        records.truncate(config.max_records);
+        builder
+            .insert_header((TRUNCATED_HEADER, "true"))
+            .insert_header((TOTAL_COUNT_HEADER, total.to_string()))
+            .insert_header((
+                header::WARNING,
+                format!(
+                    "199 - \"Truncated to {} of {} records; narrow with filters or a query\"",
+                    config.max_records, total
+                ),
+            ));
+    }
+    builder
+}
+
+/// `?envelope=true` wraps list responses as `{data, meta}`.
+#[derive(Deserialize)]
+struct EnvelopeParams {
+    envelope: Option<bool>,
+}
+
+impl EnvelopeParams {
+    fn enabled(&self) -> bool {
+        self.envelope.unwrap_or(false)
+    }
+}
+
+/// `?group_by=state|city` returns `/data` as an object of record arrays.
+#[derive(Deserialize)]
+struct GroupParams {
+    group_by: Option<String>,
+}
+
+impl GroupParams {
+    /// The record field to group by, if any, or a 400-ready message.
+    fn resolve(&self) -> Result<Option<&'static str>, String> {
+        let Some(raw) = self
+            .group_by
+            .as_deref()
+            .map(str::trim)
+            .filter(|g| !g.is_empty())
+        else {
+            return Ok(None);
+        };
+        tree::GROUP_FIELDS
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(raw))
+            .map(|&(_, field)| Some(field))
+            .ok_or_else(|| {
+                format!(
+                    "Invalid group_by \"{}\": expected state or city",
+                    text::snippet(raw)
+                )
+            })
+    }
+}
+
+/// Finish a list response, either as the bare array or, when requested,
+/// wrapped with provenance and freshness metadata that survives proxies
+/// stripping headers. `total` counts matches before any truncation.
+fn list_response<T: Serialize>(
+    mut builder: HttpResponseBuilder,
+    data: T,
+    total: usize,
+    dataset: &state::Dataset,
+    envelope: &EnvelopeParams,
+) -> HttpResponse {
+    if !envelope.enabled() {
+        return builder.json(data);
+    }
+    builder.json(json!({
+        "data": data,
+        "meta": {
+            "total": total,
+            "cache_age_seconds": dataset.age().as_secs(),
+            "source": dataset.source.as_str(),
+            "generated_at": dates::format_rfc3339(std::time::SystemTime::now()),
+        }
+    }))
+}
+
+/// Endpoint to return banned area data as JSON (pre-serialized per refresh).
+/// Filter and sort parameters are applied on the fly, and the response is
+/// capped at `Config::max_records`.
+#[get("/data")]
+async fn data_handler(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    filters: web::Query<FilterParams>,
+    sort: web::Query<SortParams>,
+    envelope: web::Query<EnvelopeParams>,
+    group: web::Query<GroupParams>,
+) -> impl Responder {
+    let config = state.config();
+    let sort = match filters.validate().and_then(|_| sort.resolve()) {
+        Ok(sort) => sort,
+        Err(msg) => return errors::bad_request(msg),
+    };
+    let group_by = match group.resolve() {
+        Ok(field) => field,
+        Err(msg) => return errors::bad_request(msg),
+    };
+    if state.awaiting_first_load() {
+        return HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, NOT_READY_RETRY_SECS.to_string()))
+            .json(errors::error_body(
+                "not_ready",
+                "The data is still loading; retry shortly.",
+            ));
+    }
+    let (dataset, cache_status, load_timing) = match state.dataset_timed().await {
+        Ok(loaded) => loaded,
+        Err(e) => return e.error_response(),
+    };
+    let started = Instant::now();
+    let over_cap = config.max_records > 0 && dataset.records.len() > config.max_records;
+    let shaped = !filters.is_empty() || sort.is_some() || over_cap || envelope.enabled();
+    let mut response = if shaped || group_by.is_some() {
+        let mut records: Vec<&Value> = dataset
+            .matching(None, &dataset.filter(&filters, &config))
+            .await
+            .into_iter()
+            .map(|i| &dataset.records[i])
+            .collect();
+        if let Some((field, descending)) = sort {
+            sort_records(&mut records, field, descending);
+        }
+        let total = records.len();
+        let mut response = capped_response(&mut records, &config);
+        response
+            .insert_header((CACHE_STATUS_HEADER, cache_status.as_str()))
+            .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()));
+        report_ignored_states(&mut response, &filters, &dataset);
+        match group_by {
+            Some(field) => {
+                let groups: std::collections::BTreeMap<_, _> = tree::group_by(records, field)
+                    .into_iter()
+                    .map(|(key, records)| (key, dataset.served_all(records)))
+                    .collect();
+                list_response(response, groups, total, &dataset, &envelope)
             }
-            continue;
-        }
-        // Process data rows using the found header.
-        if let Some(ref header) = header_record {
-            let mut json_record = serde_json::Map::new();
-            for (i, field) in record.iter().enumerate() {
-                let key = match header.get(i) {
-                    Some(s) if !s.trim().is_empty() => s.trim().to_string(),
-                    _ => format!("column_{}", i),
-                };
-                json_record.insert(key, json!(field.trim()));
+            None => {
+                let records = dataset.served_all(records);
+                list_response(response, records, total, &dataset, &envelope)
             }
-            records.push(Value::Object(json_record));
         }
+    } else if etag_matches(&req, &dataset.etag) {
+        HttpResponse::NotModified()
+            .insert_header((header::ETAG, dataset.etag.clone()))
+            .insert_header((CACHE_STATUS_HEADER, cache_status.as_str()))
+            .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+            .finish()
+    } else {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((header::ETAG, dataset.etag.clone()))
+            .insert_header((CACHE_STATUS_HEADER, cache_status.as_str()))
+            .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+            .body(dataset.body.clone())
+    };
+    if let Ok(value) = header::HeaderValue::from_str(&data_cache_control(&config)) {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
     }
-    
-    // Remove unwanted keys.
-    for rec in records.iter_mut() {
-        if let Value::Object(map) = rec {
-            map.remove("Country");
-            map.remove("column_0");
+    if config.server_timing {
+        // Steps skipped because the data was already in memory report 0.
+        let mut timing = load_timing.unwrap_or_default();
+        timing.serialize += started.elapsed();
+        if let Ok(value) = header::HeaderValue::from_str(&timing.header_value()) {
+            response
+                .headers_mut()
+                .insert(header::HeaderName::from_static("server-timing"), value);
         }
     }
-    
-    Ok(json!(records))
-}
-
-/// Cache file path and duration (12 hours).
-const CACHE_FILE: &str = "data_cache.json";
-const CACHE_DURATION: Duration = Duration::from_secs(12 * 60 * 60);
-
-/// Fetch the sheet data with caching.
-async fn fetch_sheet_data() -> Result<Value, Box<dyn Error>> {
-    if let Ok(metadata) = fs::metadata(CACHE_FILE).await {
-        if let Ok(modified) = metadata.modified() {
-            if let Ok(elapsed) = modified.elapsed() {
-                if elapsed < CACHE_DURATION {
-                    println!("Using cached data (age: {:?})", elapsed);
-                    let cached_data = fs::read_to_string(CACHE_FILE).await?;
-                    let json_data: Value = serde_json::from_str(&cached_data)?;
-                    return Ok(json_data);
+    response
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+    /// `and` (default) requires every term to match; `or` accepts any term.
+    mode: Option<String>,
+    /// Opt-in regex search, used instead of `q`.
+    regex: Option<String>,
+    /// Comma-separated fields the regex is matched against (default: all).
+    fields: Option<String>,
+    /// Wrap each result as `{record, matches}` with the matched fields and offsets.
+    highlight: Option<bool>,
+    /// Keep only records within `radius_mi` of this zip, nearest first.
+    near: Option<String>,
+    radius_mi: Option<f64>,
+    /// Add each result's relevance `score` (free-text queries only).
+    debug_score: Option<bool>,
+}
+
+/// Radius used by `/search?near=` when `radius_mi` is omitted.
+const DEFAULT_RADIUS_MI: f64 = 25.0;
+
+/// Response header counting records skipped by `near=` because their zip has
+/// no known coordinates.
+const UNGEOCODED_HEADER: &str = "X-Ungeocoded-Count";
+
+/// Key for the per-dataset search cache: the matching inputs, normalized so
+/// equivalent searches share an entry.
+fn search_cache_key(
+    query: &str,
+    mode: MatchMode,
+    params: &SearchParams,
+    filter: &RecordFilter,
+) -> String {
+    let matcher = match params.regex.as_deref().filter(|r| !r.is_empty()) {
+        Some(pattern) => format!(
+            "regex={}|fields={}",
+            pattern,
+            params.fields.as_deref().unwrap_or("").trim()
+        ),
+        None => format!(
+            "q={}|mode={:?}",
+            query
+                .to_lowercase()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            mode
+        ),
+    };
+    format!("{}|{}", matcher, filter.cache_key())
+}
+
+/// Endpoint to search banned areas by City, County, Zip, or State substring,
+/// optionally narrowed by the filter parameters (mirrors the UI's filtering).
+/// Without `sort`, free-text results come most relevant first
+/// (`debug_score=true` shows the scores).
+/// Whitespace-separated terms must all match unless `mode=or`; terms prefixed
+/// with `-` exclude records (see `/search/help`). `regex` (with optional
+/// `fields`) runs a bounded regex search instead. `highlight=true` reports
+/// where each record matched. `near=<zip>&radius_mi=` keeps records within
+/// the radius, nearest first, with a `distance_mi` field.
+#[get("/search")]
+async fn search_handler(
+    state: web::Data<AppState>,
+    params: web::Query<SearchParams>,
+    filters: web::Query<FilterParams>,
+    sort: web::Query<SortParams>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    let config = state.config();
+    let (sort, mode) = match filters
+        .validate()
+        .and_then(|_| sort.resolve())
+        .and_then(|sort| Ok((sort, MatchMode::parse(params.mode.as_deref())?)))
+    {
+        Ok(resolved) => resolved,
+        Err(msg) => return errors::bad_request(msg),
+    };
+    let query = params.q.as_deref().unwrap_or("");
+    let regex = match params.regex.as_deref().filter(|r| !r.is_empty()) {
+        Some(_) if !query.trim().is_empty() => {
+            return errors::bad_request("Use either q or regex, not both");
+        }
+        Some(pattern) => match search::compile_regex(pattern)
+            .and_then(|regex| Ok((regex, search::resolve_fields(params.fields.as_deref())?)))
+        {
+            Ok(compiled) => Some(compiled),
+            Err(msg) => return errors::bad_request(msg),
+        },
+        None => None,
+    };
+    let near = match params
+        .near
+        .as_deref()
+        .map(str::trim)
+        .filter(|z| !z.is_empty())
+    {
+        Some(zip) => {
+            let radius = params.radius_mi.unwrap_or(DEFAULT_RADIUS_MI);
+            if !radius.is_finite() || radius <= 0.0 {
+                return errors::bad_request("Invalid radius_mi: expected a positive number");
+            }
+            match geo::coordinates(zip) {
+                Some(center) => Some((center, radius)),
+                None => {
+                    return errors::bad_request(format!(
+                        "Unknown zip code for near: \"{}\"",
+                        text::snippet(zip)
+                    ));
                 }
             }
         }
+        None => None,
+    };
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+
+    let filter = dataset.filter(&filters, &config);
+    let key = search_cache_key(query, mode, &params, &filter);
+    let indices = match dataset.search_cache.get(&key) {
+        Some(indices) => indices,
+        None => {
+            let indices = match &regex {
+                Some((regex, fields)) => match search::regex_search(
+                    &dataset.records,
+                    regex,
+                    fields,
+                    search::REGEX_TIME_BUDGET,
+                ) {
+                    Ok(indices) => indices
+                        .into_iter()
+                        .filter(|&i| filter.matches(&dataset.records[i]))
+                        .collect(),
+                    Err(msg) => return errors::bad_request(msg),
+                },
+                None => dataset.matching(Some((query, mode)), &filter).await,
+            };
+            let indices = Arc::new(indices);
+            dataset.search_cache.insert(key, Arc::clone(&indices));
+            indices
+        }
+    };
+    let mut results: Vec<&Value> = indices.iter().map(|&i| &dataset.records[i]).collect();
+    match sort {
+        Some((field, descending)) => sort_records(&mut results, field, descending),
+        None if regex.is_none() => search::rank(&mut results, query),
+        None => {}
     }
-    
-    println!("Fetching fresh data from Google Sheets...");
-    let json_data = fetch_sheet_data_from_google().await?;
-    
-    // Save fresh data to cache.
-    let json_string = serde_json::to_string_pretty(&json_data)?;
-    let mut file = fs::File::create(CACHE_FILE).await?;
-    file.write_all(json_string.as_bytes()).await?;
-    println!("Saved new data to cache.");
-    
-    Ok(json_data)
+    let located: Vec<Value>;
+    let mut ungeocoded = None;
+    if let Some((center, radius)) = near {
+        let (mut hits, skipped) = geo::within_radius(&results, center, radius);
+        if sort.is_none() {
+            hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+        located = hits
+            .into_iter()
+            .map(|(distance, record)| {
+                let mut record = record.clone();
+                record["distance_mi"] = json!(geo::round_mi(distance));
+                record
+            })
+            .collect();
+        results = located.iter().collect();
+        ungeocoded = Some(skipped);
+    }
+    let total = results.len();
+    let mut response = capped_response(&mut results, &config);
+    response.insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()));
+    report_ignored_states(&mut response, &filters, &dataset);
+    if let Some(skipped) = ungeocoded {
+        response.insert_header((UNGEOCODED_HEADER, skipped.to_string()));
+    }
+    let scored: Vec<Value>;
+    if params.debug_score.unwrap_or(false) && regex.is_none() {
+        scored = results
+            .iter()
+            .map(|&record| {
+                let mut scored = record.clone();
+                scored["score"] = json!(search::relevance(record, query));
+                scored
+            })
+            .collect();
+        results = scored.iter().collect();
+    }
+    if !params.highlight.unwrap_or(false) {
+        let results = dataset.served_all(results);
+        return list_response(response, results, total, &dataset, &envelope);
+    }
+    let highlighted: Vec<Value> = results
+        .into_iter()
+        .map(|record| {
+            let matches = match &regex {
+                Some((regex, fields)) => search::highlight_regex(record, regex, fields),
+                None => search::highlight_terms(record, query),
+            };
+            let matches: Vec<Value> = matches
+                .iter()
+                .map(|m| json!({ "field": dataset.served_key(m.field), "start": m.start, "end": m.end }))
+                .collect();
+            json!({ "record": dataset.served(record), "matches": matches })
+        })
+        .collect();
+    list_response(response, highlighted, total, &dataset, &envelope)
 }
 
-// ---------------------------------------------------------------------------
-// API endpoints
-// ---------------------------------------------------------------------------
+#[derive(Deserialize)]
+struct NearbyParams {
+    zip: Option<String>,
+}
 
-/// Endpoint to return banned area data as JSON.
-#[get("/data")]
-async fn data_handler() -> impl Responder {
-    match fetch_sheet_data().await {
-        Ok(json_data) => HttpResponse::Ok().json(json_data),
-        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+/// Endpoint for "is this zip banned?": every record whose zip (normalized to
+/// five digits) is exactly `zip`, plus a top-level `banned` flag.
+#[get("/data/by-zip/{zip}")]
+async fn by_zip_handler(state: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let zip = path.into_inner();
+    if zip.len() != 5 || !zip.bytes().all(|b| b.is_ascii_digit()) {
+        return errors::bad_request(format!(
+            "Invalid zip: expected exactly 5 digits, got \"{}\"",
+            text::snippet(&zip)
+        ));
     }
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let records: Vec<&Value> = dataset
+        .records
+        .iter()
+        .filter(|r| geo::normalize_zip(field_str(r, "Zip")).as_deref() == Some(zip.as_str()))
+        .collect();
+    HttpResponse::Ok()
+        .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+        .json(json!({
+            "zip": zip,
+            "banned": !records.is_empty(),
+            "records": dataset.served_all(records),
+        }))
 }
 
-/// Endpoint to return supplemental info (links, previews, tags) from JSON.
-#[get("/supplemental")]
-async fn supplemental_handler() -> impl Responder {
-    match fs::read_to_string("supplemental.json").await {
-        Ok(data) => match serde_json::from_str::<Value>(&data) {
-            Ok(json_data) => HttpResponse::Ok().json(json_data),
-            Err(e) => HttpResponse::InternalServerError()
-                .body(format!("Error parsing supplemental JSON: {}", e)),
-        },
-        Err(e) => HttpResponse::InternalServerError()
-            .body(format!("Error reading supplemental JSON file: {}", e)),
+#[derive(Deserialize)]
+struct CompareParams {
+    zips: Option<String>,
+}
+
+/// Endpoint for checking many zips at once: for each comma-separated input,
+/// in order, `{ input, zip, banned, records }` as in `/data/by-zip/{zip}`.
+/// Inputs are zero-padded first; ones that aren't a zip get `zip: null`.
+#[get("/compare")]
+async fn compare_handler(
+    state: web::Data<AppState>,
+    params: web::Query<CompareParams>,
+) -> impl Responder {
+    let inputs: Vec<&str> = params
+        .zips
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|zip| !zip.is_empty())
+        .collect();
+    if inputs.is_empty() {
+        return errors::error_response(
+            StatusCode::BAD_REQUEST,
+            errors::MISSING_PARAMETER,
+            "Missing zips: pass ?zips=33701,01850",
+        );
     }
+    compare(&state, &inputs).await
 }
 
-/// The root endpoint (/) serves the complete HTML/JS/CSS page.
-#[get("/")]
-async fn index() -> impl Responder {
-    let html = r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-  <meta charset="UTF-8">
-  <title>GKC Kratom Bans 🌌</title>
-  <style>
-    /* Global reset and smooth transitions */
-    * { box-sizing: border-box; margin: 0; padding: 0; }
-    
-    /* Subtle animated background */
-    @keyframes backgroundAnimation {
-      0% { background-position: 0% 50%; }
-      50% { background-position: 100% 50%; }
-      100% { background-position: 0% 50%; }
+#[derive(Deserialize)]
+struct CompareBody {
+    zips: Vec<String>,
+}
+
+/// `/compare` for lists too long for a query string: the zips come as a
+/// JSON body, `{"zips": ["33701", "01850"]}`, within `GKC_MAX_BODY_BYTES`.
+#[post("/compare")]
+async fn compare_post_handler(
+    state: web::Data<AppState>,
+    body: web::Json<CompareBody>,
+) -> impl Responder {
+    let inputs: Vec<&str> = body
+        .zips
+        .iter()
+        .map(|zip| zip.trim())
+        .filter(|zip| !zip.is_empty())
+        .collect();
+    if inputs.is_empty() {
+        return errors::error_response(
+            StatusCode::BAD_REQUEST,
+            errors::MISSING_PARAMETER,
+            "Missing zips: send {\"zips\": [\"33701\", \"01850\"]}",
+        );
     }
-    body {
-      min-height: 100vh;
-      font-family: 'Roboto', sans-serif;
-      color: #e0e0e0;
-      background: linear-gradient(135deg, #1e1e2f, #2e2e48);
-      background-size: 200% 200%;
-      animation: backgroundAnimation 20s ease infinite;
-      transition: background 0.5s ease;
-      display: flex;
-      flex-direction: column;
+    compare(&state, &inputs).await
+}
+
+/// The `/compare` results for `inputs`, after checking them against
+/// `GKC_MAX_COMPARE_ZIPS`.
+async fn compare(state: &AppState, inputs: &[&str]) -> HttpResponse {
+    let max = state.config().max_compare_zips;
+    if max > 0 && inputs.len() > max {
+        return errors::error_response(
+            StatusCode::BAD_REQUEST,
+            "too_many_zips",
+            format!(
+                "Too many zips: {} given, at most {} per request",
+                inputs.len(),
+                max
+            ),
+        );
     }
-    
-    /* Global link styles: new default and visited colors remain the same */
-    a {
-      color: #66ccff;
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    // One pass over the records, however many zips were asked about.
+    let mut matches: std::collections::HashMap<String, Vec<&Value>> = inputs
+        .iter()
+        .filter_map(|input| geo::normalize_zip(input))
+        .map(|zip| (zip, Vec::new()))
+        .collect();
+    for record in &dataset.records {
+        if let Some(zip) = geo::normalize_zip(field_str(record, "Zip")) {
+            if let Some(records) = matches.get_mut(&zip) {
+                records.push(record);
+            }
+        }
     }
-    a:visited {
-      color: #66ccff;
+    let results: Vec<Value> = inputs
+        .iter()
+        .map(|input| {
+            let zip = geo::normalize_zip(input);
+            let records = zip
+                .as_ref()
+                .and_then(|zip| matches.get(zip))
+                .cloned()
+                .unwrap_or_default();
+            json!({
+                "input": input,
+                "zip": zip,
+                "banned": !records.is_empty(),
+                "records": dataset.served_all(records),
+            })
+        })
+        .collect();
+    HttpResponse::Ok()
+        .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+        .json(results)
+}
+
+#[derive(Deserialize)]
+struct ZipsParams {
+    state: Option<String>,
+    /// Also list zips that don't normalize to five digits, as written.
+    include_invalid: Option<bool>,
+}
+
+/// Endpoint listing every distinct banned zip (zero-padded and sorted), for
+/// clients doing their own membership checks. `X-Total-Count` carries the count.
+#[get("/zips")]
+async fn zips_handler(
+    state: web::Data<AppState>,
+    params: web::Query<ZipsParams>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    let config = state.config();
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let params = params.into_inner();
+    let filters = FilterParams {
+        state: params.state,
+        ..Default::default()
+    };
+    let filter = dataset.filter(&filters, &config);
+    let mut valid = std::collections::BTreeSet::new();
+    let mut invalid = std::collections::BTreeSet::new();
+    for record in dataset.records.iter().filter(|r| filter.matches(r)) {
+        let raw = field_str(record, "Zip").trim();
+        match geo::normalize_zip(raw) {
+            Some(zip) => {
+                valid.insert(zip);
+            }
+            None if !raw.is_empty() => {
+                invalid.insert(raw.to_string());
+            }
+            None => {}
+        }
     }
-    
-    header {
-      background: linear-gradient(135deg, #27293d, #1e1e2f);
-      padding: 20px;
-      text-align: center;
-      font-size: 1.8em;
-      font-weight: bold;
-      box-shadow: 0 2px 4px rgba(0,0,0,0.3);
-      transition: opacity 0.5s ease;
-      /* Subtle pulsing animation */
-      animation: pulse 3s ease-in-out infinite;
+    let mut zips: Vec<String> = valid.into_iter().collect();
+    if params.include_invalid.unwrap_or(false) {
+        zips.extend(invalid);
     }
-    @keyframes pulse {
-      0% { transform: scale(1); }
-      50% { transform: scale(1.02); }
-      100% { transform: scale(1); }
+    let total = zips.len();
+    let mut response = HttpResponse::Ok();
+    response.insert_header((TOTAL_COUNT_HEADER, total.to_string()));
+    list_response(response, zips, total, &dataset, &envelope)
+}
+
+#[derive(Deserialize)]
+struct RandomParams {
+    state: Option<String>,
+    /// Makes the pick repeatable: the same seed and data give the same record.
+    seed: Option<u64>,
+}
+
+/// Endpoint returning one random banned record, optionally within a state,
+/// for "did you know" widgets. `404` when nothing is in scope.
+#[get("/random")]
+async fn random_handler(
+    state: web::Data<AppState>,
+    params: web::Query<RandomParams>,
+) -> impl Responder {
+    let config = state.config();
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let params = params.into_inner();
+    let filters = FilterParams {
+        state: params.state,
+        ..Default::default()
+    };
+    let filter = dataset.filter(&filters, &config);
+    let records: Vec<&Value> = dataset
+        .records
+        .iter()
+        .filter(|r| filter.matches(r))
+        .collect();
+    let picked = match params.seed {
+        Some(seed) => records.choose(&mut StdRng::seed_from_u64(seed)),
+        None => records.choose(&mut rand::thread_rng()),
+    };
+    match picked {
+        Some(record) => HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, NO_STORE))
+            .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+            .json(dataset.served(record)),
+        None => errors::not_found("No banned areas match"),
     }
-    
-    main {
-      flex: 1;
-      padding: 20px;
-      max-width: 1200px;
-      width: 100%;
-      margin: 0 auto;
+}
+
+/// `/recent` length when `limit` isn't given.
+const DEFAULT_RECENT_LIMIT: usize = 10;
+
+#[derive(Deserialize)]
+struct RecentParams {
+    /// How many records to return (default 10, at most `GKC_MAX_RECORDS`).
+    limit: Option<usize>,
+}
+
+/// Endpoint for "recently added" feeds: the newest records by first-seen
+/// time with `GKC_FIRST_SEEN`, otherwise by effective date.
+#[get("/recent")]
+async fn recent_handler(
+    state: web::Data<AppState>,
+    params: web::Query<RecentParams>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    let config = state.config();
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let mut limit = params.limit.unwrap_or(DEFAULT_RECENT_LIMIT);
+    if config.max_records > 0 {
+        limit = limit.min(config.max_records);
     }
-    
-    /* Search panel styling */
-    .search-panel {
-      display: flex;
-      flex-wrap: wrap;
-      gap: 10px;
-      justify-content: center;
-      margin-bottom: 10px;
-      transition: opacity 0.5s ease;
+    let newest = recent::newest(&dataset.records, dataset.first_seen.as_ref(), limit);
+    let records = dataset.served_all(&newest);
+    let total = records.len();
+    list_response(HttpResponse::Ok(), records, total, &dataset, &envelope)
+}
+
+/// The verdict line for `zip`, worded like the page's: banned itself, banned
+/// through its city or county, through a statewide ban, or not banned.
+/// `bans` holds how each ban applying to the zip matched it.
+fn verdict_message(zip: &str, bans: &[MatchedBy]) -> String {
+    if bans.contains(&MatchedBy::ExactZip) {
+        format!("❌ {} is in a banned area.", zip)
+    } else if bans.contains(&MatchedBy::City) || bans.contains(&MatchedBy::County) {
+        format!(
+            "⚠️ {} is not banned itself, but its city or county has bans.",
+            zip
+        )
+    } else if bans.contains(&MatchedBy::State) {
+        format!(
+            "⚠️ {} is not banned itself, but its state has a statewide ban.",
+            zip
+        )
+    } else {
+        format!(
+            "✅ Congratulations! There do not appear to be bans near \"{}\".",
+            zip
+        )
     }
-    .search-panel input[type="text"],
-    .search-panel select {
-      padding: 10px;
-      border-radius: 5px;
-      border: 1px solid #444;
-      background: rgba(44, 47, 58, 0.9);
-      color: #e0e0e0;
-      font-size: 1em;
-      min-width: 200px;
-      transition: box-shadow 0.2s ease, opacity 0.5s ease;
+}
+
+/// How a record applies to a zip in `/check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum MatchedBy {
+    ExactZip,
+    City,
+    County,
+    /// A statewide entry: the zip's state, with no city, county, or zip.
+    State,
+}
+
+/// Each record applying to `zip` (in `place`, when the zip is known), in
+/// sheet order, with the first way it matched.
+fn check_matches<'a>(
+    records: &'a [Value],
+    zip: &str,
+    place: Option<&geo::Place>,
+) -> Vec<(&'a Value, MatchedBy)> {
+    records
+        .iter()
+        .filter_map(|record| {
+            if geo::normalize_zip(field_str(record, "Zip")).as_deref() == Some(zip) {
+                return Some((record, MatchedBy::ExactZip));
+            }
+            let place = place?;
+            if !field_str(record, "State")
+                .trim()
+                .eq_ignore_ascii_case(place.state)
+            {
+                return None;
+            }
+            let (city, county) = (field_str(record, "City"), field_str(record, "County"));
+            let zip_blank = field_str(record, "Zip").trim().is_empty();
+            if !city.trim().is_empty() {
+                (geo::city_key(city) == geo::city_key(place.city)).then_some(MatchedBy::City)
+            } else if !county.trim().is_empty() {
+                canonical_county(county, true)
+                    .eq_ignore_ascii_case(&canonical_county(place.county, true))
+                    .then_some(MatchedBy::County)
+            } else {
+                zip_blank.then_some(MatchedBy::State)
+            }
+            .map(|matched| (record, matched))
+        })
+        .collect()
+}
+
+/// The verdict for one zip, shared by `/check`, `/nearby` and the page (which
+/// asks `/check`), so they never disagree.
+struct Verdict<'a> {
+    /// The records applying to the zip, in sheet order, as in [`check_matches`].
+    matches: Vec<(&'a Value, MatchedBy)>,
+    /// Whether any of them has `ban_status` `banned`.
+    banned: bool,
+    /// Whether one for the zip itself does.
+    zip_banned: bool,
+    message: String,
+}
+
+impl<'a> Verdict<'a> {
+    fn of(records: &'a [Value], zip: &str, place: Option<&geo::Place>) -> Self {
+        let matches = check_matches(records, zip, place);
+        let bans: Vec<MatchedBy> = matches
+            .iter()
+            .filter(|(r, _)| BanStatus::of_record(r) == BanStatus::Banned)
+            .map(|(_, matched)| *matched)
+            .collect();
+        let zip_banned = bans.contains(&MatchedBy::ExactZip);
+        let banned = !bans.is_empty();
+        let message = if !banned && !matches.is_empty() {
+            format!(
+                "⚠️ {} has no bans, but restricted, pending, or unconfirmed entries apply.",
+                zip
+            )
+        } else {
+            verdict_message(zip, &bans)
+        };
+        Verdict {
+            matches,
+            banned,
+            zip_banned,
+            message,
+        }
     }
-    .search-panel input[type="text"]:focus,
-    .search-panel select:focus {
-      box-shadow: 0 0 8px rgba(0,170,255,0.7);
-      outline: none;
+
+    /// How many of the bans applying to the zip are for its city, how many
+    /// for its county as a whole (no city given), and how many statewide.
+    fn area_bans(&self, place: &geo::Place) -> (usize, usize, usize) {
+        let bans = self
+            .matches
+            .iter()
+            .filter(|(r, _)| BanStatus::of_record(r) == BanStatus::Banned);
+        let (mut city, mut county, mut state) = (0, 0, 0);
+        for (record, matched) in bans {
+            let record_city = field_str(record, "City");
+            if *matched == MatchedBy::State {
+                state += 1;
+            } else if record_city.trim().is_empty() {
+                let record_county = canonical_county(field_str(record, "County"), true);
+                if record_county.eq_ignore_ascii_case(&canonical_county(place.county, true)) {
+                    county += 1;
+                }
+            } else if geo::city_key(record_city) == geo::city_key(place.city) {
+                city += 1;
+            }
+        }
+        (city, county, state)
     }
-    .search-panel button {
-      background: linear-gradient(135deg, #00aaff, #005fbb);
-      border: none;
-      border-radius: 5px;
-      padding: 10px 15px;
-      color: #fff;
-      font-size: 1em;
-      cursor: pointer;
-      box-shadow: 0 4px 6px rgba(0,0,0,0.2);
-      transition: transform 0.2s, box-shadow 0.2s, opacity 0.5s ease;
+}
+
+/// The zip in `params`, normalized, or the `400` for a malformed one.
+fn zip_param(params: &NearbyParams) -> Result<String, HttpResponse> {
+    let raw = params.zip.as_deref().unwrap_or("").trim();
+    geo::normalize_zip(raw).ok_or_else(|| {
+        errors::bad_request(format!(
+            "Invalid zip: expected a 5-digit zip code, got \"{}\"",
+            text::snippet(raw)
+        ))
+    })
+}
+
+/// Endpoint for a "check my area" flow: the verdict for one zip with how it
+/// was reached, so every client shows the same answer. `banned` counts only
+/// records whose `ban_status` is `banned`; restricted or pending entries are
+/// still listed. An unknown zip gets a negative verdict.
+#[get("/check")]
+async fn check_handler(
+    state: web::Data<AppState>,
+    params: web::Query<NearbyParams>,
+) -> impl Responder {
+    let zip = match zip_param(&params) {
+        Ok(zip) => zip,
+        Err(response) => return response,
+    };
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let place = geo::lookup(&zip);
+    let verdict = Verdict::of(&dataset.records, &zip, place);
+    let mut matched_by: Vec<MatchedBy> = verdict.matches.iter().map(|(_, m)| *m).collect();
+    matched_by.sort();
+    matched_by.dedup();
+    let records = dataset.served_all(verdict.matches.iter().map(|(r, _)| *r));
+    HttpResponse::Ok()
+        .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+        .json(json!({
+            "zip": zip,
+            "banned": verdict.banned,
+            "matched_by": matched_by,
+            "records": records,
+            "location": place.map(|p| json!({ "city": p.city, "county": p.county, "state": p.state })),
+            "message": verdict.message,
+            "disclaimer": "This information is not legal advice. Consult a lawyer for the most up-to-date information.",
+        }))
+}
+
+/// Endpoint answering "is my area banned?" for one zip: the `/check` verdict,
+/// with the bans for its city and county counted, and the nearest ban.
+#[get("/nearby")]
+async fn nearby_handler(
+    state: web::Data<AppState>,
+    params: web::Query<NearbyParams>,
+) -> impl Responder {
+    let zip = match zip_param(&params) {
+        Ok(zip) => zip,
+        Err(response) => return response,
+    };
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let place = geo::lookup(&zip);
+    let verdict = Verdict::of(&dataset.records, &zip, place);
+    let area_bans = place.map(|place| verdict.area_bans(place));
+    let (city_bans, county_bans, state_bans) = (
+        area_bans.map(|b| b.0),
+        area_bans.map(|b| b.1),
+        area_bans.map(|b| b.2),
+    );
+
+    let bans = || {
+        dataset
+            .records
+            .iter()
+            .filter(|r| BanStatus::of_record(r) == BanStatus::Banned)
+    };
+    let nearest = place.and_then(|place| {
+        bans()
+            .filter_map(|r| {
+                let point = geo::coordinates(field_str(r, "Zip"))?;
+                Some((geo::distance_mi(place.coordinates, point), r))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    });
+    let nearest = match nearest {
+        Some((distance, record)) => {
+            json!({ "record": dataset.served(record), "distance_mi": geo::round_mi(distance) })
+        }
+        None if verdict.zip_banned => json!({
+            "record": bans()
+                .find(|r| geo::normalize_zip(field_str(r, "Zip")).as_deref() == Some(zip.as_str()))
+                .map(|r| dataset.served(r)),
+            "distance_mi": null
+        }),
+        None => Value::Null,
+    };
+
+    HttpResponse::Ok()
+        .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+        .json(json!({
+            "zip": zip,
+            "banned": verdict.banned,
+            "zip_banned": verdict.zip_banned,
+            "location": place.map(|p| json!({ "city": p.city, "county": p.county, "state": p.state })),
+            "city_bans": city_bans,
+            "county_bans": county_bans,
+            "state_bans": state_bans,
+            "nearest": nearest,
+            "message": verdict.message,
+            "disclaimer": "This information is not legal advice. Consult a lawyer for the most up-to-date information.",
+        }))
+}
+
+/// Liveness probe: answers without touching the data or cache, so a stale or
+/// failing upstream never gets the process restarted. Readiness is `/health`.
+#[get("/ping")]
+async fn ping() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, NO_STORE))
+        .content_type("text/plain; charset=utf-8")
+        .body("pong")
+}
+
+/// Health check for probes and dashboards. Reports on whatever is already in
+/// memory and never triggers a fetch.
+#[get("/health")]
+async fn health(state: web::Data<AppState>) -> impl Responder {
+    let config = state.config();
+    let dataset = state.loaded_dataset();
+    let stale = dataset.as_ref().is_some_and(|d| state.is_degraded(d));
+    let status = match &dataset {
+        None => "starting",
+        Some(_) if stale => "degraded",
+        Some(_) => "ok",
+    };
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, NO_STORE))
+        .json(json!({
+        "status": status,
+        "ready": dataset.is_some(),
+        "stale": stale,
+        "ttl_secs": config.cache_ttl.as_secs(),
+        "degraded_after_secs": config.degraded_after().as_secs(),
+        "records": dataset.as_ref().map(|d| d.records.len()),
+        "data_age_secs": dataset.as_ref().map(|d| d.age().as_secs()),
+        "source": dataset.as_ref().map(|d| d.source.as_str()),
+        "refreshing": state.is_refreshing(),
+        "schema_drift": state.schema_drift(),
+        "rejected_refresh": state.rejected_refresh(),
+        "last_error": state.last_error(),
+        "cache_check": state.cache_check(),
+        }))
+}
+
+/// Counters and gauges as a flat JSON object, for dashboards and scrapers
+/// without Prometheus. Like `/health`, it never triggers a fetch.
+#[get("/metrics.json")]
+async fn metrics_json(state: web::Data<AppState>) -> impl Responder {
+    let dataset = state.loaded_dataset();
+    let mut body: serde_json::Map<String, Value> = state
+        .metrics()
+        .counters()
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), json!(value)))
+        .collect();
+    body.insert(
+        "records".into(),
+        json!(dataset.as_ref().map(|d| d.records.len())),
+    );
+    body.insert(
+        "cache_age_secs".into(),
+        json!(dataset.as_ref().map(|d| d.age().as_secs())),
+    );
+    body.insert("refreshing".into(), json!(state.is_refreshing()));
+    body.insert(
+        "in_flight_requests".into(),
+        json!(state.metrics().in_flight()),
+    );
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, NO_STORE))
+        .json(body)
+}
+
+/// Endpoint describing the on-disk cache and in-memory dataset. Read-only:
+/// it never triggers a fetch.
+#[get("/cache/info")]
+async fn cache_info(state: web::Data<AppState>) -> impl Responder {
+    let path = &state.config().cache_path();
+    let metadata = tokio::fs::metadata(path).await.ok();
+    let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+    let age_secs = modified
+        .and_then(|m| m.elapsed().ok())
+        .map(|age| age.as_secs());
+    // One entry per source the loaded records came from, with its freshness.
+    let sources: Vec<Value> = state
+        .loaded_dataset()
+        .map(|d| {
+            json!({
+                "name": d.source.as_str(),
+                "fetched_at": dates::format_rfc3339(d.fetched_at()),
+                "age_secs": d.age().as_secs(),
+                "record_count": d.records.len(),
+            })
+        })
+        .into_iter()
+        .collect();
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, NO_STORE))
+        .json(json!({
+        "path": path,
+        "last_modified": modified.map(|m| header::HttpDate::from(m).to_string()),
+        "age_secs": age_secs,
+        "size_bytes": metadata.map(|m| m.len()),
+        "record_count": state.loaded_dataset().map(|d| d.records.len()),
+        "search_cache_hits": state.loaded_dataset().map(|d| d.search_cache.hits()),
+        "response_cache_hits": state.loaded_dataset().map(|d| d.response_cache.hits()),
+        "refreshing": state.is_refreshing(),
+        "sources": sources,
+        }))
+}
+
+/// Whether two secrets are equal, taking the same time wherever they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The error response for a request to an admin endpoint (`/admin/*`,
+/// `/data/raw.csv`), or `None` if it carries `Authorization: Bearer
+/// <GKC_ADMIN_TOKEN>`. Without a token configured the admin endpoints don't exist.
+fn admin_rejection(req: &HttpRequest, config: &Config) -> Option<HttpResponse> {
+    let Some(token) = &config.admin_token else {
+        return Some(errors::not_found(
+            "Admin endpoints are disabled (GKC_ADMIN_TOKEN is unset)",
+        ));
+    };
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if constant_time_eq(presented.trim().as_bytes(), token.as_bytes()) {
+        return None;
     }
-    .search-panel button:hover {
-      transform: translateY(-2px);
-      box-shadow: 0 6px 8px rgba(0,0,0,0.3);
+    let mut response = errors::error_response(
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+        "Missing or wrong admin token",
+    );
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Bearer"),
+    );
+    Some(response)
+}
+
+/// Admin endpoint re-reading `GKC_CONFIG_FILE` and the environment and
+/// applying the result without a restart. Settings only read at startup keep
+/// their running values and are listed under `ignored`.
+#[post("/admin/reload-config")]
+async fn reload_config(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    let running = state.config();
+    if let Some(rejection) = admin_rejection(&req, &running) {
+        return rejection;
     }
-    
-    /* Disclaimer styling */
-    .disclaimer {
-      font-size: 0.75em;
-      margin: 10px 0;
-      color: #ccc;
-      padding: 10px;
-      border: 1px solid #555;
-      border-radius: 5px;
-      background: rgba(0,0,0,0.5);
-      opacity: 1;
-      transition: opacity 1s ease;
+    let mut fresh = match Config::load(running.config_file.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            return errors::error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "config_unreadable",
+                format!("Could not read GKC_CONFIG_FILE: {}", e),
+            );
+        }
+    };
+    let ignored = fresh.keep_startup_settings(&running);
+    state.replace_config(fresh);
+    if ignored.is_empty() {
+        println!("Configuration reloaded.");
+    } else {
+        println!(
+            "Configuration reloaded; these need a restart to change: {}",
+            ignored.join(", ")
+        );
     }
-    
-    /* Containers for results */
-    .results, .drilldown-container, .supplemental-container {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, NO_STORE))
+        .json(json!({ "reloaded": true, "ignored": ignored }))
+}
+
+/// Admin endpoint showing the configuration the server is running with, each
+/// setting marked as a default or as read from the environment or
+/// `GKC_CONFIG_FILE`, with secrets redacted. `--check-config` prints the same.
+#[get("/admin/config")]
+async fn admin_config(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    let config = state.config();
+    if let Some(rejection) = admin_rejection(&req, &config) {
+        return rejection;
+    }
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, NO_STORE))
+        .json(config.effective())
+}
+
+/// Admin endpoint serving the CSV exactly as last downloaded from the sheet
+/// (kept with `GKC_KEEP_RAW_CSV`), to check what the upstream returned.
+#[get("/data/raw.csv")]
+async fn raw_csv(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    let config = state.config();
+    if let Some(rejection) = admin_rejection(&req, &config) {
+        return rejection;
+    }
+    if !config.keep_raw_csv {
+        return errors::not_found("Raw CSV retention is off (GKC_KEEP_RAW_CSV is unset)");
+    }
+    match tokio::fs::read(cache::raw_csv_path(&config.cache_path())).await {
+        Ok(raw) => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((header::CACHE_CONTROL, NO_STORE))
+            .body(raw),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            errors::not_found("No raw CSV has been saved yet")
+        }
+        Err(e) => errors::error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "io_error",
+            format!("Could not read the raw CSV: {}", e),
+        ),
+    }
+}
+
+/// Endpoint listing the dates with an archived snapshot, oldest first.
+#[get("/snapshots")]
+async fn snapshot_dates(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(snapshot::list(&state.config().snapshot_dir()).await)
+}
+
+/// Endpoint comparing the current data with the snapshot archived on a date:
+/// the records added and removed since then.
+#[get("/diff/{date}")]
+async fn diff_handler(state: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let date = path.into_inner();
+    if dates::parse_iso_date(&date).as_deref() != Some(date.as_str()) {
+        return errors::bad_request(format!(
+            "Invalid date: expected YYYY-MM-DD, got \"{}\"",
+            text::snippet(&date)
+        ));
+    }
+    let old = match snapshot::load(&state.config().snapshot_dir(), &date).await {
+        Ok(Some(old)) => old,
+        Ok(None) => {
+            return errors::not_found(format!(
+                "No snapshot for {} (see /snapshots for the available dates)",
+                date
+            ));
+        }
+        Err(e) => return e.error_response(),
+    };
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let (added, removed) = snapshot::diff(&old, &dataset.records);
+    HttpResponse::Ok()
+        .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+        .json(json!({
+            "since": date,
+            "added": dataset.served_all(added),
+            "removed": dataset.served_all(removed),
+        }))
+}
+
+/// Endpoint reporting what the last parse of the sheet saw (delimiter, header
+/// row, rows skipped, ...), for debugging sheet changes. Never triggers a fetch.
+#[get("/parse-info")]
+async fn parse_info(state: web::Data<AppState>) -> impl Responder {
+    match state.parse_info() {
+        Some(info) => HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, NO_STORE))
+            .json(info),
+        None => errors::not_found(
+            "The sheet hasn't been parsed since startup (the data came from a cache or seed)",
+        ),
+    }
+}
+
+/// Endpoint describing the `/search` query syntax.
+#[get("/search/help")]
+async fn search_help() -> impl Responder {
+    HttpResponse::Ok().json(json!({
+        "fields": search::SEARCH_FIELDS,
+        "syntax": {
+            "term": "Matches records where any searchable field contains the term (case-insensitive).",
+            "term1 term2": "Whitespace-separated terms must all match (each may match a different field).",
+            "-term": "Excludes records where any searchable field contains the term.",
+        },
+        "parameters": {
+            "q": "The query, e.g. \"pinellas -clearwater\".",
+            "mode": "and (default) requires every positive term; or accepts any of them.",
+            "regex": "A case-insensitive regular expression used instead of q (at most 200 characters).",
+            "fields": "Comma-separated fields the regex is matched against (default: all).",
+            "highlight": "true wraps each result as {record, matches: [{field, start, end}]}, with character offsets into the field.",
+            "near": "A zip code; keeps records within radius_mi of it, nearest first, adding distance_mi.",
+            "radius_mi": "Search radius in miles for near (default 25).",
+            "debug_score": "true adds each result's relevance score (q searches only).",
+            "status": "Comma-separated ban_status values to keep: banned, restricted, pending, unknown.",
+        },
+        "examples": ["clearwater 33756", "pinellas -clearwater", "33701 33755&mode=or"],
+    }))
+}
+
+/// The query parameters of each endpoint that takes any: the fields of the
+/// structs its handler extracts, so the list can't drift from the handlers.
+fn endpoint_params() -> Vec<(&'static str, Vec<&'static str>)> {
+    use capabilities::fields_of;
+    let list = [
+        fields_of::<FilterParams>(),
+        fields_of::<SortParams>(),
+        fields_of::<EnvelopeParams>(),
+    ]
+    .concat();
+    vec![
+        ("/data", [&list, fields_of::<GroupParams>()].concat()),
+        ("/search", [fields_of::<SearchParams>(), &list].concat()),
+        ("/compare", fields_of::<CompareParams>().to_vec()),
+        (
+            "/zips",
+            [fields_of::<ZipsParams>(), fields_of::<EnvelopeParams>()].concat(),
+        ),
+        ("/random", fields_of::<RandomParams>().to_vec()),
+        (
+            "/recent",
+            [fields_of::<RecentParams>(), fields_of::<EnvelopeParams>()].concat(),
+        ),
+        ("/nearby", fields_of::<NearbyParams>().to_vec()),
+        ("/check", fields_of::<NearbyParams>().to_vec()),
+        (
+            "/counties",
+            [fields_of::<StateParams>(), fields_of::<EnvelopeParams>()].concat(),
+        ),
+        (
+            "/states",
+            [fields_of::<StatesParams>(), fields_of::<EnvelopeParams>()].concat(),
+        ),
+        (
+            "/cities",
+            [fields_of::<StateParams>(), fields_of::<EnvelopeParams>()].concat(),
+        ),
+        ("/tree", fields_of::<EnvelopeParams>().to_vec()),
+        ("/regions", fields_of::<EnvelopeParams>().to_vec()),
+        ("/supplemental", fields_of::<SupplementalParams>().to_vec()),
+    ]
+}
+
+/// Endpoint listing the query parameters each endpoint accepts, with types,
+/// defaults, and allowed values: a quick alternative to `/openapi.json`.
+#[get("/capabilities")]
+async fn capabilities_handler() -> impl Responder {
+    HttpResponse::Ok().json(capabilities::document(
+        &endpoint_params(),
+        capabilities::fields_of::<PrettyParams>(),
+    ))
+}
+
+/// Optional `state` parameter of the grouping endpoints.
+#[derive(Deserialize)]
+struct StateParams {
+    state: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CountyCount {
+    county: String,
+    count: usize,
+}
+
+/// Endpoint to list distinct counties (with record counts), optionally for one state.
+#[get("/counties")]
+async fn counties_handler(
+    state: web::Data<AppState>,
+    params: web::Query<StateParams>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    let config = state.config();
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let filters = FilterParams {
+        state: params.into_inner().state,
+        ..Default::default()
+    };
+    let filter = dataset.filter(&filters, &config);
+
+    let mut counts: std::collections::BTreeMap<String, usize> = Default::default();
+    for record in dataset.records.iter().filter(|r| filter.matches(r)) {
+        let county = canonical_county(field_str(record, "County"), config.normalize_county);
+        if !county.is_empty() {
+            *counts.entry(county).or_default() += 1;
+        }
+    }
+    let counties: Vec<CountyCount> = counts
+        .into_iter()
+        .map(|(county, count)| CountyCount { county, count })
+        .collect();
+    let total = counties.len();
+    list_response(HttpResponse::Ok(), counties, total, &dataset, &envelope)
+}
+
+#[derive(Deserialize)]
+struct StatesParams {
+    /// Group by country as well as state (for sheets that keep `Country`).
+    by_country: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct StateCount {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country: Option<String>,
+    state: String,
+    count: usize,
+}
+
+/// Endpoint to list the states with record counts, sorted by code (or by
+/// country and then code with `by_country=true`).
+#[get("/states")]
+async fn states_handler(
+    state: web::Data<AppState>,
+    params: web::Query<StatesParams>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let states: Vec<StateCount> = if params.by_country.unwrap_or(false) {
+        tree::country_state_counts(&dataset.records)
+            .into_iter()
+            .map(|((country, state), count)| StateCount {
+                country: Some(country),
+                state,
+                count,
+            })
+            .collect()
+    } else {
+        tree::state_counts(&dataset.records)
+            .into_iter()
+            .map(|(state, count)| StateCount {
+                country: None,
+                state,
+                count,
+            })
+            .collect()
+    };
+    let total = states.len();
+    list_response(HttpResponse::Ok(), states, total, &dataset, &envelope)
+}
+
+/// Endpoint for map visualizations: `{ "FL": 12, ... }` with an entry (zero
+/// if need be) for every state and DC, plus any other codes in the data.
+#[get("/choropleth")]
+async fn choropleth_handler(state: web::Data<AppState>) -> impl Responder {
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let mut counts = tree::state_counts(&dataset.records);
+    for code in tree::US_STATE_CODES {
+        counts.entry(code.to_string()).or_default();
+    }
+    HttpResponse::Ok().json(counts)
+}
+
+/// Endpoint grouping record counts by Census region, division, and state,
+/// from the bundled mapping. Only served with `GKC_REGIONS`.
+#[get("/regions")]
+async fn regions_handler(
+    state: web::Data<AppState>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    if !state.config().regions {
+        return errors::not_found("Region metadata is off (GKC_REGIONS is unset)");
+    }
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let regions = regions::group_by_region(&dataset.records);
+    let total = regions.len();
+    list_response(HttpResponse::Ok(), regions, total, &dataset, &envelope)
+}
+
+/// Endpoint to list the cities (with record counts and zips) for the
+/// drill-down view. County-wide bans appear as "All of X County" nodes
+/// flagged with `county_wide`.
+#[get("/cities")]
+async fn cities_handler(
+    state: web::Data<AppState>,
+    params: web::Query<StateParams>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    let config = state.config();
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let filters = FilterParams {
+        state: params.into_inner().state,
+        ..Default::default()
+    };
+    let filter = dataset.filter(&filters, &config);
+    let cities = tree::city_nodes(
+        dataset.records.iter().filter(|r| filter.matches(r)),
+        &config,
+    );
+    let total = cities.len();
+    list_response(HttpResponse::Ok(), cities, total, &dataset, &envelope)
+}
+
+/// Endpoint returning every record grouped by state and then city, with
+/// county-wide bans under "All of X County" nodes.
+#[get("/tree")]
+async fn tree_handler(
+    state: web::Data<AppState>,
+    envelope: web::Query<EnvelopeParams>,
+) -> impl Responder {
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let states = tree::state_tree(&dataset.records, &state.config());
+    let total = states.len();
+    list_response(HttpResponse::Ok(), states, total, &dataset, &envelope)
+}
+
+/// Endpoint to download every record as CSV, named after the data's date.
+#[get("/export.csv")]
+async fn export_csv(state: web::Data<AppState>) -> impl Responder {
+    let dataset = match state.dataset().await {
+        Ok(dataset) => dataset,
+        Err(e) => return e.error_response(),
+    };
+    let records = dataset.served_all(&dataset.records);
+    let columns: Vec<String> = dataset
+        .columns
+        .iter()
+        .map(|column| dataset.served_key(column).to_string())
+        .collect();
+    let csv = match export::records_to_csv(&records, &columns) {
+        Ok(csv) => csv,
+        Err(e) => {
+            return errors::error_response(StatusCode::INTERNAL_SERVER_ERROR, "export_failed", e);
+        }
+    };
+    let date = dates::format_rfc3339(dataset.fetched_at())[..10].replace('-', "");
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"gkc-bans-{}.csv\"", date),
+        ))
+        .insert_header((DATA_SOURCE_HEADER, dataset.source.as_str()))
+        .body(csv)
+}
+
+#[derive(Deserialize)]
+struct SupplementalParams {
+    /// Only return entries carrying this tag (case-insensitive).
+    tag: Option<String>,
+}
+
+/// Endpoint to return supplemental info (links, previews, tags) from JSON,
+/// optionally only the entries with one tag. The file is only re-read when
+/// its modification time changes.
+#[get("/supplemental")]
+async fn supplemental_handler(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    params: web::Query<SupplementalParams>,
+) -> impl Responder {
+    let config = state.config();
+    match state
+        .supplemental
+        .get(&config.supplemental_file, config.img_hosts.as_deref())
+        .await
+    {
+        Ok(supplemental) => {
+            let cache_control = data_cache_control(&config);
+            if let Some(tag) = params.tag.as_deref().filter(|t| !t.trim().is_empty()) {
+                return HttpResponse::Ok()
+                    .insert_header((header::CACHE_CONTROL, cache_control))
+                    .json(supplemental.with_tag(tag));
+            }
+            if etag_matches(&req, &supplemental.etag) {
+                return HttpResponse::NotModified()
+                    .insert_header((header::ETAG, supplemental.etag.clone()))
+                    .insert_header((header::CACHE_CONTROL, cache_control))
+                    .finish();
+            }
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .insert_header((header::ETAG, supplemental.etag.clone()))
+                .insert_header((header::CACHE_CONTROL, cache_control))
+                .body(supplemental.body.clone())
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Endpoint listing every supplemental tag with how many entries carry it.
+#[get("/supplemental/tags")]
+async fn supplemental_tags(state: web::Data<AppState>) -> impl Responder {
+    let config = state.config();
+    match state
+        .supplemental
+        .get(&config.supplemental_file, config.img_hosts.as_deref())
+        .await
+    {
+        Ok(supplemental) => HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, data_cache_control(&config)))
+            .json(supplemental.tag_counts()),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Crawler policy used unless `GKC_ROBOTS_FILE` provides one: the landing page
+/// stays indexable, the data API does not.
+const DEFAULT_ROBOTS_TXT: &str = "\
+User-agent: *
+Disallow: /data
+Disallow: /search
+Disallow: /nearby
+Disallow: /check
+Disallow: /counties
+Disallow: /cache/
+Disallow: /health
+Disallow: /metrics.json
+Disallow: /supplemental
+Disallow: /i18n
+Disallow: /openapi.json
+Disallow: /capabilities
+Allow: /
+";
+
+/// Endpoint serving the crawler policy, from `GKC_ROBOTS_FILE` when set.
+#[get("/robots.txt")]
+async fn robots_txt(state: web::Data<AppState>) -> impl Responder {
+    let config = state.config();
+    let policy = match &config.robots_file {
+        Some(path) => tokio::fs::read_to_string(path).await.unwrap_or_else(|e| {
+            println!("Error reading robots file {}: {}", path, e);
+            DEFAULT_ROBOTS_TXT.to_string()
+        }),
+        None => DEFAULT_ROBOTS_TXT.to_string(),
+    };
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(policy)
+}
+
+/// Whether `path` is part of the indexable site rather than the data API.
+fn is_indexable(path: &str) -> bool {
+    matches!(
+        path,
+        "/" | "/robots.txt" | "/favicon.ico" | "/manifest.webmanifest"
+    ) || path.starts_with("/icons/")
+        || path.starts_with("/static/")
+}
+
+/// Middleware adding `X-Robots-Tag: noindex` to API responses, so search
+/// engines skip them even when they ignore `robots.txt`.
+async fn robots_tag(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let indexable = is_indexable(req.path());
+    let mut response = next.call(req).await?;
+    if !indexable {
+        response.headers_mut().insert(
+            header::HeaderName::from_static("x-robots-tag"),
+            header::HeaderValue::from_static("noindex"),
+        );
+    }
+    Ok(response)
+}
+
+/// Middleware flagging responses built from degraded data (older than
+/// `GKC_MAX_STALE_AGE_SECS` while refreshes keep failing) with
+/// `X-Data-Stale: true` and a `Warning` saying how old it is. Only responses
+/// carrying `X-Data-Source`, i.e. built from the records, are flagged.
+async fn stale_data(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+    let mut response = next.call(req).await?;
+    if !response.headers().contains_key(DATA_SOURCE_HEADER) {
+        return Ok(response);
+    }
+    let Some(state) = state else {
+        return Ok(response);
+    };
+    let Some(dataset) = state.loaded_dataset() else {
+        return Ok(response);
+    };
+    if state.is_degraded(&dataset) {
+        let note = format!(
+            "110 - \"Data is {} hours old; the sheet could not be refreshed\"",
+            dataset.age().as_secs() / 3600
+        );
+        let headers = response.headers_mut();
+        headers.insert(
+            header::HeaderName::from_static("x-data-stale"),
+            header::HeaderValue::from_static("true"),
+        );
+        if let Ok(value) = header::HeaderValue::from_str(&note) {
+            headers.insert(header::WARNING, value);
+        }
+    }
+    Ok(response)
+}
+
+/// The warning logged for a request that took `elapsed`, or `None` if it was
+/// within `threshold` (a zero threshold disables the warning).
+fn slow_request_warning(
+    method: &str,
+    path: &str,
+    status: u16,
+    elapsed: Duration,
+    threshold: Duration,
+) -> Option<String> {
+    if threshold.is_zero() || elapsed < threshold {
+        return None;
+    }
+    Some(format!(
+        "Warning: slow request {} {} -> {} took {} ms (threshold {} ms)",
+        method,
+        path,
+        status,
+        elapsed.as_millis(),
+        threshold.as_millis()
+    ))
+}
+
+/// Middleware logging requests slower than `GKC_SLOW_MS`, e.g. a cold `/data`
+/// that had to fetch the sheet.
+async fn slow_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let threshold = req
+        .app_data::<web::Data<AppState>>()
+        .map_or(Duration::ZERO, |state| state.config().slow_request);
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    let started = Instant::now();
+    let response = next.call(req).await?;
+    if let Some(warning) = slow_request_warning(
+        &method,
+        &path,
+        response.status().as_u16(),
+        started.elapsed(),
+        threshold,
+    ) {
+        println!("{}", warning);
+    }
+    Ok(response)
+}
+
+/// The error response for a request over `GKC_MAX_QUERY_BYTES` (`414`) or
+/// declaring a body over `GKC_MAX_BODY_BYTES` (`413`), if it is.
+fn limit_rejection(req: &ServiceRequest, config: &Config) -> Option<HttpResponse> {
+    let over = |len: usize, max: usize| max > 0 && len > max;
+    if over(req.query_string().len(), config.max_query_bytes) {
+        return Some(errors::error_response(
+            StatusCode::URI_TOO_LONG,
+            "uri_too_long",
+            format!(
+                "Query string is longer than {} bytes",
+                config.max_query_bytes
+            ),
+        ));
+    }
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    if declared.is_some_and(|len| over(len, config.max_body_bytes)) {
+        return Some(payload_too_large(config.max_body_bytes));
+    }
+    None
+}
+
+fn payload_too_large(max_bytes: usize) -> HttpResponse {
+    errors::error_response(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "payload_too_large",
+        format!("Request body is larger than {} bytes", max_bytes),
+    )
+}
+
+/// Middleware turning away over-long query strings and bodies before any
+/// handler (or extractor) reads them.
+async fn request_limits(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let rejection = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| limit_rejection(&req, &state.config()));
+    if let Some(response) = rejection {
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Body limits for the JSON and raw payload extractors, for bodies sent
+/// without a `Content-Length` (which `request_limits` can't check up front).
+fn body_limits(cfg: &mut web::ServiceConfig, max_bytes: usize) {
+    let limit = if max_bytes == 0 {
+        usize::MAX
+    } else {
+        max_bytes
+    };
+    let json_config = web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(move |e, _| {
+            let response = match e {
+                error::JsonPayloadError::Overflow { .. }
+                | error::JsonPayloadError::OverflowKnownLength { .. } => {
+                    payload_too_large(max_bytes)
+                }
+                _ => errors::bad_request(&e),
+            };
+            error::InternalError::from_response(e, response).into()
+        });
+    cfg.app_data(json_config)
+        .app_data(web::PayloadConfig::default().limit(limit));
+}
+
+#[derive(Deserialize)]
+struct PrettyParams {
+    pretty: Option<bool>,
+}
+
+/// Middleware re-serializing JSON responses with indentation when `?pretty=true`
+/// (or `GKC_PRETTY_JSON`) asks for it. Otherwise the compact bodies, including
+/// the one `/data` serializes once per refresh, pass through untouched.
+async fn pretty_json(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody, Vec<u8>>>, actix_web::Error> {
+    let default = req
+        .app_data::<web::Data<AppState>>()
+        .is_some_and(|state| state.config().pretty_json);
+    let pretty = web::Query::<PrettyParams>::from_query(req.query_string())
+        .ok()
+        .and_then(|params| params.pretty)
+        .unwrap_or(default);
+    let response = next.call(req).await?;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    if !pretty || !is_json {
+        return Ok(response.map_into_left_body());
+    }
+    let (req, response) = response.into_parts();
+    let (mut response, body) = response.into_parts();
+    let bytes = actix_web::body::to_bytes(body).await.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e.into();
+        actix_web::error::ErrorInternalServerError(e.to_string())
+    })?;
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) => serde_json::to_vec_pretty(&value)?,
+        Err(_) => bytes.to_vec(),
+    };
+    // The ETag describes the compact bytes.
+    response.headers_mut().remove(header::ETAG);
+    Ok(ServiceResponse::new(req, response.set_body(body)).map_into_right_body())
+}
+
+/// Favicon matching the dark UI, embedded in the binary.
+const FAVICON: &[u8] = include_bytes!("../assets/favicon.ico");
+
+/// Endpoint to serve the favicon with a long cache lifetime.
+#[get("/favicon.ico")]
+async fn favicon() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("image/x-icon")
+        .insert_header((header::CACHE_CONTROL, "public, max-age=604800, immutable"))
+        .body(FAVICON)
+}
+
+/// App icons referenced by the web app manifest.
+const ICON_192: &[u8] = include_bytes!("../assets/icon-192.png");
+const ICON_512: &[u8] = include_bytes!("../assets/icon-512.png");
+
+/// Endpoint to serve the app icons used when the page is installed.
+#[get("/icons/{name}")]
+async fn icon(name: web::Path<String>) -> impl Responder {
+    let bytes = match name.as_str() {
+        "icon-192.png" => ICON_192,
+        "icon-512.png" => ICON_512,
+        _ => return errors::not_found(format!("No icon named \"{}\"", text::snippet(&name))),
+    };
+    HttpResponse::Ok()
+        .content_type("image/png")
+        .insert_header((header::CACHE_CONTROL, "public, max-age=604800, immutable"))
+        .body(bytes)
+}
+
+/// Mount the front-end assets in `dir` under `/static/`, with content types
+/// guessed from the file extension and `ETag`/`Last-Modified` revalidation.
+/// Without a directory only the built-in page and icons are served.
+fn static_files(cfg: &mut web::ServiceConfig, dir: Option<&str>) {
+    let Some(dir) = dir else { return };
+    let files = actix_files::Files::new("", dir)
+        .use_etag(true)
+        .use_last_modified(true)
+        .default_handler(actix_web::dev::fn_service(|req: ServiceRequest| async {
+            let response = errors::not_found(format!("No static file \"{}\"", req.path()));
+            Ok(req.into_response(response))
+        }));
+    cfg.service(
+        web::scope("/static")
+            .wrap(from_fn(static_cache_control))
+            .service(files),
+    );
+}
+
+/// Middleware giving successful `/static/` responses an hour's
+/// `Cache-Control`; after that, clients revalidate with the `ETag`.
+async fn static_cache_control(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let mut response = next.call(req).await?;
+    if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static("public, max-age=3600"),
+        );
+    }
+    Ok(response)
+}
+
+/// Endpoint to serve the web app manifest so the page can be installed as a PWA.
+#[get("/manifest.webmanifest")]
+async fn manifest() -> impl Responder {
+    let manifest = json!({
+        "name": "GKC Kratom Bans",
+        "short_name": "Kratom Bans",
+        "description": "Search banned kratom areas by state, city, county, or zip.",
+        "start_url": "/",
+        "scope": "/",
+        "display": "standalone",
+        "background_color": "#1e1e2f",
+        "theme_color": "#27293d",
+        "icons": [
+            { "src": "/icons/icon-192.png", "sizes": "192x192", "type": "image/png", "purpose": "any maskable" },
+            { "src": "/icons/icon-512.png", "sizes": "512x512", "type": "image/png", "purpose": "any maskable" }
+        ]
+    });
+    HttpResponse::Ok()
+        .content_type("application/manifest+json")
+        .body(manifest.to_string())
+}
+
+/// Endpoint serving the OpenAPI 3 description of this API.
+#[get("/openapi.json")]
+async fn openapi_handler() -> impl Responder {
+    HttpResponse::Ok().json(openapi::spec())
+}
+
+/// Endpoint exposing the localized UI strings so other clients can reuse them.
+#[get("/i18n")]
+async fn i18n_handler() -> impl Responder {
+    let strings: serde_json::Map<String, Value> = i18n::LANGUAGES
+        .iter()
+        .map(|s| (s.lang.to_string(), json!(s)))
+        .collect();
+    HttpResponse::Ok().json(strings)
+}
+
+/// The root endpoint (/) serves the complete HTML/JS/CSS page, localized
+/// according to the request's Accept-Language header. An `index.html` in
+/// `GKC_STATIC_DIR` at startup replaces the built-in page.
+#[get("/")]
+async fn index(req: HttpRequest, state: web::Data<AppState>) -> impl Responder {
+    let strings = i18n::negotiate(
+        req.headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok()),
+    );
+    let builtin = r##"<!DOCTYPE html>
+<html lang="{{lang}}">
+<head>
+  <meta charset="UTF-8">
+  <title>GKC Kratom Bans 🌌</title>
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <meta name="theme-color" content="#27293d">
+  <link rel="icon" href="/favicon.ico">
+  <link rel="manifest" href="/manifest.webmanifest">
+  <link rel="apple-touch-icon" href="/icons/icon-192.png">
+  <style>
+    /* Global reset and smooth transitions */
+    * { box-sizing: border-box; margin: 0; padding: 0; }
+    
+    /* Subtle animated background */
+    @keyframes backgroundAnimation {
+      0% { background-position: 0% 50%; }
+      50% { background-position: 100% 50%; }
+      100% { background-position: 0% 50%; }
+    }
+    body {
+      min-height: 100vh;
+      font-family: 'Roboto', sans-serif;
+      color: #e0e0e0;
+      background: linear-gradient(135deg, #1e1e2f, #2e2e48);
+      background-size: 200% 200%;
+      animation: backgroundAnimation 20s ease infinite;
+      transition: background 0.5s ease;
+      display: flex;
+      flex-direction: column;
+    }
+    
+    /* Global link styles: new default and visited colors remain the same */
+    a {
+      color: #66ccff;
+    }
+    a:visited {
+      color: #66ccff;
+    }
+    
+    header {
+      background: linear-gradient(135deg, #27293d, #1e1e2f);
+      padding: 20px;
+      text-align: center;
+      font-size: 1.8em;
+      font-weight: bold;
+      box-shadow: 0 2px 4px rgba(0,0,0,0.3);
+      transition: opacity 0.5s ease;
+      /* Subtle pulsing animation */
+      animation: pulse 3s ease-in-out infinite;
+    }
+    @keyframes pulse {
+      0% { transform: scale(1); }
+      50% { transform: scale(1.02); }
+      100% { transform: scale(1); }
+    }
+    
+    main {
+      flex: 1;
+      padding: 20px;
+      max-width: 1200px;
+      width: 100%;
+      margin: 0 auto;
+    }
+    
+    /* Search panel styling */
+    .search-panel {
+      display: flex;
+      flex-wrap: wrap;
+      gap: 10px;
+      justify-content: center;
+      margin-bottom: 10px;
+      transition: opacity 0.5s ease;
+    }
+    .search-panel input[type="text"],
+    .search-panel select {
+      padding: 10px;
+      border-radius: 5px;
+      border: 1px solid #444;
+      background: rgba(44, 47, 58, 0.9);
+      color: #e0e0e0;
+      font-size: 1em;
+      min-width: 200px;
+      transition: box-shadow 0.2s ease, opacity 0.5s ease;
+    }
+    .search-panel input[type="text"]:focus,
+    .search-panel select:focus {
+      box-shadow: 0 0 8px rgba(0,170,255,0.7);
+      outline: none;
+    }
+    .search-panel button {
+      background: linear-gradient(135deg, #00aaff, #005fbb);
+      border: none;
+      border-radius: 5px;
+      padding: 10px 15px;
+      color: #fff;
+      font-size: 1em;
+      cursor: pointer;
+      box-shadow: 0 4px 6px rgba(0,0,0,0.2);
+      transition: transform 0.2s, box-shadow 0.2s, opacity 0.5s ease;
+    }
+    .search-panel button:hover {
+      transform: translateY(-2px);
+      box-shadow: 0 6px 8px rgba(0,0,0,0.3);
+    }
+    
+    /* Disclaimer styling */
+    .disclaimer {
+      font-size: 0.75em;
+      margin: 10px 0;
+      color: #ccc;
+      padding: 10px;
+      border: 1px solid #555;
+      border-radius: 5px;
+      background: rgba(0,0,0,0.5);
+      opacity: 1;
+      transition: opacity 1s ease;
+    }
+    
+    /* Containers for results */
+    .results, .drilldown-container, .supplemental-container {
       margin-top: 20px;
       padding: 10px;
       border-radius: 8px;
@@ -353,7 +2226,7 @@ async fn index() -> impl Responder {
   </style>
 </head>
 <body>
-  <header>GKC Kratom Bans 🌌</header>
+  <header>{{header}}</header>
   <main>
     <!-- Search panel -->
     <div class="search-panel">
@@ -366,7 +2239,7 @@ async fn index() -> impl Responder {
     
     <!-- Disclaimer now appears right under the inputs -->
     <div id="disclaimer-text" class="disclaimer">
-      <p>This service is provided for entertainment purposes only and is not a substitute for legal advice. Please consult a lawyer for the most up-to-date legal information.</p>
+      <p>{{disclaimer}}</p>
     </div>
     
     <!-- Banned results (drill-down & success messages) -->
@@ -437,17 +2310,8 @@ async fn index() -> impl Responder {
         updateResults();
         return;
       }
-      // Check if query is a zip code (5 digits) that uniquely belongs to one state.
-      if(query.length === 5 && /^\d{5}$/.test(query)) {
-        const matches = bannedData.filter(item => item.Zip === query);
-        const uniqueStates = [...new Set(matches.map(item => item.State))];
-        if(uniqueStates.length === 1) {
-          document.getElementById('state-dropdown').value = uniqueStates[0];
-          searchInputElem.value = "";
-          updateResults();
-          return;
-        }
-      }
+      // A zip code (5 digits) stays in the box: it gets the server's verdict.
+      if(/^\d{5}$/.test(query)) return;
       // Check if query exactly matches a city name that belongs to one state.
       const cityMatches = bannedData.filter(item => item.City && item.City.toLowerCase() === query.toLowerCase());
       const uniqueCityStates = [...new Set(cityMatches.map(item => item.State))];
@@ -512,11 +2376,16 @@ async fn index() -> impl Responder {
 
       const resultsSummary = document.getElementById('results-summary');
       
-      if (filteredData.length > 0) {
-        // Show banned area results.
+      if (/^\d{5}$/.test(searchQuery)) {
+        showZipVerdict(searchQuery);
+        if (filteredData.length > 0) renderDrillDown();
+      } else if (filteredData.length > 0) {
+        // Show banned area results; rows without a ban_status are bans.
+        const bans = filteredData.filter(item => (item.ban_status || 'banned') === 'banned').length;
+        const others = filteredData.length - bans;
         resultsSummary.innerHTML = `
           <div class="card">
-            <p><strong>${filteredData.length}</strong> banned area${filteredData.length > 1 ? 's' : ''} found.</p>
+            <p><strong>${bans}</strong> banned area${bans !== 1 ? 's' : ''} found${others > 0 ? `, plus <strong>${others}</strong> restricted, pending, or unconfirmed` : ''}.</p>
           </div>`;
         resultsSummary.style.display = 'block';
         renderDrillDown();
@@ -542,6 +2411,29 @@ async fn index() -> impl Responder {
       }
     }
 
+    // A zip's verdict comes from /check, so the page words it exactly as the
+    // API does (city, county and statewide entries, ban status).
+    async function showZipVerdict(zip) {
+      try {
+        const response = await fetch('/check?zip=' + encodeURIComponent(zip));
+        const verdict = await response.json();
+        // Ignore answers for a zip that is no longer in the box.
+        if (document.getElementById('search-input').value.trim() !== zip) return;
+        const card = document.createElement('div');
+        card.className = verdict.banned ? 'card' : 'card success';
+        const message = document.createElement('p');
+        message.textContent = verdict.message;
+        const disclaimer = document.createElement('p');
+        disclaimer.textContent = 'Please note: ' + verdict.disclaimer;
+        card.append(message, disclaimer);
+        const resultsSummary = document.getElementById('results-summary');
+        resultsSummary.replaceChildren(card);
+        resultsSummary.style.display = 'block';
+      } catch (error) {
+        console.error('Error checking zip:', error);
+      }
+    }
+
     function renderDrillDown() {
       const container = document.getElementById('drilldown-container');
       container.innerHTML = '';
@@ -573,7 +2465,10 @@ async fn index() -> impl Responder {
         if (currentDrillLevel === 'state') {
           li.textContent = key + ' (' + grouping[key].length + ' Banned Zip Code' + (grouping[key].length > 1 ? 's' : '') + ')';
         } else if (currentDrillLevel === 'city') {
-          li.innerHTML = key + ' <span class="flashing">❌</span>';
+          const statuses = grouping[key].map(item => item.ban_status || 'banned');
+          li.innerHTML = statuses.includes('banned')
+            ? key + ' <span class="flashing">❌</span>'
+            : key + ' ⚠️ ' + statuses[0];
         }
         li.onclick = () => {
           if (currentDrillLevel === 'state') {
@@ -680,23 +2575,3422 @@ async fn index() -> impl Responder {
   </script>
 </body>
 </html>
-"#;
-    HttpResponse::Ok().content_type("text/html").body(html)
+"##;
+    let config = state.config();
+    let html = state
+        .custom_index
+        .as_deref()
+        .unwrap_or(builtin)
+        .replace("{{lang}}", strings.lang)
+        .replace("{{header}}", strings.header)
+        .replace("{{disclaimer}}", strings.disclaimer);
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("text/html")
+        .insert_header((header::CONTENT_LANGUAGE, strings.lang))
+        .insert_header((header::VARY, "Accept-Language"));
+    if let Some(csp) = content_security_policy(&config) {
+        response.insert_header((header::CONTENT_SECURITY_POLICY, csp));
+    }
+    response.body(html)
 }
 
-// ---------------------------------------------------------------------------
+/// The page's `Content-Security-Policy` when `GKC_IMG_HOSTS` is set: images
+/// only from this server and the allowed preview hosts.
+fn content_security_policy(config: &Config) -> Option<String> {
+    let hosts = config.img_hosts.as_ref()?;
+    let sources: String = hosts.iter().map(|host| format!(" {}", host)).collect();
+    Some(format!("img-src 'self'{}", sources))
+}
+
+/// Register every route; shared by the server and the integration tests.
+fn routes(cfg: &mut web::ServiceConfig) {
+    // Unparseable query strings (e.g. `seed=abc`) get the JSON error body too.
+    let query_config = web::QueryConfig::default().error_handler(|e, _| {
+        let response = errors::bad_request(&e);
+        actix_web::error::InternalError::from_response(e, response).into()
+    });
+    cfg.app_data(query_config)
+        .service(index)
+        .service(i18n_handler)
+        .service(robots_txt)
+        .service(favicon)
+        .service(icon)
+        .service(manifest)
+        .service(data_handler)
+        .service(search_handler)
+        .service(search_help)
+        .service(capabilities_handler)
+        .service(by_zip_handler)
+        .service(compare_handler)
+        .service(compare_post_handler)
+        .service(zips_handler)
+        .service(random_handler)
+        .service(recent_handler)
+        .service(nearby_handler)
+        .service(check_handler)
+        .service(counties_handler)
+        .service(states_handler)
+        .service(choropleth_handler)
+        .service(regions_handler)
+        .service(cities_handler)
+        .service(tree_handler)
+        .service(export_csv)
+        .service(ping)
+        .service(health)
+        .service(metrics_json)
+        .service(cache_info)
+        .service(reload_config)
+        .service(admin_config)
+        .service(raw_csv)
+        .service(parse_info)
+        .service(snapshot_dates)
+        .service(diff_handler)
+        .service(openapi_handler)
+        .service(supplemental_tags)
+        .service(supplemental_handler);
+}
+
+/// Resolves on the first SIGINT (Ctrl-C) or, on Unix, SIGTERM: the signals
+/// Actix also listens for to start a graceful shutdown.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// How often shutdown checks whether the in-flight requests have drained.
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+
+/// Once shutdown starts, log how many requests are draining and, if any are
+/// still running when `timeout` expires, how many get cut off.
+fn watch_shutdown(state: web::Data<AppState>, timeout: Duration) {
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        let deadline = Instant::now() + timeout;
+        let mut remaining = state.metrics().in_flight();
+        println!(
+            "Shutting down; waiting up to {}s for {} in-flight request(s).",
+            timeout.as_secs(),
+            remaining
+        );
+        // Take the last count just before the deadline: at the deadline the
+        // server drops the remaining requests, and with them their count.
+        while remaining > 0 && deadline.saturating_duration_since(Instant::now()) > DRAIN_POLL {
+            actix_web::rt::time::sleep(DRAIN_POLL).await;
+            remaining = state.metrics().in_flight();
+        }
+        if remaining > 0 {
+            println!(
+                "Warning: shutdown timeout reached; closing {} request(s) still in flight.",
+                remaining
+            );
+        }
+    });
+}
+
+/// A listening socket on `addr`. An IPv6 socket is made IPv6-only when an
+/// IPv4 address on the same port is bound too (`0.0.0.0:7001,[::]:7001`), so
+/// the two don't collide; on its own, `[::]` keeps the system's dual-stack default.
+fn listen_on(addr: SocketAddr, all: &[SocketAddr]) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && all.iter().any(|a| a.is_ipv4() && a.port() == addr.port()) {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+// ---------------------------------------------------------------------------
 // Main: start the Actix Web server.
 // ---------------------------------------------------------------------------
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("Starting server at http://localhost:7001/");
-    HttpServer::new(|| {
+    // Dry run: show the settings this instance would start with, then exit.
+    if std::env::args().skip(1).any(|arg| arg == "--check-config") {
+        let settings = Config::from_env().effective();
+        println!("{}", serde_json::to_string_pretty(&settings)?);
+        return Ok(());
+    }
+    let state = web::Data::new(AppState::new(Config::from_env()));
+    if state.config().background_load {
+        state.start_background_load();
+    }
+    let config = state.config();
+    if !config.cache_check_interval.is_zero() && !config.offline {
+        state.start_cache_checks(config.cache_check_interval);
+    }
+    match &config.refresh_cron {
+        Some(cron) if !config.offline => {
+            state.start_scheduled_refreshes(cron.clone());
+            match schedule::next_after(cron, std::time::SystemTime::now()) {
+                Some(next) => println!(
+                    "Refreshing on the schedule {:?}; next at {}.",
+                    cron.source(),
+                    dates::format_rfc3339(next)
+                ),
+                None => println!(
+                    "Warning: the refresh schedule {:?} has no future times.",
+                    cron.source()
+                ),
+            }
+        }
+        _ => {}
+    }
+    if !config.offline {
+        println!(
+            "Fetching the sheet {}.",
+            sheet::describe_proxy(config.proxy.as_deref())
+        );
+    }
+    let listeners = config
+        .bind
+        .iter()
+        .map(|&addr| {
+            let listener = listen_on(addr, &config.bind).map_err(|e| {
+                std::io::Error::new(e.kind(), format!("Could not listen on {}: {}", addr, e))
+            })?;
+            println!("Listening on http://{}/", listener.local_addr()?);
+            Ok(listener)
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let http2 = config.http2;
+    let max_body_bytes = config.max_body_bytes;
+    let shutdown_timeout = config.shutdown_timeout;
+    let static_dir = config.static_dir.clone().filter(|dir| {
+        let found = std::path::Path::new(dir).is_dir();
+        if !found {
+            println!(
+                "Static directory {} not found; serving the built-in page only.",
+                dir
+            );
+        }
+        found
+    });
+    watch_shutdown(state.clone(), shutdown_timeout);
+    let mut server = HttpServer::new(move || {
         App::new()
-            .service(index)
-            .service(data_handler)
-            .service(supplemental_handler)
+            .app_data(state.clone())
+            .configure(|cfg| body_limits(cfg, max_body_bytes))
+            .configure(|cfg| static_files(cfg, static_dir.as_deref()))
+            .wrap(from_fn(response_cache::response_cache))
+            .wrap(from_fn(request_limits))
+            .wrap(from_fn(rate_limit::rate_limit))
+            .wrap(from_fn(robots_tag))
+            .wrap(from_fn(stale_data))
+            .wrap(from_fn(slow_requests))
+            .wrap(from_fn(pretty_json))
+            .wrap(from_fn(metrics::count_requests))
+            // Outermost, so every route and middleware sees `/data`, not `/data/`.
+            .wrap(NormalizePath::trim())
+            .configure(routes)
     })
-    .bind(("127.0.0.1", 7001))?
-    .run()
-    .await
+    // In-flight requests get this long to finish once shutdown starts.
+    .shutdown_timeout(shutdown_timeout.as_secs());
+    for listener in listeners {
+        // h2c is detected per connection, so HTTP/1.1 clients keep working.
+        server = if http2 {
+            server.listen_auto_h2c(listener)?
+        } else {
+            server.listen(listener)?
+        };
+    }
+    server.run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use sheet::FakeSheetSource;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SHEET_CSV: &str = "\
+Kratom bans,,,,
+Country,Zip,City,County,State
+US,33701,St. Petersburg,Pinellas,FL
+US,01850,Lowell,Middlesex,MA
+";
+
+    /// A fresh, empty scratch directory so tests never touch the real cache files.
+    fn scratch_dir() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "gkcsearch-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// App state backed by the fake sheet, with cache and supplemental files in `dir`.
+    fn test_state(dir: &std::path::Path) -> web::Data<AppState> {
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            supplemental_file: dir.join("supplemental.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        web::Data::new(AppState::with_source(config, Box::new(source)))
+    }
+
+    macro_rules! test_app {
+        ($state:expr) => {{
+            let state = $state.clone();
+            let max_body_bytes = state.config().max_body_bytes;
+            let static_dir = state.config().static_dir.clone();
+            test::init_service(
+                App::new()
+                    .app_data(state.clone())
+                    .configure(|cfg| body_limits(cfg, max_body_bytes))
+                    .configure(|cfg| static_files(cfg, static_dir.as_deref()))
+                    .wrap(from_fn(response_cache::response_cache))
+                    .wrap(from_fn(request_limits))
+                    .wrap(from_fn(rate_limit::rate_limit))
+                    .wrap(from_fn(robots_tag))
+                    .wrap(from_fn(stale_data))
+                    .wrap(from_fn(slow_requests))
+                    .wrap(from_fn(pretty_json))
+                    .wrap(from_fn(metrics::count_requests))
+                    .wrap(NormalizePath::trim())
+                    .configure(routes),
+            )
+            .await
+        }};
+    }
+
+    fn content_type<B>(resp: &ServiceResponse<B>) -> String {
+        resp.headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string()
+    }
+
+    #[actix_web::test]
+    async fn data_returns_parsed_records_as_json() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(content_type(&resp), "application/json");
+        let body: Value = test::read_body_json(resp).await;
+        let mut expected = vec![
+            json!({"Zip": "33701", "City": "St. Petersburg", "County": "Pinellas", "State": "FL"}),
+            json!({"Zip": "01850", "City": "Lowell", "County": "Middlesex", "State": "MA"}),
+        ];
+        ids::assign_ids(&mut expected);
+        assert_eq!(body, json!(expected));
+        // The fetch was written through to the configured cache file.
+        assert!(dir.join("data_cache.json").exists());
+    }
+
+    #[actix_web::test]
+    async fn dropped_columns_never_reach_the_endpoints() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let csv = ",Zip,Country,City,County,State\nrow 1,33701,US,St. Petersburg,Pinellas,FL\n";
+        let source = FakeSheetSource(csv.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        for uri in ["/data", "/search?q=petersburg", "/data/by-zip/33701"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+            let record = &body.get("records").unwrap_or(&body)[0];
+            assert_eq!(record["Zip"], "33701", "{}", uri);
+            assert!(record.get("Country").is_none(), "{}", uri);
+            assert!(record.get("column_0").is_none(), "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn trailing_slashes_reach_the_same_routes() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        for (uri, canonical) in [
+            ("/data/", "/data"),
+            ("/data/by-zip/01850/", "/data/by-zip/01850"),
+            ("/search//?q=lowell", "/search?q=lowell"),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 200, "{}", uri);
+            let body = test::read_body(resp).await;
+            let req = test::TestRequest::get().uri(canonical).to_request();
+            let expected = test::read_body(test::call_service(&app, req).await).await;
+            assert_eq!(body, expected, "{}", uri);
+        }
+        let req = test::TestRequest::get().uri("/").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn data_body_matches_live_serialization_and_honors_etag() {
+        let dir = scratch_dir();
+        let state = test_state(&dir);
+        let app = test_app!(state);
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        let body = test::read_body(resp).await;
+        let dataset = state.dataset().await.unwrap();
+        assert_eq!(body, serde_json::to_vec(&dataset.records).unwrap());
+
+        let req = test::TestRequest::get()
+            .uri("/data")
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 304);
+    }
+
+    #[actix_web::test]
+    async fn cache_control_depends_on_the_endpoint() {
+        let dir = scratch_dir();
+        std::fs::write(dir.join("supplemental.json"), "[]").unwrap();
+        let app = test_app!(test_state(&dir));
+        let cache_control = |resp: &ServiceResponse<_>| {
+            resp.headers()
+                .get(header::CACHE_CONTROL)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        for uri in ["/data", "/data?state=fl", "/supplemental"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(
+                cache_control(&resp).as_deref(),
+                Some("public, max-age=300, stale-while-revalidate=3600"),
+                "{}",
+                uri
+            );
+        }
+        for uri in ["/health", "/ping", "/cache/info"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(cache_control(&resp).as_deref(), Some("no-store"), "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn state_filter_accepts_several_states() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let csv = "Country,Zip,City,County,State\n\
+                   US,33701,St. Petersburg,Pinellas,FL\n\
+                   US,01850,Lowell,Middlesex,MA\n\
+                   US,36602,Mobile,Mobile,AL\n\
+                   US,39530,Biloxi,Harrison,MS\n";
+        let source = FakeSheetSource(csv.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        for uri in ["/data?state=fl,AL,ms,ZZ", "/search?q=&state=fl,AL,ms,ZZ"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(resp.headers().get(IGNORED_STATES_HEADER).unwrap(), "ZZ");
+            let body: Value = test::read_body_json(resp).await;
+            let states: Vec<&str> = body
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["State"].as_str().unwrap())
+                .collect();
+            assert_eq!(states, ["FL", "AL", "MS"], "{}", uri);
+        }
+
+        let req = test::TestRequest::get().uri("/data?state=ZZ").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body, json!([]));
+    }
+
+    #[actix_web::test]
+    async fn data_groups_records_by_state_or_city() {
+        let dir = scratch_dir();
+        let csv = format!("{}US,,,,fl\n", SHEET_CSV);
+        let state = web::Data::new(AppState::with_source(
+            Config {
+                cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+                ..Config::default()
+            },
+            Box::new(FakeSheetSource(csv)),
+        ));
+        let app = test_app!(state);
+        let get = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let req = test::TestRequest::get().uri(uri).to_request();
+                test::call_service(app, req).await
+            }
+        };
+
+        let body: Value = test::read_body_json(get("/data?group_by=state").await).await;
+        let groups = body.as_object().unwrap();
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["FL", "MA"]);
+        assert_eq!(groups["FL"].as_array().unwrap().len(), 2);
+        assert_eq!(groups["MA"][0]["City"], "Lowell");
+
+        // Blank cities are left out; filters apply first.
+        let body: Value =
+            test::read_body_json(get("/data?group_by=City&state=FL&envelope=true").await).await;
+        assert_eq!(body["meta"]["total"], 2);
+        let groups = body["data"].as_object().unwrap();
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["St. Petersburg"]);
+
+        // The flat array stays the default.
+        let body: Value = test::read_body_json(get("/data").await).await;
+        assert!(body.is_array());
+        assert_eq!(get("/data?group_by=county").await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn data_filters_and_rejects_bad_params() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get().uri("/data?state=ma").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["City"], "Lowell");
+
+        let req = test::TestRequest::get()
+            .uri("/data?sort=bogus")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn search_filters_by_ban_status() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let source = FakeSheetSource(
+            "Country,Zip,City,County,State,Status\n\
+             US,33701,St. Petersburg,Pinellas,FL,Banned\n\
+             US,01850,Lowell,Middlesex,MA,Proposed ban\n"
+                .to_string(),
+        );
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get()
+            .uri("/search?status=banned")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["City"], "St. Petersburg");
+        assert_eq!(body[0]["ban_status"], "banned");
+
+        let req = test::TestRequest::get()
+            .uri("/search?status=pending")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body[0]["Status"], "Proposed ban");
+        assert_eq!(body[0]["ban_status"], "pending");
+
+        let req = test::TestRequest::get()
+            .uri("/search?status=outlawed")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn list_responses_are_capped_and_flagged() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            max_records: 1,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        for uri in ["/data", "/search"] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(
+                resp.headers().get(TRUNCATED_HEADER).unwrap(),
+                "true",
+                "{}",
+                uri
+            );
+            assert_eq!(
+                resp.headers().get(TOTAL_COUNT_HEADER).unwrap(),
+                "2",
+                "{}",
+                uri
+            );
+            assert!(resp.headers().contains_key(header::WARNING));
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body.as_array().unwrap().len(), 1, "{}", uri);
+        }
+
+        // Under the cap nothing is flagged.
+        let req = test::TestRequest::get().uri("/data?state=fl").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.headers().contains_key(TRUNCATED_HEADER));
+    }
+
+    /// A sheet source that is always unreachable.
+    struct DownSheetSource;
+
+    impl sheet::SheetSource for DownSheetSource {
+        fn fetch_raw(&self) -> sheet::FetchFuture<'_> {
+            Box::pin(async {
+                Err(sheet::FetchError::Io(std::io::Error::other(
+                    "sheet is down",
+                )))
+            })
+        }
+    }
+
+    #[actix_web::test]
+    async fn seed_data_is_served_when_sheet_and_cache_fail() {
+        let dir = scratch_dir();
+        std::fs::write(dir.join("data_cache.json"), "{ not json").unwrap();
+        std::fs::write(
+            dir.join("seed.json"),
+            r#"[{"Zip": "33701", "State": "FL"}]"#,
+        )
+        .unwrap();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            seed_file: Some(dir.join("seed.json").to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let app = test_app!(state);
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "seed");
+        let body: Value = test::read_body_json(resp).await;
+        let mut expected = vec![json!({"Zip": "33701", "State": "FL"})];
+        ids::assign_ids(&mut expected);
+        assert_eq!(body, json!(expected));
+
+        // The seed is only a stand-in: the sheet is retried within minutes,
+        // not after the 12-hour TTL, and /health says why it is in use.
+        let dataset = state.dataset().await.unwrap();
+        assert_eq!(dataset.ttl, Duration::from_secs(5 * 60));
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert!(body["last_error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("sheet is down"));
+    }
+
+    #[actix_web::test]
+    async fn refresh_schedule_replaces_the_ttl() {
+        let get = || test::TestRequest::get().uri("/data").to_request();
+        let aged_cache = |dir: &std::path::Path| {
+            let cache = dir.join("data_cache.json");
+            std::fs::write(&cache, r#"[{"Zip": "33701", "State": "FL"}]"#).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&cache)
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() - Duration::from_secs(120))
+                .unwrap();
+            cache.to_string_lossy().into_owned()
+        };
+
+        // Within the TTL, but written before the last run of the schedule.
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: aged_cache(&dir),
+            refresh_cron: Some(schedule::parse("* * * * *").unwrap()),
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        let resp = test::call_service(&app, get()).await;
+        assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "sheet");
+
+        // Past the TTL, but no run of the schedule since it was written.
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: aged_cache(&dir),
+            cache_ttl: Duration::from_secs(1),
+            refresh_cron: Some(schedule::parse("0 0 1 1 *").unwrap()),
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let app = test_app!(state);
+        for _ in 0..2 {
+            let resp = test::call_service(&app, get()).await;
+            assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "cache");
+            assert_eq!(resp.headers().get(CACHE_STATUS_HEADER).unwrap(), "fresh");
+            assert!(resp.headers().get("X-Data-Stale").is_none());
+        }
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["status"], "ok");
+        let degraded_after = body["degraded_after_secs"].as_u64().unwrap();
+        assert!(degraded_after > body["data_age_secs"].as_u64().unwrap());
+    }
+
+    #[actix_web::test]
+    async fn expired_cache_is_served_and_flagged_when_the_sheet_is_gone() {
+        let dir = scratch_dir();
+        let cache = dir.join("data_cache.json");
+        std::fs::write(&cache, r#"[{"Zip": "33701", "State": "FL"}]"#).unwrap();
+        let hours = |h: u64| std::time::Duration::from_secs(h * 60 * 60);
+        std::fs::File::options()
+            .write(true)
+            .open(&cache)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - hours(100))
+            .unwrap();
+        let config = Config {
+            cache_file: cache.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let app = test_app!(state);
+
+        for _ in 0..2 {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "cache");
+            assert_eq!(resp.headers().get("X-Data-Stale").unwrap(), "true");
+            let warning = resp.headers().get(header::WARNING).unwrap();
+            assert!(warning.to_str().unwrap().contains("100 hours old"));
+        }
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["stale"], true);
+        // Unset, the threshold is the TTL.
+        assert_eq!(body["ttl_secs"], 12 * 60 * 60);
+        assert_eq!(body["degraded_after_secs"], 12 * 60 * 60);
+        assert_eq!(body["last_error"]["message"], "sheet is down");
+
+        // Within a longer threshold the same data is served without the flag.
+        let mut config = (*state.config()).clone();
+        config.max_stale_age = Some(hours(200));
+        state.replace_config(config);
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert!(resp.headers().get("X-Data-Stale").is_none());
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["degraded_after_secs"], 200 * 60 * 60);
+    }
+
+    #[actix_web::test]
+    async fn old_data_is_only_degraded_once_a_refresh_fails() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            max_stale_age: Some(Duration::from_millis(1)),
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+        let get = |uri| test::TestRequest::get().uri(uri).to_request();
+
+        assert_eq!(test::call_service(&app, get("/data")).await.status(), 200);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        // Older than the threshold, but within the TTL: nothing has failed.
+        let resp = test::call_service(&app, get("/data")).await;
+        assert!(resp.headers().get("X-Data-Stale").is_none());
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/health")).await).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["stale"], false);
+        assert!(body["last_error"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn failing_refreshes_back_off_instead_of_fetching_per_request() {
+        struct CountingDownSource(Arc<AtomicUsize>);
+
+        impl sheet::SheetSource for CountingDownSource {
+            fn fetch_raw(&self) -> sheet::FetchFuture<'_> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Box::pin(DownSheetSource.fetch_raw())
+            }
+        }
+
+        let dir = scratch_dir();
+        let cache = dir.join("data_cache.json");
+        std::fs::write(&cache, r#"[{"Zip": "33701", "State": "FL"}]"#).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&cache)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - Duration::from_secs(100 * 60 * 60))
+            .unwrap();
+        let config = Config {
+            cache_file: cache.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let source = CountingDownSource(Arc::clone(&fetches));
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        for _ in 0..5 {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "cache");
+        }
+        // Any background retry would have run by now.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!state.is_refreshing());
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.headers().get(CACHE_STATUS_HEADER).unwrap(), "stale");
+    }
+
+    #[actix_web::test]
+    async fn cache_check_repairs_a_corrupt_cache_and_reports_a_mismatch() {
+        let dir = scratch_dir();
+        let cache = dir.join("data_cache.json");
+        let state = test_state(&dir);
+        let app = test_app!(state);
+        let req = test::TestRequest::get().uri("/data").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        std::fs::write(&cache, "[{\"Zip\": ").unwrap();
+
+        let check = state.check_cache_now().await.unwrap();
+        assert_eq!(check.status, "repaired");
+        assert!(check.problem.unwrap().contains("does not parse"));
+        let on_disk = || {
+            let raw = std::fs::read_to_string(&cache).unwrap();
+            serde_json::from_str::<Value>(&raw).unwrap()
+        };
+        let repaired = on_disk();
+        assert_eq!(repaired.as_array().unwrap().len(), 2);
+        assert!(repaired[0].get("id").is_none());
+        assert_eq!(repaired[0]["City"], "St. Petersburg");
+        // Rewritten with the data's fetch time, so its TTL is unchanged.
+        let modified = std::fs::metadata(&cache).unwrap().modified().unwrap();
+        assert_eq!(modified, state.loaded_dataset().unwrap().fetched_at());
+        let check = state.check_cache_now().await.unwrap();
+        assert_eq!(check.status, "ok");
+        assert_eq!(check.problem, None);
+
+        // A hand edit that drops a record is reported, not overwritten.
+        std::fs::write(&cache, r#"[{"Zip": "33701"}]"#).unwrap();
+        let check = state.check_cache_now().await.unwrap();
+        assert_eq!(check.status, "mismatch");
+        assert_eq!(
+            check.problem.unwrap(),
+            format!("{} holds 1 records but 2 are served", cache.display())
+        );
+        assert_eq!(on_disk(), json!([{"Zip": "33701"}]));
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["cache_check"]["status"], "mismatch");
+    }
+
+    #[actix_web::test]
+    async fn metrics_json_counts_requests_cache_use_and_failures() {
+        let dir = scratch_dir();
+        let state = test_state(&dir);
+        let app = test_app!(state);
+        let metrics = |app| async move {
+            let req = test::TestRequest::get().uri("/metrics.json").to_request();
+            test::read_body_json::<Value, _>(test::call_service(app, req).await).await
+        };
+
+        let body = metrics(&app).await;
+        assert_eq!(body["requests_total"], 1);
+        assert_eq!(body["cache_misses_total"], 0);
+        assert_eq!(body["records"], Value::Null);
+
+        for _ in 0..3 {
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        }
+        let body = metrics(&app).await;
+        let keys: Vec<&str> = body
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "cache_age_secs",
+                "cache_hits_total",
+                "cache_misses_total",
+                "fetch_failures_total",
+                "in_flight_requests",
+                "records",
+                "refreshing",
+                "requests_total"
+            ]
+        );
+        // Only this request is being handled.
+        assert_eq!(body["in_flight_requests"], 1);
+        assert_eq!(body["requests_total"], 5);
+        assert_eq!(body["cache_misses_total"], 1);
+        assert_eq!(body["cache_hits_total"], 2);
+        assert_eq!(body["fetch_failures_total"], 0);
+        assert_eq!(body["records"], 2);
+        assert!(body["cache_age_secs"].is_u64());
+
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let app = test_app!(state);
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(metrics(&app).await["fetch_failures_total"], 1);
+        // With the response cache, a request is still one lookup: the load,
+        // then one stored by the cache, then one replayed from it.
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            response_cache_size: 8,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        for expected in [None, Some("miss"), Some("hit")] {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+            let cache = resp.headers().get("X-Response-Cache");
+            assert_eq!(cache.map(|v| v.to_str().unwrap()), expected);
+        }
+        let body = metrics(&app).await;
+        assert_eq!(body["cache_misses_total"], 1);
+        assert_eq!(body["cache_hits_total"], 2);
+    }
+
+    #[actix_web::test]
+    async fn robots_policy_is_configurable_and_api_responses_are_noindex() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+        let req = test::TestRequest::get().uri("/robots.txt").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get("X-Robots-Tag").is_none());
+        let body = test::read_body(resp).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("Disallow: /data\n"));
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        assert!(test::call_service(&app, req)
+            .await
+            .headers()
+            .get("X-Robots-Tag")
+            .is_none());
+        let req = test::TestRequest::get().uri("/counties").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("X-Robots-Tag").unwrap(), "noindex");
+
+        std::fs::write(dir.join("robots.txt"), "User-agent: *\nDisallow: /\n").unwrap();
+        let config = Config {
+            robots_file: Some(dir.join("robots.txt").to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let app = test_app!(state);
+        let req = test::TestRequest::get().uri("/robots.txt").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        assert_eq!(body, "User-agent: *\nDisallow: /\n");
+    }
+
+    #[actix_web::test]
+    async fn states_and_choropleth_share_the_counts() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get().uri("/states").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(
+            body,
+            json!([{"state": "FL", "count": 1}, {"state": "MA", "count": 1}])
+        );
+
+        let req = test::TestRequest::get().uri("/choropleth").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        let map = body.as_object().unwrap();
+        assert_eq!(map.len(), 51);
+        assert_eq!(map["FL"], 1);
+        assert_eq!(map["DC"], 0);
+        assert_eq!(map["WY"], 0);
+    }
+
+    #[actix_web::test]
+    async fn served_records_round_trip_through_the_typed_record() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            drop_columns: Vec::new(),
+            title_case: true,
+            title_case_keep_raw: true,
+            regions: true,
+            ..Config::default()
+        };
+        let csv = "Country,Zip,City,County,State,Effective Date,Status,Notes\n\
+                   US,33701,ST. PETERSBURG,Pinellas,FL,1/5/2025,Banned,\n\
+                   US,01850,Lowell,Middlesex,MA,soon,Proposed ban,council vote\n";
+        let source = FakeSheetSource(csv.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+
+        let req = test::TestRequest::get().uri("/data").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        let records =
+            gkcsearch::record::parse_records(std::str::from_utf8(&body).unwrap()).unwrap();
+        let served: Vec<Value> = serde_json::from_slice(&body).unwrap();
+        for (record, value) in records.iter().zip(&served) {
+            assert_eq!(&record.to_value().unwrap(), value);
+        }
+        assert_eq!(records[0].city.as_deref(), Some("St. Petersburg"));
+        assert_eq!(records[0].get("City_raw"), Some("ST. PETERSBURG"));
+        assert_eq!(records[1].ban_status.as_deref(), Some("pending"));
+        assert_eq!(records[1].effective_date_valid, Some(false));
+        assert_eq!(records[1].region.as_deref(), Some("Northeast"));
+        assert_eq!(records[1].get("Notes"), Some("council vote"));
+
+        // Endpoints that add numbers to the records parse too.
+        let req = test::TestRequest::get()
+            .uri("/search?q=lowell&debug_score=true")
+            .to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        let records =
+            gkcsearch::record::parse_records(std::str::from_utf8(&body).unwrap()).unwrap();
+        assert!(records[0].extra["score"].is_number());
+    }
+
+    #[actix_web::test]
+    async fn regions_annotate_records_and_group_states() {
+        let dir = scratch_dir();
+        let state = test_state(&dir);
+        let app = test_app!(state);
+        let req = test::TestRequest::get().uri("/regions").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+        let config = Config {
+            cache_file: dir
+                .join("regions_cache.json")
+                .to_string_lossy()
+                .into_owned(),
+            regions: true,
+            ..Config::default()
+        };
+        let csv = format!(
+            "{}US,60601,Chicago,Cook,IL\nUS,90001,Los Angeles,Los Angeles,CA\n",
+            SHEET_CSV
+        );
+        let state = web::Data::new(AppState::with_source(
+            config,
+            Box::new(FakeSheetSource(csv)),
+        ));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get().uri("/data").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        let fields: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["State"].clone(),
+                    r["region"].clone(),
+                    r["timezone"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (json!("FL"), json!("South"), json!("America/New_York")),
+                (json!("MA"), json!("Northeast"), json!("America/New_York")),
+                (json!("IL"), json!("Midwest"), json!("America/Chicago")),
+                (json!("CA"), json!("West"), json!("America/Los_Angeles")),
+            ]
+        );
+
+        let req = test::TestRequest::get().uri("/regions").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        let counts: Vec<_> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| (r["region"].as_str().unwrap(), r["count"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(
+            counts,
+            [("Northeast", 1), ("Midwest", 1), ("South", 1), ("West", 1)]
+        );
+        let pacific = &body[3]["divisions"][1];
+        assert_eq!(pacific["division"], "Pacific");
+        assert_eq!(pacific["states"]["CA"], 1);
+        assert_eq!(pacific["states"]["WA"], 0);
+    }
+
+    #[actix_web::test]
+    async fn country_survives_when_not_dropped() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            drop_columns: vec!["column_0".to_string()],
+            ..Config::default()
+        };
+        let csv = format!("{}CA,K1A,Ottawa,,ON\n", SHEET_CSV);
+        let state = web::Data::new(AppState::with_source(
+            config,
+            Box::new(FakeSheetSource(csv)),
+        ));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get().uri("/data").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body[0]["Country"], "US");
+        assert_eq!(body[2]["Country"], "CA");
+
+        let req = test::TestRequest::get()
+            .uri("/states?by_country=true")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(
+            body,
+            json!([
+                {"country": "CA", "state": "ON", "count": 1},
+                {"country": "US", "state": "FL", "count": 1},
+                {"country": "US", "state": "MA", "count": 1}
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn tree_and_cities_keep_county_wide_bans() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let csv = format!("{}US,,,Hillsborough,FL\n", SHEET_CSV);
+        let state = web::Data::new(AppState::with_source(
+            config,
+            Box::new(FakeSheetSource(csv)),
+        ));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get()
+            .uri("/cities?state=fl")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(body[0]["name"], "All of Hillsborough County");
+        assert_eq!(body[0]["county_wide"], true);
+        assert_eq!(body[1]["name"], "St. Petersburg");
+        assert_eq!(body[1]["county_wide"], false);
+
+        let req = test::TestRequest::get().uri("/tree").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body[0]["state"], "FL");
+        assert_eq!(body[0]["count"], 2);
+        assert_eq!(body[1]["state"], "MA");
+        assert_eq!(body[1]["cities"][0]["zips"], json!(["01850"]));
+    }
+
+    #[actix_web::test]
+    async fn data_is_unavailable_until_the_background_load_succeeds() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            background_load: true,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["ready"], false);
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 503);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "5");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "not_ready");
+
+        // Waits for the load the request above started.
+        state.dataset().await.unwrap();
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["ready"], true);
+    }
+
+    #[actix_web::test]
+    async fn server_timing_reports_the_work_done_when_enabled() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            server_timing: true,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let timing = |resp: &ServiceResponse<_>| {
+            let value = resp
+                .headers()
+                .get("Server-Timing")
+                .unwrap()
+                .to_str()
+                .unwrap();
+            value
+                .split(", ")
+                .map(|metric| {
+                    let (name, dur) = metric.split_once(";dur=").unwrap();
+                    (name.to_string(), dur.parse::<f64>().unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        let req = test::TestRequest::get().uri("/data").to_request();
+        let first = timing(&test::call_service(&app, req).await);
+        let names: Vec<&str> = first.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["cache", "fetch", "serialize"]);
+
+        // Served from memory: no cache or fetch work.
+        let req = test::TestRequest::get().uri("/data").to_request();
+        let second = timing(&test::call_service(&app, req).await);
+        assert_eq!(second[0].1, 0.0);
+        assert_eq!(second[1].1, 0.0);
+
+        let app = test_app!(test_state(&dir));
+        let req = test::TestRequest::get().uri("/data").to_request();
+        assert!(!test::call_service(&app, req)
+            .await
+            .headers()
+            .contains_key("Server-Timing"));
+    }
+
+    #[actix_web::test]
+    async fn repeated_searches_are_cached_until_a_refresh() {
+        let dir = scratch_dir();
+        let state = test_state(&dir);
+        let app = test_app!(state);
+        let search = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let first: Value =
+            test::read_body_json(test::call_service(&app, search("/search?q=lowell")).await).await;
+        let dataset = state.loaded_dataset().unwrap();
+        assert_eq!(dataset.search_cache.hits(), 0);
+        // Equivalent after normalization, so answered from the cache.
+        let second: Value =
+            test::read_body_json(test::call_service(&app, search("/search?q=%20LOWELL")).await)
+                .await;
+        assert_eq!(first, second);
+        assert_eq!(dataset.search_cache.hits(), 1);
+
+        // Every request reloads here; each refresh starts with an empty cache.
+        let (state, source) = always_refetching_state(&scratch_dir(), &[]);
+        let app = test_app!(state);
+        let body: Value =
+            test::read_body_json(test::call_service(&app, search("/search?q=lowell")).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        source
+            .0
+            .lock()
+            .unwrap()
+            .push_str("US,01851,Lowell,Middlesex,MA\n");
+        let body: Value =
+            test::read_body_json(test::call_service(&app, search("/search?q=lowell")).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(state.loaded_dataset().unwrap().search_cache.hits(), 0);
+    }
+
+    #[actix_web::test]
+    async fn list_responses_are_cached_until_a_refresh() {
+        let dir = scratch_dir();
+        let cache_file = dir.join("data_cache.json");
+        let config = Config {
+            cache_file: cache_file.to_string_lossy().into_owned(),
+            response_cache_size: 8,
+            ..Config::default()
+        };
+        let source = EditableSheetSource(Default::default());
+        *source.0.lock().unwrap() = SHEET_CSV.to_string();
+        let state = web::Data::new(AppState::with_source(config, Box::new(source.clone())));
+        let app = test_app!(state);
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        let cache = |resp: &ServiceResponse<_>| resp.headers().get("X-Response-Cache").cloned();
+
+        // The request that loads the data isn't cached; the next one is stored.
+        let resp = test::call_service(&app, get("/search?q=lowell&state=MA")).await;
+        assert!(cache(&resp).is_none());
+        let resp = test::call_service(&app, get("/search?q=lowell&state=MA")).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(cache(&resp).unwrap(), "miss");
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        let first = test::read_body(resp).await;
+
+        // The same query with its parameters in another order is a hit.
+        let resp = test::call_service(&app, get("/search?state=MA&q=lowell")).await;
+        assert_eq!(cache(&resp).unwrap(), "hit");
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &etag);
+        assert_eq!(content_type(&resp), "application/json");
+        assert_eq!(test::read_body(resp).await, first);
+
+        let req = test::TestRequest::get()
+            .uri("/search?q=lowell&state=MA")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(cache(&resp).unwrap(), "hit");
+
+        // Other queries, errors and endpoints outside the list are not cached.
+        let resp = test::call_service(&app, get("/search?q=lowell&state=FL")).await;
+        assert_eq!(cache(&resp).unwrap(), "miss");
+        for _ in 0..2 {
+            let resp = test::call_service(&app, get("/search?sort=bogus")).await;
+            assert_eq!(resp.status(), 400);
+            assert!(cache(&resp).is_none());
+        }
+        let resp = test::call_service(&app, get("/random")).await;
+        assert!(cache(&resp).is_none());
+
+        let resp = test::call_service(&app, get("/data?state=FL")).await;
+        assert_eq!(cache(&resp).unwrap(), "miss");
+        let resp = test::call_service(&app, get("/data?state=FL")).await;
+        assert_eq!(cache(&resp).unwrap(), "hit");
+        assert_eq!(resp.headers().get(CACHE_STATUS_HEADER).unwrap(), "fresh");
+
+        // A config reload doesn't replay responses built under the old one.
+        let resp = test::call_service(&app, get("/data")).await;
+        assert_eq!(cache(&resp).unwrap(), "miss");
+        let mut config = (*state.config()).clone();
+        config.max_records = 1;
+        state.replace_config(config.clone());
+        let resp = test::call_service(&app, get("/data")).await;
+        assert_eq!(cache(&resp).unwrap(), "miss");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        let resp = test::call_service(&app, get("/data")).await;
+        assert_eq!(cache(&resp).unwrap(), "hit");
+        config.max_records = Config::default().max_records;
+        state.replace_config(config);
+
+        // A refresh publishes a new dataset with an empty cache.
+        source
+            .0
+            .lock()
+            .unwrap()
+            .push_str("US,33755,Clearwater,Pinellas,FL\n");
+        std::fs::remove_file(&cache_file).unwrap();
+        let before = state.loaded_dataset().unwrap();
+        state.start_background_load();
+        for _ in 0..200 {
+            if !Arc::ptr_eq(&state.loaded_dataset().unwrap(), &before) {
+                break;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        let resp = test::call_service(&app, get("/data?state=FL")).await;
+        assert_eq!(cache(&resp).unwrap(), "miss");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn json_is_indented_only_when_asked() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert!(resp.headers().contains_key(header::ETAG));
+        let compact = test::read_body(resp).await;
+        assert!(!compact.contains(&b'\n'));
+
+        let req = test::TestRequest::get()
+            .uri("/data?pretty=true")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(content_type(&resp), "application/json");
+        assert!(!resp.headers().contains_key(header::ETAG));
+        let pretty = test::read_body(resp).await;
+        assert!(pretty.starts_with(b"[\n  {\n    \"City\": \""));
+        let parse = |body: &[u8]| serde_json::from_slice::<Value>(body).unwrap();
+        assert_eq!(parse(&pretty), parse(&compact));
+
+        // A global default, which a request can still turn off.
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            pretty_json: true,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        assert!(body.contains(&b'\n'));
+        let req = test::TestRequest::get()
+            .uri("/data?pretty=false")
+            .to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        assert_eq!(body, compact);
+    }
+
+    #[actix_web::test]
+    async fn cache_is_compact_unless_pretty_is_requested() {
+        for pretty in [false, true] {
+            let dir = scratch_dir();
+            let config = Config {
+                cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+                cache_pretty: pretty,
+                ..Config::default()
+            };
+            let source = FakeSheetSource(SHEET_CSV.to_string());
+            let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+            state.dataset().await.unwrap();
+            let cached = std::fs::read_to_string(dir.join("data_cache.json")).unwrap();
+            assert_eq!(cached.contains('\n'), pretty);
+        }
+    }
+
+    #[actix_web::test]
+    async fn gzip_cache_replaces_an_existing_plain_cache() {
+        let dir = scratch_dir();
+        let plain = dir.join("data_cache.json");
+        std::fs::write(&plain, r#"[{"Zip": "33701", "State": "FL"}]"#).unwrap();
+        let config = Config {
+            cache_file: plain.to_string_lossy().into_owned(),
+            cache_gzip: true,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config.clone(), Box::new(source)));
+        let app = test_app!(state);
+
+        // The plain cache is still fresh, so it is used as-is.
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "cache");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+
+        // The next fetch writes the compressed cache and drops the plain one.
+        let config = Config {
+            cache_ttl: std::time::Duration::ZERO,
+            ..config
+        };
+        let state = web::Data::new(AppState::with_source(
+            config,
+            Box::new(FakeSheetSource(SHEET_CSV.to_string())),
+        ));
+        state.dataset().await.unwrap();
+        assert!(!plain.exists());
+        let gz = std::fs::read(dir.join("data_cache.json.gz")).unwrap();
+        assert_eq!(&gz[..2], [0x1f, 0x8b]);
+
+        let config = Config {
+            cache_file: plain.to_string_lossy().into_owned(),
+            cache_gzip: true,
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let dataset = state.dataset().await.unwrap();
+        assert_eq!(dataset.source, cache::DataSource::Cache);
+        assert_eq!(dataset.records.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn truncated_gzip_cache_falls_through_to_a_refetch() {
+        let dir = scratch_dir();
+        let plain = dir.join("data_cache.json");
+        // A gzip header with nothing after it, as left by an interrupted write.
+        std::fs::write(dir.join("data_cache.json.gz"), [0x1f, 0x8b, 0x08, 0x00]).unwrap();
+        let config = Config {
+            cache_file: plain.to_string_lossy().into_owned(),
+            cache_gzip: true,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let dataset = state.dataset().await.unwrap();
+        assert_eq!(dataset.source, cache::DataSource::Sheet);
+        assert_eq!(dataset.records.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn cache_dir_holds_one_file_per_source() {
+        let dir = scratch_dir().join("cache");
+        let config = Config {
+            cache_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Config::default()
+        };
+        let other = Config {
+            sheet_url: format!("{}&gid=1", config.sheet_url),
+            ..config.clone()
+        };
+        assert_ne!(config.cache_path(), other.cache_path());
+        assert!(config.cache_path().starts_with(dir.to_str().unwrap()));
+
+        let path = config.cache_path();
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+        let req = test::TestRequest::get().uri("/data").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        assert!(std::fs::read_to_string(path).unwrap().contains("Pinellas"));
+    }
+
+    #[actix_web::test]
+    async fn export_downloads_the_records_as_csv() {
+        let dir = scratch_dir();
+        let state = test_state(&dir);
+        let app = test_app!(state);
+        let req = test::TestRequest::get().uri("/export.csv").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(content_type(&resp), "text/csv; charset=utf-8");
+        let date = dates::format_rfc3339(state.dataset().await.unwrap().fetched_at())[..10]
+            .replace('-', "");
+        assert_eq!(
+            resp.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            format!("attachment; filename=\"gkc-bans-{}.csv\"", date).as_str()
+        );
+        let body = test::read_body(resp).await;
+        let id = |record| ids::record_id(&record);
+        assert_eq!(
+            body,
+            format!(
+                "id,Zip,City,County,State\n{},33701,St. Petersburg,Pinellas,FL\n{},01850,Lowell,Middlesex,MA\n",
+                id(json!({"State": "FL", "City": "St. Petersburg", "County": "Pinellas", "Zip": "33701"})),
+                id(json!({"State": "MA", "City": "Lowell", "County": "Middlesex", "Zip": "01850"})),
+            )
+        );
+    }
+
+    #[actix_web::test]
+    async fn aliases_rename_served_keys_without_breaking_lookups() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            column_aliases: vec![
+                ("City".to_string(), "city".to_string()),
+                ("Zip".to_string(), "zip".to_string()),
+            ],
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let body: Value = test::call_and_read_body_json(&app, get("/data")).await;
+        assert_eq!(body[0]["city"], "St. Petersburg");
+        assert_eq!(body[0]["zip"], "33701");
+        assert!(body[0].get("City").is_none());
+        // Filters, IDs, and zip lookups still find the sheet's columns.
+        let body: Value = test::call_and_read_body_json(&app, get("/data?state=MA")).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["city"], "Lowell");
+        let record =
+            json!({"State": "MA", "City": "Lowell", "County": "Middlesex", "Zip": "01850"});
+        assert_eq!(body[0]["id"], ids::record_id(&record));
+        let body: Value = test::call_and_read_body_json(&app, get("/data/by-zip/33701")).await;
+        assert_eq!(body["records"][0]["city"], "St. Petersburg");
+        let body: Value = test::call_and_read_body_json(&app, get("/check?zip=01850")).await;
+        assert_eq!(body["matched_by"], json!(["exact_zip"]));
+        assert_eq!(body["records"][0]["zip"], "01850");
+        let body: Value =
+            test::call_and_read_body_json(&app, get("/search?q=lowell&highlight=true")).await;
+        assert_eq!(body[0]["matches"][0]["field"], "city");
+
+        let body = test::call_and_read_body(&app, get("/export.csv")).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("id,zip,city,County,State\n"), "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn offline_mode_serves_the_embedded_dataset() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            offline: true,
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let app = test_app!(state);
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "embedded");
+        let body: Value = test::read_body_json(resp).await;
+        assert!(!body.as_array().unwrap().is_empty());
+        assert!(!dir.join("data_cache.json").exists());
+    }
+
+    #[actix_web::test]
+    async fn search_supports_regex_and_rejects_bad_patterns() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get()
+            .uri("/search?regex=%5Elow&fields=city")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["City"], "Lowell");
+
+        for uri in [
+            "/search?regex=(unclosed",
+            "/search?regex=low&fields=Country",
+            "/search?regex=low&q=lowell",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn search_highlight_reports_fields_and_offsets() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get()
+            .uri("/search?q=peters%20FL&highlight=true")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(
+            body,
+            json!([{
+                "record": {
+                    "id": ids::record_id(&json!({"State": "FL", "City": "St. Petersburg", "County": "Pinellas", "Zip": "33701"})),
+                    "Zip": "33701", "City": "St. Petersburg", "County": "Pinellas", "State": "FL"
+                },
+                "matches": [
+                    {"field": "City", "start": 4, "end": 10},
+                    {"field": "State", "start": 0, "end": 2}
+                ]
+            }])
+        );
+
+        // Without the flag the response shape is unchanged.
+        let req = test::TestRequest::get()
+            .uri("/search?q=peters")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body[0]["City"], "St. Petersburg");
+    }
+
+    #[actix_web::test]
+    async fn search_ranks_by_relevance_unless_sorted() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let csv = "Country,Zip,City,County,State\n\
+                   US,84115,Salt Lake City,Salt Lake,UT\n\
+                   US,32726,Eustis,Lake,FL\n\
+                   US,32055,Lake City,Columbia,FL\n";
+        let source = FakeSheetSource(csv.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+        let cities = |body: &Value| -> Vec<String> {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["City"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let req = test::TestRequest::get()
+            .uri("/search?q=lake&debug_score=true")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(cities(&body), ["Eustis", "Lake City", "Salt Lake City"]);
+        assert_eq!(body[0]["score"], 31);
+        assert_eq!(body[2]["score"], 12);
+
+        let req = test::TestRequest::get()
+            .uri("/search?q=lake&sort=zip")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(cities(&body), ["Lake City", "Eustis", "Salt Lake City"]);
+        assert!(body[0].get("score").is_none());
+    }
+
+    #[actix_web::test]
+    async fn search_near_a_zip_sorts_by_distance() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let csv = "Country,Zip,City,State\n\
+                   US,33701,St. Petersburg,FL\n\
+                   US,01850,Lowell,MA\n\
+                   US,33755,Clearwater,FL\n\
+                   US,unknown,Nowhere,FL\n";
+        let source = FakeSheetSource(csv.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get()
+            .uri("/search?near=33755&radius_mi=30")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(UNGEOCODED_HEADER).unwrap(), "1");
+        let body: Value = test::read_body_json(resp).await;
+        let cities: Vec<&str> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["City"].as_str().unwrap())
+            .collect();
+        assert_eq!(cities, ["Clearwater", "St. Petersburg"]);
+        assert_eq!(body[0]["distance_mi"], 0.0);
+        assert!(body[1]["distance_mi"].as_f64().unwrap() > 0.0);
+
+        for uri in ["/search?near=00000", "/search?near=33701&radius_mi=-1"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn nearby_gives_a_verdict_for_a_zip() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get()
+            .uri("/nearby?zip=33701")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["banned"], true);
+        assert_eq!(body["zip_banned"], true);
+        assert_eq!(body["city_bans"], 1);
+        assert_eq!(body["nearest"]["record"]["City"], "St. Petersburg");
+        assert_eq!(body["nearest"]["distance_mi"], 0.0);
+
+        // Clearwater shares Pinellas County with St. Petersburg, but that ban
+        // is for the city alone.
+        let req = test::TestRequest::get()
+            .uri("/nearby?zip=33755")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["zip_banned"], false);
+        assert_eq!(body["county_bans"], 0);
+        assert_eq!(body["banned"], false);
+        assert!(body["nearest"]["distance_mi"].as_f64().unwrap() > 0.0);
+
+        // A zip the coordinate table doesn't know still gets an answer.
+        let req = test::TestRequest::get()
+            .uri("/nearby?zip=00000")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["banned"], false);
+        assert_eq!(body["location"], Value::Null);
+        assert_eq!(body["nearest"], Value::Null);
+
+        let req = test::TestRequest::get().uri("/nearby?zip=abc").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn check_explains_the_verdict_for_a_zip() {
+        let dir = scratch_dir();
+        let csv = "Country,Zip,City,County,State,Status\n\
+                   US,33701,St. Petersburg,Pinellas,FL,Banned\n\
+                   US,,,Pinellas,FL,Restricted to 21+\n\
+                   US,,,,FL,Proposed ban\n\
+                   US,01850,Lowell,Middlesex,MA,Banned\n\
+                   US,,,,VT,Banned\n";
+        let state = web::Data::new(AppState::with_source(
+            Config {
+                cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+                ..Config::default()
+            },
+            Box::new(FakeSheetSource(csv.to_string())),
+        ));
+        let app = test_app!(state);
+        let check = |zip: &'static str| {
+            let app = &app;
+            async move {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/check?zip={}", zip))
+                    .to_request();
+                let resp = test::call_service(app, req).await;
+                assert_eq!(resp.status(), 200);
+                test::read_body_json::<Value, _>(resp).await
+            }
+        };
+
+        let body = check("33701").await;
+        assert_eq!(body["banned"], true);
+        assert_eq!(body["matched_by"], json!(["exact_zip", "county", "state"]));
+        assert_eq!(body["records"].as_array().unwrap().len(), 3);
+        assert_eq!(body["message"], "❌ 33701 is in a banned area.");
+
+        // Clearwater: only the restricted county entry and the pending state one.
+        let body = check("33755").await;
+        assert_eq!(body["banned"], false);
+        assert_eq!(body["matched_by"], json!(["county", "state"]));
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("⚠️ 33755 has no bans"));
+
+        // Another Lowell zip is banned through its city.
+        let body = check("01851").await;
+        assert_eq!(body["banned"], true);
+        assert_eq!(body["matched_by"], json!(["city"]));
+        assert_eq!(
+            body["message"],
+            "⚠️ 01851 is not banned itself, but its city or county has bans."
+        );
+
+        // Burlington, VT only falls under the statewide ban.
+        let body = check("05401").await;
+        assert_eq!(body["banned"], true);
+        assert_eq!(body["matched_by"], json!(["state"]));
+        assert_eq!(
+            body["message"],
+            "⚠️ 05401 is not banned itself, but its state has a statewide ban."
+        );
+
+        // Unknown zips get a clear negative verdict.
+        let body = check("00000").await;
+        assert_eq!(body["banned"], false);
+        assert_eq!(body["matched_by"], json!([]));
+        assert_eq!(body["location"], Value::Null);
+        assert!(body["message"].as_str().unwrap().starts_with("✅"));
+
+        let req = test::TestRequest::get().uri("/check?zip=abc").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        // /nearby gives the same verdict, counting only bans.
+        for zip in ["33701", "33755", "01851", "05401", "00000"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/nearby?zip={}", zip))
+                .to_request();
+            let nearby: Value = test::read_body_json(test::call_service(&app, req).await).await;
+            let checked = check(zip).await;
+            assert_eq!(nearby["banned"], checked["banned"], "{}", zip);
+            assert_eq!(nearby["message"], checked["message"], "{}", zip);
+        }
+        let req = test::TestRequest::get()
+            .uri("/nearby?zip=33755")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["county_bans"], 0);
+        assert_eq!(body["nearest"]["record"]["City"], "St. Petersburg");
+        let req = test::TestRequest::get()
+            .uri("/nearby?zip=05401")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(
+            (
+                &body["city_bans"],
+                &body["county_bans"],
+                &body["state_bans"]
+            ),
+            (&json!(0), &json!(0), &json!(1))
+        );
+    }
+
+    #[actix_web::test]
+    async fn list_endpoints_wrap_results_in_an_envelope_on_request() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        for (uri, len) in [
+            ("/data?envelope=true", 2),
+            ("/search?q=lowell&envelope=true", 1),
+            ("/counties?envelope=true", 2),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+            assert_eq!(body["data"].as_array().unwrap().len(), len, "{}", uri);
+            assert_eq!(body["meta"]["total"], len, "{}", uri);
+            assert_eq!(body["meta"]["source"], "sheet", "{}", uri);
+            assert!(body["meta"]["cache_age_seconds"].is_u64(), "{}", uri);
+            assert!(body["meta"]["generated_at"]
+                .as_str()
+                .unwrap()
+                .ends_with('Z'));
+        }
+    }
+
+    /// A sheet source whose CSV can be swapped between refreshes.
+    #[derive(Clone)]
+    struct EditableSheetSource(std::sync::Arc<std::sync::Mutex<String>>);
+
+    impl sheet::SheetSource for EditableSheetSource {
+        fn fetch_raw(&self) -> sheet::FetchFuture<'_> {
+            let raw = self.0.lock().unwrap().clone().into_bytes();
+            Box::pin(async move { Ok(raw) })
+        }
+    }
+
+    /// State that refetches the sheet on every request.
+    fn always_refetching_state(
+        dir: &std::path::Path,
+        required_columns: &[&str],
+    ) -> (web::Data<AppState>, EditableSheetSource) {
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            cache_ttl: std::time::Duration::ZERO,
+            stale_while_revalidate: std::time::Duration::ZERO,
+            required_columns: required_columns.iter().map(|c| c.to_string()).collect(),
+            ..Config::default()
+        };
+        let source = EditableSheetSource(Default::default());
+        *source.0.lock().unwrap() = SHEET_CSV.to_string();
+        let state = web::Data::new(AppState::with_source(config, Box::new(source.clone())));
+        (state, source)
+    }
+
+    /// A sheet whose ETag is its length, counting the bodies it sends.
+    #[derive(Clone, Default)]
+    struct VersionedSheetSource {
+        csv: std::sync::Arc<std::sync::Mutex<String>>,
+        downloads: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl sheet::SheetSource for VersionedSheetSource {
+        fn fetch_raw(&self) -> sheet::FetchFuture<'_> {
+            let raw = self.csv.lock().unwrap().clone().into_bytes();
+            Box::pin(async move { Ok(raw) })
+        }
+
+        fn fetch_if_changed<'a>(
+            &'a self,
+            validators: &'a sheet::Validators,
+        ) -> sheet::DownloadFuture<'a> {
+            let raw = self.csv.lock().unwrap().clone().into_bytes();
+            let etag = format!("\"{}\"", raw.len());
+            Box::pin(async move {
+                if validators.etag.as_ref() == Some(&etag) {
+                    return Ok(sheet::Download::Unchanged);
+                }
+                self.downloads
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let next = sheet::Validators {
+                    etag: Some(etag),
+                    last_modified: None,
+                };
+                Ok(sheet::Download::Changed(raw, next))
+            })
+        }
+    }
+
+    #[actix_web::test]
+    async fn data_etag_and_body_follow_each_refresh() {
+        let dir = scratch_dir();
+        let (state, source) = always_refetching_state(&dir, &[]);
+        let app = test_app!(state);
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        let first_etag = resp.headers().get(header::ETAG).unwrap().clone();
+        test::read_body(resp).await;
+
+        source
+            .0
+            .lock()
+            .unwrap()
+            .push_str("US,01851,Lowell,Middlesex,MA\n");
+        let req = test::TestRequest::get()
+            .uri("/data")
+            .insert_header((header::IF_NONE_MATCH, first_etag.clone()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        assert_ne!(etag, first_etag);
+        let body = test::read_body(resp).await;
+        let dataset = state.dataset().await.unwrap();
+        assert_eq!(dataset.records.len(), 3);
+        assert_eq!(body, serde_json::to_vec(&dataset.records).unwrap());
+        assert_eq!(dataset.etag, etag.to_str().unwrap());
+    }
+
+    #[actix_web::test]
+    async fn unchanged_sheets_are_not_downloaded_again() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            cache_ttl: std::time::Duration::ZERO,
+            stale_while_revalidate: std::time::Duration::ZERO,
+            ..Config::default()
+        };
+        let source = VersionedSheetSource::default();
+        *source.csv.lock().unwrap() = SHEET_CSV.to_string();
+        let state = web::Data::new(AppState::with_source(config, Box::new(source.clone())));
+        let app = test_app!(state);
+        let downloads = || source.downloads.load(std::sync::atomic::Ordering::SeqCst);
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "sheet");
+        assert_eq!(downloads(), 1);
+        assert!(dir.join("data_cache.validators.json").exists());
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "cache");
+        assert_eq!(downloads(), 1);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        source
+            .csv
+            .lock()
+            .unwrap()
+            .push_str("US,01851,Lowell,Middlesex,MA\n");
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.headers().get(DATA_SOURCE_HEADER).unwrap(), "sheet");
+        assert_eq!(downloads(), 2);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_array().unwrap().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn slow_requests_are_reported_past_the_threshold() {
+        let threshold = Duration::from_millis(2000);
+        let fast = slow_request_warning("GET", "/data", 200, Duration::from_millis(5), threshold);
+        assert!(fast.is_none());
+        let slow =
+            slow_request_warning("GET", "/data", 200, Duration::from_millis(2500), threshold);
+        assert_eq!(
+            slow.unwrap(),
+            "Warning: slow request GET /data -> 200 took 2500 ms (threshold 2000 ms)"
+        );
+        let disabled =
+            slow_request_warning("GET", "/data", 200, Duration::from_secs(60), Duration::ZERO);
+        assert!(disabled.is_none());
+    }
+
+    #[actix_web::test]
+    async fn random_picks_a_record_in_scope() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let pick = |uri: &'static str| test::TestRequest::get().uri(uri).to_request();
+        let first: Value =
+            test::read_body_json(test::call_service(&app, pick("/random?seed=7")).await).await;
+        let again: Value =
+            test::read_body_json(test::call_service(&app, pick("/random?seed=7")).await).await;
+        assert_eq!(first, again);
+        assert!(first["City"] == "St. Petersburg" || first["City"] == "Lowell");
+
+        let body: Value =
+            test::read_body_json(test::call_service(&app, pick("/random?state=ma")).await).await;
+        assert_eq!(body["City"], "Lowell");
+        let resp = test::call_service(&app, pick("/random?state=TX")).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn recent_lists_the_newest_records() {
+        let csv = "Country,Zip,City,County,State,Effective Date\n\
+                   US,33701,St. Petersburg,Pinellas,FL,1/5/2025\n\
+                   US,01850,Lowell,Middlesex,MA,2024-03-01\n\
+                   US,33755,Clearwater,Pinellas,FL,\n";
+        let cities = |body: &Value| -> Vec<String> {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["City"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let get = |uri: &'static str| test::TestRequest::get().uri(uri).to_request();
+
+        // Without first-seen tracking, dated records by effective date.
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let source = FakeSheetSource(csv.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/recent")).await).await;
+        assert_eq!(cities(&body), ["St. Petersburg", "Lowell"]);
+        assert!(body[0].get("first_seen").is_none());
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/recent?limit=1")).await).await;
+        assert_eq!(cities(&body), ["St. Petersburg"]);
+
+        // With it, records already known sort after the ones new in this load.
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            first_seen: true,
+            ..Config::default()
+        };
+        let st_pete =
+            json!({"Zip": "33701", "City": "St. Petersburg", "County": "Pinellas", "State": "FL"});
+        let known = json!({ ids::record_id(&st_pete): "2020-01-01T00:00:00Z" });
+        let path = config.first_seen_path();
+        std::fs::write(&path, known.to_string()).unwrap();
+        let source = FakeSheetSource(csv.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/recent")).await).await;
+        assert_eq!(cities(&body), ["Lowell", "Clearwater", "St. Petersburg"]);
+        assert_eq!(body[2]["first_seen"], "2020-01-01T00:00:00Z");
+        assert_eq!(body[0]["first_seen"], body[1]["first_seen"]);
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.as_object().unwrap().len(), 3);
+        assert_eq!(saved[ids::record_id(&st_pete)], "2020-01-01T00:00:00Z");
+    }
+
+    #[actix_web::test]
+    async fn state_filter_takes_any_code_in_the_data() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let csv = "Country,Zip,City,County,State\n\
+                   US,33701,St. Petersburg,Pinellas,FL\n\
+                   CA,M5V,Toronto,,ON\n";
+        let source = FakeSheetSource(csv.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get()
+            .uri("/data?state=on,TX")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(IGNORED_STATES_HEADER).unwrap(), "TX");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["City"], "Toronto");
+    }
+
+    #[actix_web::test]
+    async fn by_zip_matches_the_exact_zero_padded_zip() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        // The sheet lost Lowell's leading zero, 01850 also appears inside a
+        // city name, and 33702 is a near miss for 33701.
+        let csv = "Country,Zip,City,County,State\n\
+                   US,1850,Lowell,Middlesex,MA\n\
+                   US,33702,Not 01850,Pinellas,FL\n";
+        let source = FakeSheetSource(csv.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get()
+            .uri("/data/by-zip/01850")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["zip"], "01850");
+        assert_eq!(body["banned"], true);
+        assert_eq!(body["records"].as_array().unwrap().len(), 1);
+        assert_eq!(body["records"][0]["City"], "Lowell");
+
+        let req = test::TestRequest::get()
+            .uri("/data/by-zip/33701")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["banned"], false);
+        assert_eq!(body["records"], json!([]));
+
+        for bad in ["1850", "018500", "0185x"] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/data/by-zip/{}", bad))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", bad);
+        }
+    }
+
+    #[actix_web::test]
+    async fn compare_checks_many_zips_in_input_order() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            max_compare_zips: 4,
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(
+            config,
+            Box::new(FakeSheetSource(SHEET_CSV.to_string())),
+        ));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get()
+            .uri("/compare?zips=1850,%2090210,33701-1234,abc")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        let results = body.as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0]["input"], "1850");
+        assert_eq!(results[0]["zip"], "01850");
+        assert_eq!(results[0]["banned"], true);
+        assert_eq!(results[0]["records"][0]["City"], "Lowell");
+        assert_eq!(results[1]["zip"], "90210");
+        assert_eq!(results[1]["banned"], false);
+        assert_eq!(results[1]["records"], json!([]));
+        assert_eq!(results[2]["zip"], "33701");
+        assert_eq!(results[2]["banned"], true);
+        assert_eq!(results[3]["zip"], Value::Null);
+        assert_eq!(results[3]["banned"], false);
+
+        for (uri, code) in [
+            ("/compare", "missing_parameter"),
+            ("/compare?zips=,", "missing_parameter"),
+            ("/compare?zips=1,2,3,4,5", "too_many_zips"),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 400, "{}", uri);
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"]["code"], code, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn zips_lists_distinct_padded_zips() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let csv = format!(
+            "{}US,1850,Lowell,Middlesex,MA\nUS,33701-1234,St. Petersburg,Pinellas,FL\nUS,TBD,Ocala,Marion,FL\n",
+            SHEET_CSV
+        );
+        let state = web::Data::new(AppState::with_source(
+            config,
+            Box::new(FakeSheetSource(csv)),
+        ));
+        let app = test_app!(state);
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/zips").to_request()).await;
+        assert_eq!(resp.headers().get(TOTAL_COUNT_HEADER).unwrap(), "2");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!(["01850", "33701"]));
+
+        let req = test::TestRequest::get()
+            .uri("/zips?state=fl&include_invalid=true")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body, json!(["33701", "TBD"]));
+    }
+
+    #[actix_web::test]
+    async fn page_and_api_are_served_over_h2c() {
+        let dir = scratch_dir();
+        let state = test_state(&dir);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = HttpServer::new(move || App::new().app_data(state.clone()).configure(routes))
+            .workers(1)
+            .listen_auto_h2c(listener)
+            .unwrap()
+            .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let h2 = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+        for path in ["/", "/data"] {
+            let resp = h2
+                .get(format!("http://{}{}", addr, path))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.version(), reqwest::Version::HTTP_2, "{}", path);
+            assert_eq!(resp.status(), 200, "{}", path);
+        }
+        let resp = reqwest::get(format!("http://{}/data", addr)).await.unwrap();
+        assert_eq!(resp.version(), reqwest::Version::HTTP_11);
+        assert_eq!(
+            resp.json::<Value>()
+                .await
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        handle.stop(true).await;
+    }
+
+    #[actix_web::test]
+    async fn ping_answers_without_loading_data() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let state = web::Data::new(AppState::with_source(config, Box::new(DownSheetSource)));
+        let app = test_app!(state);
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/ping").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(test::read_body(resp).await, "pong");
+        assert!(state.loaded_dataset().is_none());
+        assert!(!dir.join("data_cache.json").exists());
+    }
+
+    #[actix_web::test]
+    async fn diff_compares_with_an_archived_snapshot() {
+        let dir = scratch_dir();
+        let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+        // Archiving is off by default.
+        let app = test_app!(test_state(&dir));
+        test::call_service(&app, get("/data".to_string())).await;
+        assert!(!dir.join("snapshots").exists());
+
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            cache_ttl: std::time::Duration::ZERO,
+            snapshot_retention_days: 90,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        // Loading the sheet archives today's snapshot.
+        test::call_service(&app, get("/data".to_string())).await;
+        let today = dates::format_rfc3339(std::time::SystemTime::now())[..10].to_string();
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/snapshots".to_string())).await)
+                .await;
+        assert_eq!(body, json!([today]));
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get(format!("/diff/{}", today))).await)
+                .await;
+        assert_eq!(body["added"], json!([]));
+        assert_eq!(body["removed"], json!([]));
+
+        let snapshot_dir = dir.join("snapshots").to_string_lossy().into_owned();
+        let old = [
+            json!({"Zip": "01850", "City": "Lowell", "County": "Middlesex", "State": "MA"}),
+            json!({"Zip": "36602", "City": "Mobile", "County": "Mobile", "State": "AL"}),
+        ];
+        snapshot::save(&snapshot_dir, "2025-01-01", &old)
+            .await
+            .unwrap();
+        let body: Value = test::read_body_json(
+            test::call_service(&app, get("/diff/2025-01-01".to_string())).await,
+        )
+        .await;
+        assert_eq!(body["since"], "2025-01-01");
+        assert_eq!(body["added"].as_array().unwrap().len(), 1);
+        assert_eq!(body["added"][0]["City"], "St. Petersburg");
+        assert_eq!(body["removed"], json!([old[1]]));
+
+        let resp = test::call_service(&app, get("/diff/2024-01-01".to_string())).await;
+        assert_eq!(resp.status(), 404);
+        let resp = test::call_service(&app, get("/diff/yesterday".to_string())).await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn parse_info_describes_the_last_sheet_parse() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+        let req = || test::TestRequest::get().uri("/parse-info").to_request();
+
+        assert_eq!(test::call_service(&app, req()).await.status(), 404);
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        let body: Value = test::read_body_json(test::call_service(&app, req()).await).await;
+        assert_eq!(body["delimiter"], ",");
+        assert_eq!(body["header_row"], 2);
+        assert_eq!(body["preamble_rows"], 1);
+        assert_eq!(body["records"], 2);
+        assert_eq!(body["duplicate_rows"], 0);
+        assert!(body["parsed_at"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn health_reports_schema_drift_between_refreshes() {
+        let dir = scratch_dir();
+        let (state, source) = always_refetching_state(&dir, &[]);
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["status"], "starting");
+
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        *source.0.lock().unwrap() =
+            "Country,Zip,City,State,Notes\nUS,33701,St. Petersburg,FL,x\n".to_string();
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["records"], 1);
+        assert_eq!(body["schema_drift"]["added"], json!(["Notes"]));
+        assert_eq!(body["schema_drift"]["removed"], json!(["County"]));
+    }
+
+    #[actix_web::test]
+    async fn an_emptied_sheet_keeps_the_previous_cache() {
+        let dir = scratch_dir();
+        let (state, source) = always_refetching_state(&dir, &[]);
+        let app = test_app!(state);
+        let health_req = || test::TestRequest::get().uri("/health").to_request();
+
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        *source.0.lock().unwrap() = "Country,Zip,City,County,State\n".to_string();
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        let cached = std::fs::read_to_string(dir.join("data_cache.json")).unwrap();
+        assert!(cached.contains("Pinellas"));
+        let body: Value = test::read_body_json(test::call_service(&app, health_req()).await).await;
+        assert!(body["rejected_refresh"]["reason"]
+            .as_str()
+            .unwrap()
+            .contains("from 2 to 0 records"));
+
+        // A sheet that looks right again is accepted and clears the warning.
+        *source.0.lock().unwrap() = SHEET_CSV.to_string();
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        let body: Value = test::read_body_json(test::call_service(&app, health_req()).await).await;
+        assert_eq!(body["rejected_refresh"], Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn misaligned_rows_are_counted_and_can_refuse_a_refresh() {
+        let dir = scratch_dir();
+        let (state, source) = always_refetching_state(&dir, &[]);
+        let app = test_app!(state);
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        // One of three rows was exported with semicolons.
+        let mixed = format!("{}US;33755;Clearwater;Pinellas;FL\n", SHEET_CSV);
+
+        test::call_service(&app, get("/data")).await;
+        *source.0.lock().unwrap() = mixed.clone();
+        let body: Value = test::read_body_json(test::call_service(&app, get("/data")).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/parse-info")).await).await;
+        assert_eq!(body["misaligned_rows"], 1);
+        assert_eq!(body["records"], 3);
+
+        // Over the limit the previous cache is kept, and the count still shows.
+        *source.0.lock().unwrap() = SHEET_CSV.to_string();
+        test::call_service(&app, get("/data")).await;
+        state.replace_config(Config {
+            max_misaligned_pct: 25.0,
+            ..(*state.config()).clone()
+        });
+        *source.0.lock().unwrap() = mixed;
+        let body: Value = test::read_body_json(test::call_service(&app, get("/data")).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/parse-info")).await).await;
+        assert_eq!(body["misaligned_rows"], 1);
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/health")).await).await;
+        assert_eq!(
+            body["rejected_refresh"]["reason"],
+            "1 of 3 rows don't match the header (33%, over the 25% limit)"
+        );
+    }
+
+    #[actix_web::test]
+    async fn losing_a_required_column_fails_the_refresh_and_keeps_the_cache() {
+        let dir = scratch_dir();
+        let (state, source) = always_refetching_state(&dir, &["County"]);
+        let app = test_app!(state);
+
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        *source.0.lock().unwrap() =
+            "Country,Zip,City,State\nUS,33701,St. Petersburg,FL\n".to_string();
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert_eq!(resp.status(), 502);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "sheet_schema_mismatch");
+
+        let cached = std::fs::read_to_string(dir.join("data_cache.json")).unwrap();
+        assert!(cached.contains("Pinellas"));
+        assert_eq!(state.loaded_dataset().unwrap().records.len(), 2);
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(
+            body["rejected_refresh"]["reason"],
+            "Sheet is missing required column(s): County"
+        );
+        assert_eq!(
+            body["last_error"]["message"],
+            "Sheet is missing required column(s): County"
+        );
+
+        // The next successful load clears the error.
+        *source.0.lock().unwrap() = SHEET_CSV.to_string();
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["last_error"], Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn stale_data_is_served_while_revalidating() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            cache_ttl: std::time::Duration::ZERO,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let status =
+            |resp: &ServiceResponse<_>| resp.headers().get(CACHE_STATUS_HEADER).unwrap().clone();
+        let req = test::TestRequest::get().uri("/data").to_request();
+        assert_eq!(status(&test::call_service(&app, req).await), "fresh");
+        // With a zero TTL the loaded data is immediately stale, so the next
+        // request gets it right away and kicks off a background refresh.
+        let req = test::TestRequest::get().uri("/data").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(status(&resp), "revalidating");
+    }
+
+    #[actix_web::test]
+    async fn cache_info_reports_without_fetching() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get().uri("/cache/info").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["record_count"], Value::Null);
+        assert_eq!(body["size_bytes"], Value::Null);
+        assert_eq!(body["sources"], json!([]));
+        assert!(!dir.join("data_cache.json").exists());
+
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        let req = test::TestRequest::get().uri("/cache/info").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body["record_count"], 2);
+        assert_eq!(body["refreshing"], false);
+        assert!(body["size_bytes"].as_u64().unwrap() > 0);
+        assert!(body["last_modified"].is_string());
+        assert_eq!(body["sources"][0]["name"], "sheet");
+        assert_eq!(body["sources"][0]["record_count"], 2);
+        assert!(body["sources"][0]["fetched_at"].is_string());
+    }
+
+    #[actix_web::test]
+    async fn icons_are_served_and_unknown_names_quoted_short() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+        let req = test::TestRequest::get()
+            .uri("/icons/icon-192.png")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(content_type(&resp), "image/png");
+
+        let name = "x".repeat(500);
+        let req = test::TestRequest::get()
+            .uri(&format!("/icons/{}", name))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404);
+        let body: Value = test::read_body_json(resp).await;
+        let message = body["error"]["message"].as_str().unwrap();
+        assert!(message.starts_with("No icon named \"xxx"));
+        assert!(message.len() < 100, "{}", message);
+    }
+
+    #[actix_web::test]
+    async fn oversized_queries_and_bodies_are_refused() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            admin_token: Some("s3cret".to_string()),
+            max_query_bytes: 64,
+            max_body_bytes: 32,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+
+        let zips = vec!["33701"; 20].join(",");
+        let req = test::TestRequest::get()
+            .uri(&format!("/compare?zips={}", zips))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 414);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "uri_too_long");
+
+        let req = test::TestRequest::post()
+            .uri("/admin/reload-config")
+            .insert_header((header::AUTHORIZATION, "Bearer s3cret"))
+            .insert_header((header::CONTENT_LENGTH, "33"))
+            .set_payload("x".repeat(33))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 413);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "payload_too_large");
+
+        let req = test::TestRequest::get()
+            .uri("/compare?zips=33701,01850")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        // JSON bodies are held to the same limit, declared or not.
+        let req = test::TestRequest::post()
+            .uri("/compare")
+            .set_json(json!({ "zips": ["33701", "01850", "90210"] }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 413);
+        let mut req = test::TestRequest::post()
+            .uri("/compare")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(json!({ "zips": ["33701", "01850", "90210"] }).to_string())
+            .to_request();
+        req.headers_mut().remove(header::CONTENT_LENGTH);
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 413);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "payload_too_large");
+        let req = test::TestRequest::post()
+            .uri("/compare")
+            .set_json(json!({ "zips": ["1850"] }))
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(body[0]["zip"], "01850");
+        assert_eq!(body[0]["banned"], true);
+    }
+
+    #[actix_web::test]
+    async fn rate_limiting_is_opt_in_and_spares_probes() {
+        let get = |uri: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .peer_addr("203.0.113.7:4000".parse().unwrap())
+                .to_request()
+        };
+
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+        for _ in 0..50 {
+            assert_eq!(test::call_service(&app, get("/ping")).await.status(), 200);
+            assert_eq!(test::call_service(&app, get("/states")).await.status(), 200);
+        }
+
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            rate_limit: 0.5,
+            rate_burst: 2.0,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        for _ in 0..2 {
+            assert_eq!(test::call_service(&app, get("/states")).await.status(), 200);
+        }
+        let resp = test::call_service(&app, get("/states")).await;
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "2");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "rate_limited");
+        for uri in ["/ping", "/health", "/metrics.json"] {
+            assert_ne!(
+                test::call_service(&app, get(uri)).await.status(),
+                429,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn ttl_jitter_must_be_a_percentage() {
+        let dir = scratch_dir();
+        let config_file = dir.join("gkc.env");
+        let load = |contents: &str| {
+            std::fs::write(&config_file, contents).unwrap();
+            Config::load(Some(config_file.to_string_lossy().into_owned())).unwrap()
+        };
+        assert_eq!(
+            load("GKC_CACHE_TTL_JITTER_PCT=12.5\n").cache_ttl_jitter_pct,
+            12.5
+        );
+        for invalid in ["NaN", "inf", "-5", "250"] {
+            let config = load(&format!("GKC_CACHE_TTL_JITTER_PCT={}\n", invalid));
+            assert_eq!(config.cache_ttl_jitter_pct, 0.0, "{}", invalid);
+            assert_eq!(
+                config.effective()["GKC_CACHE_TTL_JITTER_PCT"]["source"],
+                "default (invalid)"
+            );
+        }
+        // Loads still work with a long TTL and jitter.
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            cache_ttl: Duration::MAX,
+            cache_ttl_jitter_pct: 50.0,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        for _ in 0..2 {
+            let req = test::TestRequest::get().uri("/data").to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+    }
+
+    #[actix_web::test]
+    async fn rate_settings_must_be_finite_and_non_negative() {
+        let dir = scratch_dir();
+        let config_file = dir.join("gkc.env");
+        let load = |contents: &str| {
+            std::fs::write(&config_file, contents).unwrap();
+            Config::load(Some(config_file.to_string_lossy().into_owned())).unwrap()
+        };
+        let config = load("GKC_RATE_LIMIT=0.5\nGKC_RATE_BURST=5\n");
+        assert_eq!((config.rate_limit, config.rate_burst), (0.5, 5.0));
+        for invalid in ["NaN", "inf", "-1"] {
+            let config = load(&format!(
+                "GKC_RATE_LIMIT={0}\nGKC_RATE_BURST={0}\n",
+                invalid
+            ));
+            assert_eq!((config.rate_limit, config.rate_burst), (0.0, 30.0));
+            let effective = config.effective();
+            for name in ["GKC_RATE_LIMIT", "GKC_RATE_BURST"] {
+                assert_eq!(effective[name]["source"], "default (invalid)", "{}", name);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn refresh_guards_must_be_percentages() {
+        let dir = scratch_dir();
+        let config_file = dir.join("gkc.env");
+        let load = |contents: &str| {
+            std::fs::write(&config_file, contents).unwrap();
+            Config::load(Some(config_file.to_string_lossy().into_owned())).unwrap()
+        };
+        assert_eq!(
+            load("GKC_MAX_RECORD_DROP_PCT=50\n").max_record_drop_pct,
+            50.0
+        );
+        assert_eq!(load("GKC_MAX_MISALIGNED_PCT=5\n").max_misaligned_pct, 5.0);
+        for invalid in ["NaN", "inf", "-1", "101"] {
+            let config = load(&format!(
+                "GKC_MAX_RECORD_DROP_PCT={0}\nGKC_MAX_MISALIGNED_PCT={0}\n",
+                invalid
+            ));
+            assert_eq!(config.max_record_drop_pct, 90.0, "{}", invalid);
+            assert_eq!(config.max_misaligned_pct, 100.0, "{}", invalid);
+            let effective = config.effective();
+            for name in ["GKC_MAX_RECORD_DROP_PCT", "GKC_MAX_MISALIGNED_PCT"] {
+                assert_eq!(effective[name]["source"], "default (invalid)", "{}", name);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn shutdown_timeout_is_read_in_seconds() {
+        let dir = scratch_dir();
+        let config_file = dir.join("gkc.env");
+        let load = |contents: &str| {
+            std::fs::write(&config_file, contents).unwrap();
+            Config::load(Some(config_file.to_string_lossy().into_owned())).unwrap()
+        };
+        assert_eq!(
+            load("GKC_SHUTDOWN_TIMEOUT=5\n").shutdown_timeout,
+            Duration::from_secs(5)
+        );
+        // Zero closes connections right away.
+        assert_eq!(
+            load("GKC_SHUTDOWN_TIMEOUT= 0 \n").shutdown_timeout,
+            Duration::ZERO
+        );
+        for invalid in ["GKC_SHUTDOWN_TIMEOUT=2.5\n", "GKC_SHUTDOWN_TIMEOUT=-1\n"] {
+            let config = load(invalid);
+            assert_eq!(
+                config.shutdown_timeout,
+                Duration::from_secs(30),
+                "{}",
+                invalid
+            );
+            assert_eq!(
+                config.effective()["GKC_SHUTDOWN_TIMEOUT"]["source"],
+                "default (invalid)"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn binds_ipv4_and_ipv6_side_by_side() {
+        let dir = scratch_dir();
+        let config_file = dir.join("gkc.env");
+        std::fs::write(&config_file, "GKC_BIND=0.0.0.0:0, [::]:0, localhost\n").unwrap();
+        let config = Config::load(Some(config_file.to_string_lossy().into_owned())).unwrap();
+        let expected: [SocketAddr; 2] = ["0.0.0.0:0".parse().unwrap(), "[::]:0".parse().unwrap()];
+        assert_eq!(config.bind, expected);
+
+        // Hosts and containers without IPv6 can't bind either address.
+        if std::net::TcpListener::bind("[::1]:0").is_err()
+            || std::net::TcpListener::bind("[::]:0").is_err()
+        {
+            eprintln!("skipping: IPv6 isn't available here");
+            return;
+        }
+        let v4 = listen_on(config.bind[0], &config.bind).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        let both = [
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, port)),
+        ];
+        let v6 = listen_on(both[1], &both).unwrap();
+        assert_eq!(v6.local_addr().unwrap(), both[1]);
+    }
+
+    #[actix_web::test]
+    async fn admin_reload_applies_live_settings_and_reports_the_rest() {
+        let dir = scratch_dir();
+        let config_file = dir.join("gkc.env");
+        let cache_file = dir.join("data_cache.json").to_string_lossy().into_owned();
+        std::fs::write(
+            &config_file,
+            format!(
+                "# tuned live\nGKC_CACHE_FILE={}\nGKC_ADMIN_TOKEN=s3cret\n\
+                 GKC_CACHE_TTL_SECS=60\nGKC_DROP_COLUMNS=Country,column_0,City\nGKC_RATE_LIMIT=1\n",
+                cache_file
+            ),
+        )
+        .unwrap();
+        let config = Config {
+            config_file: Some(config_file.to_string_lossy().into_owned()),
+            cache_file,
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        for authorization in [None, Some("Bearer wrong"), Some("s3cret")] {
+            let mut req = test::TestRequest::post().uri("/admin/reload-config");
+            if let Some(value) = authorization {
+                req = req.insert_header((header::AUTHORIZATION, value));
+            }
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), 401, "{:?}", authorization);
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"]["code"], "unauthorized");
+        }
+        assert_eq!(state.config().cache_ttl, Config::default().cache_ttl);
+
+        let req = test::TestRequest::post()
+            .uri("/admin/reload-config")
+            .insert_header((header::AUTHORIZATION, "Bearer s3cret"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body,
+            json!({ "reloaded": true, "ignored": ["GKC_RATE_LIMIT"] })
+        );
+        let config = state.config();
+        assert_eq!(config.cache_ttl, Duration::from_secs(60));
+        assert!(config.is_dropped("City"));
+        assert_eq!(config.rate_limit, Config::default().rate_limit);
+
+        // The next load processes the sheet with the new drop-list.
+        let body: Value = test::read_body_json(
+            test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await,
+        )
+        .await;
+        assert!(body[0].get("City").is_none());
+
+        let app = test_app!(test_state(&dir));
+        let req = test::TestRequest::post()
+            .uri("/admin/reload-config")
+            .insert_header((header::AUTHORIZATION, "Bearer s3cret"))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn admin_config_shows_effective_settings_and_their_sources() {
+        let dir = scratch_dir();
+        let config_file = dir.join("gkc.conf");
+        std::fs::write(
+            &config_file,
+            format!(
+                "GKC_CACHE_FILE={}\nGKC_ADMIN_TOKEN=s3cret\nGKC_CACHE_TTL_SECS=60\n\
+                 GKC_SHEET_URL=https://me:pw@example.com/sheet.csv?key=abc\nGKC_HEADER_ROW=0\n\
+                 GKC_MAX_RECORDS=lots\nGKC_REFRESH_CRON=every morning\n",
+                dir.join("data_cache.json").display()
+            ),
+        )
+        .unwrap();
+        let config = Config::load(Some(config_file.to_string_lossy().into_owned())).unwrap();
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+
+        let req = test::TestRequest::get().uri("/admin/config").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+        let req = test::TestRequest::get()
+            .uri("/admin/config")
+            .insert_header((header::AUTHORIZATION, "Bearer s3cret"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(header::CACHE_CONTROL).unwrap(), NO_STORE);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(
+            body["GKC_CACHE_TTL_SECS"],
+            json!({ "value": 60, "source": "file" })
+        );
+        assert_eq!(
+            body["GKC_RATE_LIMIT"],
+            json!({ "value": 0.0, "source": "default" })
+        );
+        assert_eq!(body["GKC_CONFIG_FILE"]["source"], "environment");
+        // Rows are numbered from 1, so 0 is ignored.
+        // Values that were set but couldn't be used don't pass for the file's.
+        assert_eq!(
+            body["GKC_HEADER_ROW"],
+            json!({ "value": null, "source": "default (invalid)" })
+        );
+        assert_eq!(
+            body["GKC_MAX_RECORDS"],
+            json!({ "value": 10_000, "source": "default (invalid)" })
+        );
+        assert_eq!(
+            body["GKC_REFRESH_CRON"],
+            json!({ "value": null, "source": "default (invalid)" })
+        );
+        assert_eq!(
+            body["GKC_ADMIN_TOKEN"],
+            json!({ "value": "[redacted]", "source": "file" })
+        );
+        assert_eq!(
+            body["GKC_SHEET_URL"]["value"],
+            "https://example.com/sheet.csv?[redacted]"
+        );
+        assert!(!body.to_string().contains("s3cret"));
+    }
+
+    #[actix_web::test]
+    async fn raw_csv_serves_the_download_to_admins() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            admin_token: Some("s3cret".to_string()),
+            keep_raw_csv: true,
+            ..Config::default()
+        };
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let state = web::Data::new(AppState::with_source(config, Box::new(source)));
+        let app = test_app!(state);
+        let raw_request = || {
+            test::TestRequest::get()
+                .uri("/data/raw.csv")
+                .insert_header((header::AUTHORIZATION, "Bearer s3cret"))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, raw_request()).await;
+        assert_eq!(resp.status(), 404);
+        test::call_service(&app, test::TestRequest::get().uri("/data").to_request()).await;
+        assert!(dir.join("data_cache.raw.csv").exists());
+
+        let resp = test::call_service(&app, raw_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(content_type(&resp), "text/csv; charset=utf-8");
+        assert_eq!(test::read_body(resp).await, SHEET_CSV.as_bytes());
+
+        let req = test::TestRequest::get().uri("/data/raw.csv").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn supplemental_serves_the_file() {
+        let dir = scratch_dir();
+        std::fs::write(
+            dir.join("supplemental.json"),
+            r#"[{"State": "FL", "tags": ["FL"]}]"#,
+        )
+        .unwrap();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get().uri("/supplemental").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(content_type(&resp), "application/json");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!([{"State": "FL", "tags": ["FL"]}]));
+    }
+
+    #[actix_web::test]
+    async fn supplemental_honors_etag_until_the_file_changes() {
+        let dir = scratch_dir();
+        let file = dir.join("supplemental.json");
+        std::fs::write(&file, r#"[{"State": "FL"}]"#).unwrap();
+        let app = test_app!(test_state(&dir));
+        let get = |etag: Option<&header::HeaderValue>| {
+            let req = test::TestRequest::get().uri("/supplemental");
+            match etag {
+                Some(etag) => req.insert_header((header::IF_NONE_MATCH, etag.clone())),
+                None => req,
+            }
+            .to_request()
+        };
+
+        let resp = test::call_service(&app, get(None)).await;
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        let body = test::read_body(resp).await;
+        assert_eq!(body, r#"[{"State":"FL"}]"#);
+
+        let resp = test::call_service(&app, get(Some(&etag))).await;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(resp.headers().get(header::ETAG), Some(&etag));
+
+        std::fs::write(&file, r#"[{"State": "MA"}]"#).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let resp = test::call_service(&app, get(Some(&etag))).await;
+        assert_eq!(resp.status(), 200);
+        assert_ne!(resp.headers().get(header::ETAG), Some(&etag));
+        let body = test::read_body(resp).await;
+        assert_eq!(body, r#"[{"State":"MA"}]"#);
+    }
+
+    #[actix_web::test]
+    async fn previews_outside_the_image_hosts_are_dropped_and_csp_lists_them() {
+        let dir = scratch_dir();
+        std::fs::write(
+            dir.join("supplemental.json"),
+            r#"[
+                {"title": "allowed", "preview": "https://i.imgur.com/logo.png"},
+                {"title": "subdomain", "preview": "https://cdn.example.org/a.JPG"},
+                {"title": "foreign", "preview": "https://tracker.example.com/pixel.gif"},
+                {"title": "emoji", "preview": "🍃"}
+            ]"#,
+        )
+        .unwrap();
+        let state = test_state(&dir);
+        let app = test_app!(state);
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        // Without GKC_IMG_HOSTS every preview is kept and there is no CSP.
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/supplemental")).await).await;
+        assert_eq!(body[2]["preview"], "https://tracker.example.com/pixel.gif");
+        let resp = test::call_service(&app, get("/")).await;
+        assert!(resp
+            .headers()
+            .get(header::CONTENT_SECURITY_POLICY)
+            .is_none());
+
+        state.replace_config(Config {
+            img_hosts: Some(vec!["i.imgur.com".to_string(), "*.example.org".to_string()]),
+            ..(*state.config()).clone()
+        });
+        let body: Value =
+            test::read_body_json(test::call_service(&app, get("/supplemental")).await).await;
+        assert_eq!(body[0]["preview"], "https://i.imgur.com/logo.png");
+        assert_eq!(body[1]["preview"], "https://cdn.example.org/a.JPG");
+        assert_eq!(body[2], json!({"title": "foreign"}));
+        assert_eq!(body[3]["preview"], "🍃");
+
+        let resp = test::call_service(&app, get("/")).await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_SECURITY_POLICY).unwrap(),
+            "img-src 'self' i.imgur.com *.example.org"
+        );
+    }
+
+    #[actix_web::test]
+    async fn supplemental_tags_are_counted_and_filterable() {
+        let dir = scratch_dir();
+        std::fs::write(
+            dir.join("supplemental.json"),
+            r#"[
+                {"title": "a", "tags": ["FL", "lab-results"]},
+                {"title": "b", "tags": ["Lab-Results", "fl", "FL"]},
+                {"title": "c", "tags": ["MA"]},
+                {"title": "d"}
+            ]"#,
+        )
+        .unwrap();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get()
+            .uri("/supplemental/tags")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(
+            body,
+            json!([
+                {"tag": "FL", "count": 2},
+                {"tag": "lab-results", "count": 2},
+                {"tag": "MA", "count": 1}
+            ])
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/supplemental?tag=LAB-RESULTS")
+            .to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        let titles: Vec<&str> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["a", "b"]);
+    }
+
+    #[actix_web::test]
+    async fn missing_supplemental_file_is_a_500_with_a_useful_message() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get().uri("/supplemental").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 500);
+        assert_eq!(content_type(&resp), "application/json");
+        let body: Value = test::read_body_json(resp).await;
+        let error = body["error"].as_object().unwrap();
+        assert_eq!(error.len(), 2, "unexpected error shape: {}", body);
+        assert_eq!(error["code"], "supplemental_unreadable");
+        let message = error["message"].as_str().unwrap();
+        assert!(
+            message.starts_with("Error reading supplemental JSON file:"),
+            "unexpected message: {}",
+            message
+        );
+    }
+
+    #[actix_web::test]
+    async fn openapi_spec_covers_every_route() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get().uri("/openapi.json").to_request();
+        let spec: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        let documented: Vec<&str> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+
+        // Every `#[get(...)]` / `#[post(...)]` route in this file, read from
+        // the source itself.
+        let registered: Vec<(&str, &str)> = include_str!("main.rs")
+            .lines()
+            .filter_map(|line| {
+                let attribute = line.trim().strip_prefix("#[")?;
+                ["get", "post"].into_iter().find_map(|method| {
+                    let rest = attribute.strip_prefix(method)?.strip_prefix("(\"")?;
+                    Some((method, rest.split('"').next()?))
+                })
+            })
+            .collect();
+        assert!(registered.len() > 10);
+        for (method, path) in &registered {
+            assert!(
+                spec["paths"][path][method].is_object(),
+                "{} {} is missing from the spec",
+                method.to_uppercase(),
+                path
+            );
+        }
+        for path in &documented {
+            assert!(
+                registered.iter().any(|(_, registered)| registered == path),
+                "{} is not a registered route",
+                path
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn capabilities_describe_every_parameter() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let req = test::TestRequest::get().uri("/capabilities").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        let endpoints = body["endpoints"].as_array().unwrap();
+        let data = endpoints.iter().find(|e| e["path"] == "/data").unwrap();
+        let names: Vec<&str> = data["params"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "state",
+                "county",
+                "effective_before",
+                "effective_after",
+                "include_undated",
+                "status",
+                "sort",
+                "order",
+                "envelope",
+                "group_by"
+            ]
+        );
+        assert_eq!(
+            data["params"][6]["values"],
+            json!(["state", "city", "county", "zip", "effective_date"])
+        );
+        assert_eq!(body["global_params"][0]["name"], "pretty");
+
+        // Adding a parameter to a handler's struct without describing it fails here.
+        let all_params = endpoints
+            .iter()
+            .flat_map(|e| e["params"].as_array().unwrap())
+            .chain(body["global_params"].as_array().unwrap());
+        for param in all_params {
+            assert!(
+                param["type"].is_string(),
+                "{} is undescribed",
+                param["name"]
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn capabilities_match_what_the_handlers_do() {
+        let dir = scratch_dir();
+        let config = Config {
+            cache_file: dir.join("data_cache.json").to_string_lossy().into_owned(),
+            supplemental_file: dir.join("supplemental.json").to_string_lossy().into_owned(),
+            regions: true,
+            ..Config::default()
+        };
+        std::fs::write(dir.join("supplemental.json"), "[]").unwrap();
+        let source = FakeSheetSource(SHEET_CSV.to_string());
+        let app = test_app!(web::Data::new(AppState::with_source(
+            config,
+            Box::new(source)
+        )));
+        let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+        let req = test::TestRequest::get().uri("/capabilities").to_request();
+        let body: Value = test::read_body_json(test::call_service(&app, req).await).await;
+        for endpoint in body["endpoints"].as_array().unwrap() {
+            let path = endpoint["path"].as_str().unwrap();
+            // Just enough for the endpoints that need a parameter to answer.
+            let base = match path {
+                "/search" => "q=lowell&",
+                "/compare" => "zips=33701,01850&",
+                "/nearby" | "/check" => "zip=33701&",
+                _ => "",
+            };
+            let plain = test::call_service(&app, get(format!("{}?{}", path, base))).await;
+            assert_eq!(plain.status(), 200, "{}", path);
+            let plain = test::read_body(plain).await;
+            for param in endpoint["params"].as_array().unwrap() {
+                let name = param["name"].as_str().unwrap();
+                // A listed parameter the handler doesn't extract would let a
+                // malformed value through.
+                if ["boolean", "integer", "number", "date", "zip"]
+                    .contains(&param["type"].as_str().unwrap())
+                {
+                    let uri = format!("{}?{}{}=not-a-value", path, base, name);
+                    let resp = test::call_service(&app, get(uri)).await;
+                    assert_eq!(resp.status(), 400, "{} {}", path, name);
+                }
+                // Spelling out the documented default changes nothing.
+                let default = match &param["default"] {
+                    Value::Null => continue,
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                let uri = format!("{}?{}{}={}", path, base, name, default);
+                let resp = test::call_service(&app, get(uri)).await;
+                assert_eq!(resp.status(), 200, "{} {}={}", path, name, default);
+                assert_eq!(
+                    test::read_body(resp).await,
+                    plain,
+                    "{} {}={}",
+                    path,
+                    name,
+                    default
+                );
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn index_serves_the_html_page() {
+        let dir = scratch_dir();
+        let app = test_app!(test_state(&dir));
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(content_type(&resp), "text/html");
+        let body = test::read_body(resp).await;
+        let html = String::from_utf8_lossy(&body);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<header>GKC Kratom Bans"));
+    }
+
+    #[actix_web::test]
+    async fn static_dir_serves_assets_and_replaces_the_page() {
+        let dir = scratch_dir();
+        let assets = dir.join("static");
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::write(assets.join("app.js"), "console.log('hi');").unwrap();
+        let state = test_state(&dir);
+        state.replace_config(Config {
+            static_dir: Some(assets.to_string_lossy().into_owned()),
+            ..(*state.config()).clone()
+        });
+        let app = test_app!(state);
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+
+        let resp = test::call_service(&app, get("/static/app.js")).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(content_type(&resp), "text/javascript");
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=3600"
+        );
+        assert!(resp.headers().contains_key(header::ETAG));
+        assert!(resp.headers().get("X-Robots-Tag").is_none());
+
+        let resp = test::call_service(&app, get("/static/missing.png")).await;
+        assert_eq!(resp.status(), 404);
+        assert!(resp.headers().get(header::CACHE_CONTROL).is_none());
+        let resp = test::call_service(&app, get("/static/../data_cache.json")).await;
+        assert_eq!(resp.status(), 404);
+
+        // Without an index.html at startup the built-in page is kept, even
+        // if one appears later.
+        std::fs::write(
+            assets.join("index.html"),
+            "<html lang=\"{{lang}}\">custom</html>",
+        )
+        .unwrap();
+        let resp = test::call_service(&app, get("/")).await;
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("<header>GKC Kratom Bans"));
+
+        // With one, it wins, as read at startup.
+        let config = Config {
+            static_dir: Some(assets.to_string_lossy().into_owned()),
+            ..(*state.config()).clone()
+        };
+        let state = web::Data::new(AppState::with_source(
+            config,
+            Box::new(FakeSheetSource(SHEET_CSV.to_string())),
+        ));
+        let app = test_app!(state);
+        std::fs::remove_file(assets.join("index.html")).unwrap();
+        let resp = test::call_service(&app, get("/")).await;
+        assert_eq!(content_type(&resp), "text/html");
+        let body = test::read_body(resp).await;
+        assert_eq!(body, "<html lang=\"en\">custom</html>");
+    }
 }
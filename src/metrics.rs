@@ -0,0 +1,83 @@
+use crate::state::AppState;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// ---------------------------------------------------------------------------
+// Request and cache counters (`/metrics.json`)
+// ---------------------------------------------------------------------------
+
+/// Counters since startup. Relaxed ordering is enough: each one is only ever
+/// added to and read on its own.
+#[derive(Default)]
+pub struct Metrics {
+    requests: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    fetch_failures: AtomicU64,
+    /// Requests received but not yet answered (a gauge, not a counter).
+    in_flight: AtomicU64,
+}
+
+impl Metrics {
+    pub fn count_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request answered from the dataset already in memory.
+    pub fn count_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request that had to wait for a load from the disk cache or sheet.
+    pub fn count_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A load that failed, or fell back to the expired cache.
+    pub fn count_fetch_failure(&self) {
+        self.fetch_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Requests being handled right now.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Each counter by name, in the order they are reported.
+    pub fn counters(&self) -> [(&'static str, u64); 4] {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        [
+            ("requests_total", get(&self.requests)),
+            ("cache_hits_total", get(&self.cache_hits)),
+            ("cache_misses_total", get(&self.cache_misses)),
+            ("fetch_failures_total", get(&self.fetch_failures)),
+        ]
+    }
+}
+
+/// Takes a request off the in-flight gauge when it is answered, or dropped
+/// because the client went away or the server shut down.
+struct InFlight(web::Data<AppState>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.metrics().in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Middleware counting every request, including ones turned away, and
+/// tracking how many are in flight.
+pub async fn count_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let _in_flight = req.app_data::<web::Data<AppState>>().map(|state| {
+        state.metrics().count_request();
+        state.metrics().in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(state.clone())
+    });
+    next.call(req).await
+}
@@ -0,0 +1,88 @@
+use serde_json::{Map, Value};
+
+// ---------------------------------------------------------------------------
+// Consistent casing for City and County names from the sheet
+// ---------------------------------------------------------------------------
+
+/// Fields that get title-cased when `GKC_TITLE_CASE` is on.
+pub const TITLE_CASE_FIELDS: [&str; 2] = ["City", "County"];
+
+/// Small words kept lowercase unless they start the name ("Village of the Branch").
+const PARTICLES: [&str; 7] = ["of", "the", "and", "on", "upon", "at", "by"];
+
+/// Uppercase the first letter of an already-lowercased word part, handling
+/// "mc" ("McAllen") and one-letter apostrophe prefixes ("O'Fallon", "D'Iberville").
+fn capitalize(part: &str) -> String {
+    let mut chars = part.chars();
+    let Some(first) = chars.next() else {
+        return String::new();
+    };
+    let rest = chars.as_str();
+    if let Some(tail) = part.strip_prefix("mc").filter(|t| t.len() > 1) {
+        return format!("Mc{}", capitalize(tail));
+    }
+    if let Some(tail) = rest.strip_prefix('\'').filter(|t| !t.is_empty()) {
+        return format!("{}'{}", first.to_uppercase(), capitalize(tail));
+    }
+    format!("{}{}", first.to_uppercase(), rest)
+}
+
+/// Title-case a place name: "st. petersburg" → "St. Petersburg", "CLEARWATER"
+/// → "Clearwater". Words already in mixed case ("DeKalb") are left alone.
+pub fn title_case(name: &str) -> String {
+    name.split_whitespace()
+        .enumerate()
+        .map(|(i, word)| {
+            let mixed =
+                word.chars().any(char::is_lowercase) && word.chars().any(char::is_uppercase);
+            if mixed {
+                return word.to_string();
+            }
+            let lower = word.to_lowercase();
+            if i > 0 && PARTICLES.contains(&lower.as_str()) {
+                return lower;
+            }
+            lower
+                .split('-')
+                .map(capitalize)
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Title-case the City and County of a record, keeping the originals as
+/// `City_raw` / `County_raw` when `keep_raw` is set.
+pub fn title_case_record(record: &mut Map<String, Value>, keep_raw: bool) {
+    for field in TITLE_CASE_FIELDS {
+        let Some(raw) = record.get(field).and_then(Value::as_str).map(String::from) else {
+            continue;
+        };
+        record.insert(field.to_string(), Value::String(title_case(&raw)));
+        if keep_raw {
+            record.insert(format!("{}_raw", field), Value::String(raw));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_cases_tricky_names() {
+        assert_eq!(title_case("st. petersburg"), "St. Petersburg");
+        assert_eq!(title_case("ST PETERSBURG"), "St Petersburg");
+        assert_eq!(title_case("CLEARWATER"), "Clearwater");
+        assert_eq!(title_case("mcallen"), "McAllen");
+        assert_eq!(title_case("MCDONOUGH"), "McDonough");
+        assert_eq!(title_case("o'fallon"), "O'Fallon");
+        assert_eq!(title_case("D'IBERVILLE"), "D'Iberville");
+        assert_eq!(title_case("village of the branch"), "Village of the Branch");
+        assert_eq!(title_case("the colony"), "The Colony");
+        assert_eq!(title_case("winston-salem"), "Winston-Salem");
+        assert_eq!(title_case("DeKalb  county"), "DeKalb County");
+        assert_eq!(title_case("macon"), "Macon");
+    }
+}
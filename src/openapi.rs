@@ -0,0 +1,1035 @@
+use serde_json::{json, Value};
+
+// ---------------------------------------------------------------------------
+// Hand-maintained OpenAPI 3 description of the HTTP API (`/openapi.json`)
+// ---------------------------------------------------------------------------
+
+/// A query parameter of type string.
+fn query_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": "string" }
+    })
+}
+
+/// `?envelope=true`, accepted by every list endpoint.
+fn envelope_param() -> Value {
+    json!({
+        "name": "envelope",
+        "in": "query",
+        "required": false,
+        "description": "Wrap the list as {data, meta} (see the Envelope schema).",
+        "schema": { "type": "boolean" }
+    })
+}
+
+/// Query parameters shared by the list endpoints (filters and sorting).
+fn list_params() -> Vec<Value> {
+    vec![
+        envelope_param(),
+        query_param("state", "Keep records in this state, or in any of several comma-separated states (case-insensitive). Codes in no record match nothing and are listed in X-Ignored-States."),
+        query_param("county", "Keep records in this county."),
+        query_param(
+            "effective_before",
+            "Keep records effective strictly before this YYYY-MM-DD date.",
+        ),
+        query_param(
+            "effective_after",
+            "Keep records effective strictly after this YYYY-MM-DD date.",
+        ),
+        json!({
+            "name": "include_undated",
+            "in": "query",
+            "required": false,
+            "description": "With a date bound set, also keep records without a valid effective date.",
+            "schema": { "type": "boolean" }
+        }),
+        query_param(
+            "status",
+            "Keep records with this ban_status (banned, restricted, pending, unknown), or any of several comma-separated ones. Records without a status count as banned.",
+        ),
+        json!({
+            "name": "sort",
+            "in": "query",
+            "required": false,
+            "schema": { "type": "string", "enum": ["state", "city", "county", "zip", "effective_date"] }
+        }),
+        json!({
+            "name": "order",
+            "in": "query",
+            "required": false,
+            "schema": { "type": "string", "enum": ["asc", "desc"] }
+        }),
+    ]
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } }
+    })
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// An error response with the `{ "error": { "code", "message" } }` body.
+fn error_response(description: &str) -> Value {
+    json_response(description, schema_ref("Error"))
+}
+
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } }
+    })
+}
+
+/// Headers describing truncation by `GKC_MAX_RECORDS`.
+fn list_headers() -> Value {
+    json!({
+        "X-Truncated": { "description": "\"true\" when the list was cut to the record cap.", "schema": { "type": "string" } },
+        "X-Total-Count": { "description": "Number of matching records before truncation.", "schema": { "type": "integer" } },
+        "X-Data-Source": { "description": "Where the data came from: sheet, cache, seed, or embedded.", "schema": { "type": "string" } },
+        "X-Data-Stale": { "description": "\"true\" when the data is older than GKC_MAX_STALE_AGE_SECS because refreshes keep failing; a Warning header says how old.", "schema": { "type": "string" } }
+    })
+}
+
+/// The OpenAPI document for every route registered in `main.rs`.
+pub fn spec() -> Value {
+    let mut search_params = vec![
+        query_param(
+            "q",
+            "Whitespace-separated terms; prefix a term with - to exclude it.",
+        ),
+        json!({
+            "name": "mode",
+            "in": "query",
+            "required": false,
+            "description": "and (default) requires every term; or accepts any.",
+            "schema": { "type": "string", "enum": ["and", "or"] }
+        }),
+        query_param(
+            "regex",
+            "Case-insensitive regular expression used instead of q (max 200 characters).",
+        ),
+        query_param(
+            "fields",
+            "Comma-separated fields the regex is matched against (City, County, Zip, State).",
+        ),
+        json!({
+            "name": "highlight",
+            "in": "query",
+            "required": false,
+            "description": "Wrap each result as {record, matches}.",
+            "schema": { "type": "boolean" }
+        }),
+        query_param(
+            "near",
+            "Zip code; keep records within radius_mi of it, nearest first, with distance_mi.",
+        ),
+        json!({
+            "name": "radius_mi",
+            "in": "query",
+            "required": false,
+            "description": "Radius in miles for near (default 25).",
+            "schema": { "type": "number" }
+        }),
+        json!({
+            "name": "debug_score",
+            "in": "query",
+            "required": false,
+            "description": "Add each result's relevance score (q searches only).",
+            "schema": { "type": "boolean" }
+        }),
+    ];
+    search_params.extend(list_params());
+
+    let mut data_ok = json_response(
+        "Banned-area records, or with group_by an object of record arrays keyed by state or city.",
+        json!({
+            "oneOf": [
+                { "type": "array", "items": schema_ref("Record") },
+                {
+                    "type": "object",
+                    "additionalProperties": { "type": "array", "items": schema_ref("Record") }
+                }
+            ]
+        }),
+    );
+    let mut data_params = list_params();
+    data_params.push(json!({
+        "name": "group_by",
+        "in": "query",
+        "required": false,
+        "description": "Return an object keyed by state code or city name with arrays of records, in key order; records with a blank key are left out.",
+        "schema": { "type": "string", "enum": ["state", "city"] }
+    }));
+    data_ok["headers"] = list_headers();
+    data_ok["headers"]["ETag"] = json!({
+        "description": "Validator for the unfiltered body.",
+        "schema": { "type": "string" }
+    });
+    data_ok["headers"]["Cache-Control"] = json!({
+        "description": "public, with max-age from GKC_HTTP_MAX_AGE_SECS and stale-while-revalidate.",
+        "schema": { "type": "string" }
+    });
+    data_ok["headers"]["X-Cache-Status"] = json!({
+        "description": "fresh, stale, or revalidating.",
+        "schema": { "type": "string" }
+    });
+
+    let mut search_ok = json_response(
+        "Matching records, or highlighted hits with highlight=true.",
+        json!({
+            "oneOf": [
+                { "type": "array", "items": schema_ref("Record") },
+                { "type": "array", "items": schema_ref("SearchHit") }
+            ]
+        }),
+    );
+    search_ok["headers"] = list_headers();
+    search_ok["headers"]["X-Ungeocoded-Count"] = json!({
+        "description": "With near, records skipped because their zip has no coordinates.",
+        "schema": { "type": "integer" }
+    });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "GKC Kratom Bans",
+            "description": "Banned kratom areas by state, city, county, and zip, sourced from a Google Sheet. Every JSON endpoint also accepts pretty=true to indent its response. Any request may be refused with 414 (uri_too_long) for a query string over GKC_MAX_QUERY_BYTES or 413 (payload_too_large) for a body over GKC_MAX_BODY_BYTES.",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": {
+            "/": {
+                "get": {
+                    "summary": "The interactive HTML page, localized by Accept-Language.",
+                    "responses": { "200": { "description": "HTML page.", "content": { "text/html": {} } } }
+                }
+            },
+            "/data": {
+                "get": {
+                    "summary": "All banned-area records, optionally filtered, sorted, and grouped.",
+                    "parameters": data_params,
+                    "responses": {
+                        "200": data_ok,
+                        "304": { "description": "The If-None-Match ETag is still current." },
+                        "400": error_response("Invalid filter or sort parameter."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed)."),
+                        "503": error_response(
+                            "With GKC_BACKGROUND_LOAD, the first load has not finished yet (code not_ready); see Retry-After."
+                        )
+                    }
+                }
+            },
+            "/search": {
+                "get": {
+                    "summary": "Substring or regex search over City, County, Zip, and State; q results come most relevant first unless sorted.",
+                    "parameters": search_params,
+                    "responses": {
+                        "200": search_ok,
+                        "400": error_response("Invalid parameter or regex."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/search/help": {
+                "get": {
+                    "summary": "The /search query syntax.",
+                    "responses": { "200": json_response("Syntax description.", json!({ "type": "object" })) }
+                }
+            },
+            "/capabilities": {
+                "get": {
+                    "summary": "The query parameters each endpoint accepts, with types, defaults, and allowed values.",
+                    "responses": { "200": json_response("Parameters by endpoint.", json!({
+                        "type": "object",
+                        "properties": {
+                            "endpoints": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "path": { "type": "string" },
+                                        "params": { "type": "array", "items": schema_ref("Capability") }
+                                    }
+                                }
+                            },
+                            "global_params": { "type": "array", "items": schema_ref("Capability") }
+                        }
+                    })) }
+                }
+            },
+            "/data/by-zip/{zip}": {
+                "get": {
+                    "summary": "Records whose zip (normalized to five digits) is exactly this one.",
+                    "parameters": [{
+                        "name": "zip",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string", "pattern": "^[0-9]{5}$" }
+                    }],
+                    "responses": {
+                        "200": json_response("Whether the zip is banned, with its records.", schema_ref("ByZip")),
+                        "400": error_response("The zip is not exactly 5 digits."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/compare": {
+                "get": {
+                    "summary": "Whether each of several zips is banned, with its records.",
+                    "parameters": [{
+                        "name": "zips",
+                        "in": "query",
+                        "required": true,
+                        "description": "Comma-separated zips, normalized to five digits before matching.",
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": json_response(
+                            "One result per input zip, in order.",
+                            json!({ "type": "array", "items": schema_ref("CompareResult") })
+                        ),
+                        "400": error_response("No zips, or more than GKC_MAX_COMPARE_ZIPS."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                },
+                "post": {
+                    "summary": "The same, with the zips in a JSON body for lists too long for a query string.",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "required": ["zips"],
+                                    "properties": {
+                                        "zips": { "type": "array", "items": { "type": "string" } }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": json_response(
+                            "One result per input zip, in order.",
+                            json!({ "type": "array", "items": schema_ref("CompareResult") })
+                        ),
+                        "400": error_response("No zips, more than GKC_MAX_COMPARE_ZIPS, or a malformed body."),
+                        "413": error_response("The body is larger than GKC_MAX_BODY_BYTES."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/zips": {
+                "get": {
+                    "summary": "Every distinct banned zip, zero-padded and sorted.",
+                    "parameters": [
+                        query_param("state", "Only list zips in this state."),
+                        query_param("include_invalid", "true to append zips that aren't 5 digits, as written."),
+                        envelope_param()
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Sorted zip codes.",
+                            "headers": {
+                                "X-Total-Count": { "description": "Number of zips returned.", "schema": { "type": "integer" } }
+                            },
+                            "content": { "application/json": { "schema": { "type": "array", "items": { "type": "string" } } } }
+                        },
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/random": {
+                "get": {
+                    "summary": "One random banned record.",
+                    "parameters": [
+                        query_param("state", "Only pick from this state."),
+                        {
+                            "name": "seed",
+                            "in": "query",
+                            "required": false,
+                            "description": "Makes the pick repeatable for the same data.",
+                            "schema": { "type": "integer", "minimum": 0 }
+                        }
+                    ],
+                    "responses": {
+                        "200": json_response("A random record.", schema_ref("Record")),
+                        "404": error_response("No records in scope."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/recent": {
+                "get": {
+                    "summary": "The newest records: by first-seen time with GKC_FIRST_SEEN, otherwise by effective date.",
+                    "parameters": [
+                        {
+                            "name": "limit",
+                            "in": "query",
+                            "required": false,
+                            "description": "How many records to return, at most GKC_MAX_RECORDS.",
+                            "schema": { "type": "integer", "minimum": 0, "default": 10 }
+                        },
+                        envelope_param()
+                    ],
+                    "responses": {
+                        "200": json_response(
+                            "Newest first; without first-seen tracking, records lacking a valid effective date are left out.",
+                            json!({ "type": "array", "items": schema_ref("Record") })
+                        ),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/nearby": {
+                "get": {
+                    "summary": "Whether a zip, its city, or its county is banned, plus the nearest banned area.",
+                    "parameters": [{
+                        "name": "zip",
+                        "in": "query",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": json_response("The verdict.", schema_ref("Nearby")),
+                        "400": error_response("Missing or malformed zip."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/check": {
+                "get": {
+                    "summary": "The verdict for one zip with how it was reached (exact zip, city, county, or statewide entry) and the matching records.",
+                    "parameters": [{
+                        "name": "zip",
+                        "in": "query",
+                        "required": true,
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": json_response("The verdict.", schema_ref("Check")),
+                        "400": error_response("Missing or malformed zip."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/counties": {
+                "get": {
+                    "summary": "Distinct counties with record counts.",
+                    "parameters": [
+                        query_param("state", "Only count counties in this state."),
+                        envelope_param()
+                    ],
+                    "responses": {
+                        "200": json_response(
+                            "Counties sorted by name.",
+                            json!({ "type": "array", "items": schema_ref("CountyCount") })
+                        ),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/states": {
+                "get": {
+                    "summary": "States with record counts.",
+                    "parameters": [
+                        query_param("by_country", "true to group by country as well as state."),
+                        envelope_param()
+                    ],
+                    "responses": {
+                        "200": json_response(
+                            "States sorted by code.",
+                            json!({ "type": "array", "items": schema_ref("StateCount") })
+                        ),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/choropleth": {
+                "get": {
+                    "summary": "Record counts keyed by state code, with zero entries for every state and DC.",
+                    "responses": {
+                        "200": json_response(
+                            "State code to record count.",
+                            json!({ "type": "object", "additionalProperties": { "type": "integer" } })
+                        ),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/cities": {
+                "get": {
+                    "summary": "Cities with record counts and zips; county-wide bans appear as \"All of X County\" nodes.",
+                    "parameters": [
+                        query_param("state", "Only list cities in this state."),
+                        envelope_param()
+                    ],
+                    "responses": {
+                        "200": json_response(
+                            "County-wide nodes first, then cities sorted by name.",
+                            json!({ "type": "array", "items": schema_ref("CityNode") })
+                        ),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/tree": {
+                "get": {
+                    "summary": "Every record grouped by state and then city.",
+                    "parameters": [envelope_param()],
+                    "responses": {
+                        "200": json_response(
+                            "States sorted by code.",
+                            json!({ "type": "array", "items": schema_ref("StateNode") })
+                        ),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/regions": {
+                "get": {
+                    "summary": "Record counts grouped by Census region, division, and state (only with GKC_REGIONS).",
+                    "parameters": [envelope_param()],
+                    "responses": {
+                        "200": json_response(
+                            "Regions in Census order, listing every state (zero if need be).",
+                            json!({ "type": "array", "items": schema_ref("RegionNode") })
+                        ),
+                        "404": error_response("GKC_REGIONS is unset."),
+                        "500": error_response("The data could not be loaded (`502` instead when the sheet itself failed).")
+                    }
+                }
+            },
+            "/ping": {
+                "get": {
+                    "summary": "Liveness probe; never reads the data or cache.",
+                    "responses": { "200": text_response("Always \"pong\".") }
+                }
+            },
+            "/health": {
+                "get": {
+                    "summary": "Health of the in-memory dataset; never triggers a fetch.",
+                    "responses": { "200": json_response("Health report.", schema_ref("Health")) }
+                }
+            },
+            "/metrics.json": {
+                "get": {
+                    "summary": "Request, cache and fetch counters plus dataset gauges as plain JSON; never triggers a fetch.",
+                    "responses": { "200": json_response("Counters since startup and gauges of the loaded data.", schema_ref("Metrics")) }
+                }
+            },
+            "/admin/reload-config": {
+                "post": {
+                    "summary": "Re-read GKC_CONFIG_FILE and the environment and apply the result without a restart.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(
+                            "The new settings are in effect; startup-only settings that changed are listed under ignored.",
+                            json!({
+                                "type": "object",
+                                "properties": {
+                                    "reloaded": { "type": "boolean" },
+                                    "ignored": { "type": "array", "items": { "type": "string" } }
+                                }
+                            })
+                        ),
+                        "401": error_response("Missing or wrong admin token."),
+                        "404": error_response("GKC_ADMIN_TOKEN is unset, so admin endpoints are disabled."),
+                        "500": error_response("GKC_CONFIG_FILE could not be read; the running settings are unchanged.")
+                    }
+                }
+            },
+            "/admin/config": {
+                "get": {
+                    "summary": "The configuration in effect, each setting with its value and source (default, environment, or file); secrets are redacted.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(
+                            "Settings keyed by variable name.",
+                            json!({
+                                "type": "object",
+                                "additionalProperties": {
+                                    "type": "object",
+                                    "properties": {
+                                        "value": {},
+                                        "source": { "type": "string", "enum": ["default", "environment", "file"] }
+                                    }
+                                }
+                            })
+                        ),
+                        "401": error_response("Missing or wrong admin token."),
+                        "404": error_response("GKC_ADMIN_TOKEN is unset, so admin endpoints are disabled.")
+                    }
+                }
+            },
+            "/data/raw.csv": {
+                "get": {
+                    "summary": "The CSV exactly as last downloaded from the sheet (GKC_KEEP_RAW_CSV); never triggers a fetch.",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": { "description": "The unprocessed sheet export.", "content": { "text/csv": { "schema": { "type": "string" } } } },
+                        "401": error_response("Missing or wrong admin token."),
+                        "404": error_response("Admin endpoints or raw CSV retention are disabled, or nothing has been saved yet."),
+                        "500": error_response("The saved CSV could not be read.")
+                    }
+                }
+            },
+            "/cache/info": {
+                "get": {
+                    "summary": "Disk cache and in-memory dataset metadata; never triggers a fetch.",
+                    "responses": { "200": json_response("Cache metadata.", schema_ref("CacheInfo")) }
+                }
+            },
+            "/snapshots": {
+                "get": {
+                    "summary": "Dates (YYYY-MM-DD) with an archived data snapshot, oldest first.",
+                    "responses": {
+                        "200": json_response("Snapshot dates.", json!({ "type": "array", "items": { "type": "string", "format": "date" } }))
+                    }
+                }
+            },
+            "/diff/{date}": {
+                "get": {
+                    "summary": "Records added and removed since the snapshot archived on a date.",
+                    "parameters": [{
+                        "name": "date",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string", "format": "date" }
+                    }],
+                    "responses": {
+                        "200": json_response(
+                            "The changes since that date.",
+                            json!({
+                                "type": "object",
+                                "properties": {
+                                    "since": { "type": "string", "format": "date" },
+                                    "added": { "type": "array", "items": schema_ref("Record") },
+                                    "removed": { "type": "array", "items": schema_ref("Record") }
+                                }
+                            })
+                        ),
+                        "400": error_response("The date is not YYYY-MM-DD."),
+                        "404": error_response("No snapshot was archived on that date."),
+                        "500": error_response("The data or snapshot could not be loaded.")
+                    }
+                }
+            },
+            "/parse-info": {
+                "get": {
+                    "summary": "What the last parse of the sheet saw; never triggers a fetch.",
+                    "responses": {
+                        "200": json_response("Parse statistics.", schema_ref("ParseInfo")),
+                        "404": error_response("The sheet hasn't been parsed since startup.")
+                    }
+                }
+            },
+            "/export.csv": {
+                "get": {
+                    "summary": "Every record as a CSV download named gkc-bans-YYYYMMDD.csv after the data's date.",
+                    "responses": { "200": { "description": "CSV with a header row of every field, in sheet order.", "content": { "text/csv": { "schema": { "type": "string" } } } } }
+                }
+            },
+            "/supplemental": {
+                "get": {
+                    "summary": "Supplemental links, previews, and tags from the local JSON file.",
+                    "parameters": [query_param("tag", "Only return entries carrying this tag (case-insensitive).")],
+                    "responses": {
+                        "200": json_response("The supplemental file.", json!({})),
+                        "304": { "description": "The If-None-Match ETag is still current." },
+                        "500": error_response("The file could not be read or parsed.")
+                    }
+                }
+            },
+            "/supplemental/tags": {
+                "get": {
+                    "summary": "Every distinct supplemental tag with the number of entries carrying it, most used first.",
+                    "responses": {
+                        "200": json_response(
+                            "Tag counts.",
+                            json!({
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "tag": { "type": "string" },
+                                        "count": { "type": "integer" }
+                                    }
+                                }
+                            })
+                        ),
+                        "500": error_response("The file could not be read or parsed.")
+                    }
+                }
+            },
+            "/i18n": {
+                "get": {
+                    "summary": "Localized UI strings keyed by language code.",
+                    "responses": {
+                        "200": json_response(
+                            "Strings per language.",
+                            json!({ "type": "object", "additionalProperties": schema_ref("Strings") })
+                        )
+                    }
+                }
+            },
+            "/robots.txt": {
+                "get": {
+                    "summary": "Crawler policy; API responses also carry X-Robots-Tag: noindex.",
+                    "responses": { "200": text_response("The robots.txt policy.") }
+                }
+            },
+            "/favicon.ico": {
+                "get": {
+                    "summary": "The favicon.",
+                    "responses": { "200": { "description": "Icon.", "content": { "image/x-icon": {} } } }
+                }
+            },
+            "/icons/{name}": {
+                "get": {
+                    "summary": "App icons referenced by the manifest.",
+                    "parameters": [{
+                        "name": "name",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string", "enum": ["icon-192.png", "icon-512.png"] }
+                    }],
+                    "responses": {
+                        "200": { "description": "PNG icon.", "content": { "image/png": {} } },
+                        "404": { "description": "Unknown icon." }
+                    }
+                }
+            },
+            "/manifest.webmanifest": {
+                "get": {
+                    "summary": "Web app manifest for installing the page.",
+                    "responses": { "200": { "description": "Manifest.", "content": { "application/manifest+json": {} } } }
+                }
+            },
+            "/openapi.json": {
+                "get": {
+                    "summary": "This document.",
+                    "responses": { "200": json_response("OpenAPI 3 document.", json!({ "type": "object" })) }
+                }
+            }
+        },
+        "components": {
+            "securitySchemes": {
+                "adminToken": { "type": "http", "scheme": "bearer", "description": "The value of GKC_ADMIN_TOKEN." }
+            },
+            "schemas": {
+                "Record": {
+                    "type": "object",
+                    "description": "One sheet row keyed by header name. Fields vary with the sheet.",
+                    "properties": {
+                        "id": { "type": "string", "description": "Stable ID: a hash of State, City, County and Zip (case-insensitive), with -2, -3, ... appended to repeats." },
+                        "Zip": { "type": "string" },
+                        "City": { "type": "string" },
+                        "County": { "type": "string" },
+                        "State": { "type": "string" },
+                        "effective_date": { "type": "string", "description": "ISO-8601 when parseable." },
+                        "effective_date_valid": { "type": "boolean", "description": "Present (false) only for unparseable dates." },
+                        "ban_status": { "type": "string", "enum": ["banned", "restricted", "pending", "unknown"], "description": "Normalized from the status column, when the sheet has one." },
+                        "first_seen": { "type": "string", "format": "date-time", "description": "When the record first appeared; only in /recent with GKC_FIRST_SEEN, and not for records already there when tracking started." },
+                        "region": { "type": "string", "description": "Census region of the state; only with GKC_REGIONS." },
+                        "timezone": { "type": "string", "description": "IANA time zone of most of the state; only with GKC_REGIONS." },
+                        "distance_mi": { "type": "number", "description": "Only in /search?near= results." },
+                        "score": { "type": "integer", "description": "Only in /search?debug_score=true results." }
+                    },
+                    "additionalProperties": true
+                },
+                "Capability": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "type": { "type": "string", "description": "string, boolean, integer, number, date, or zip." },
+                        "default": { "description": "Value used when the parameter is omitted; null if none." },
+                        "description": { "type": "string" },
+                        "values": { "type": "array", "items": { "type": "string" }, "description": "Accepted values, when limited." }
+                    }
+                },
+                "SearchHit": {
+                    "type": "object",
+                    "properties": {
+                        "record": schema_ref("Record"),
+                        "matches": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "field": { "type": "string" },
+                                    "start": { "type": "integer", "description": "Character offset." },
+                                    "end": { "type": "integer", "description": "Character offset (exclusive)." }
+                                }
+                            }
+                        }
+                    }
+                },
+                "Check": {
+                    "type": "object",
+                    "properties": {
+                        "zip": { "type": "string" },
+                        "banned": { "type": "boolean", "description": "Whether a matching record has ban_status banned." },
+                        "matched_by": {
+                            "type": "array",
+                            "items": { "type": "string", "enum": ["exact_zip", "city", "county", "state"] }
+                        },
+                        "records": { "type": "array", "items": schema_ref("Record") },
+                        "location": {
+                            "type": "object",
+                            "nullable": true,
+                            "properties": {
+                                "city": { "type": "string" },
+                                "county": { "type": "string" },
+                                "state": { "type": "string" }
+                            }
+                        },
+                        "message": { "type": "string", "description": "The verdict as the page words it." },
+                        "disclaimer": { "type": "string" }
+                    }
+                },
+                "Nearby": {
+                    "type": "object",
+                    "properties": {
+                        "zip": { "type": "string" },
+                        "banned": { "type": "boolean", "description": "The /check verdict: a record with ban_status banned applies to the zip." },
+                        "zip_banned": { "type": "boolean" },
+                        "location": {
+                            "type": "object",
+                            "nullable": true,
+                            "properties": {
+                                "city": { "type": "string" },
+                                "county": { "type": "string" },
+                                "state": { "type": "string" }
+                            }
+                        },
+                        "city_bans": { "type": "integer", "nullable": true },
+                        "county_bans": { "type": "integer", "nullable": true },
+                        "state_bans": { "type": "integer", "nullable": true },
+                        "nearest": {
+                            "type": "object",
+                            "nullable": true,
+                            "properties": {
+                                "record": schema_ref("Record"),
+                                "distance_mi": { "type": "number", "nullable": true }
+                            }
+                        },
+                        "message": { "type": "string" },
+                        "disclaimer": { "type": "string" }
+                    }
+                },
+                "Envelope": {
+                    "type": "object",
+                    "description": "List response with ?envelope=true.",
+                    "properties": {
+                        "data": { "type": "array", "items": {} },
+                        "meta": {
+                            "type": "object",
+                            "properties": {
+                                "total": { "type": "integer", "description": "Matches before truncation." },
+                                "cache_age_seconds": { "type": "integer" },
+                                "source": { "type": "string", "enum": ["sheet", "cache", "seed", "embedded"] },
+                                "generated_at": { "type": "string", "format": "date-time" }
+                            }
+                        }
+                    }
+                },
+                "CountyCount": {
+                    "type": "object",
+                    "properties": {
+                        "county": { "type": "string" },
+                        "count": { "type": "integer" }
+                    }
+                },
+                "ByZip": {
+                    "type": "object",
+                    "properties": {
+                        "zip": { "type": "string" },
+                        "banned": { "type": "boolean" },
+                        "records": { "type": "array", "items": schema_ref("Record") }
+                    }
+                },
+                "CompareResult": {
+                    "type": "object",
+                    "properties": {
+                        "input": { "type": "string" },
+                        "zip": { "type": "string", "nullable": true, "description": "The input zero-padded to five digits, or null if it isn't a zip." },
+                        "banned": { "type": "boolean" },
+                        "records": { "type": "array", "items": schema_ref("Record") }
+                    }
+                },
+                "Error": {
+                    "type": "object",
+                    "properties": {
+                        "error": {
+                            "type": "object",
+                            "properties": {
+                                "code": {
+                                    "type": "string",
+                                    "description": "Stable identifier, e.g. invalid_parameter, not_found, not_ready, rate_limited, upstream_unreachable, sheet_schema_mismatch, supplemental_unreadable."
+                                },
+                                "message": { "type": "string", "description": "Human-readable explanation." }
+                            }
+                        }
+                    }
+                },
+                "StateCount": {
+                    "type": "object",
+                    "properties": {
+                        "country": { "type": "string", "description": "Only with by_country=true." },
+                        "state": { "type": "string" },
+                        "count": { "type": "integer" }
+                    }
+                },
+                "CityNode": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string", "description": "The city, or \"All of X County\" for a county-wide ban." },
+                        "county": { "type": "string" },
+                        "county_wide": { "type": "boolean" },
+                        "count": { "type": "integer" },
+                        "zips": { "type": "array", "items": { "type": "string" } }
+                    }
+                },
+                "StateNode": {
+                    "type": "object",
+                    "properties": {
+                        "state": { "type": "string" },
+                        "count": { "type": "integer" },
+                        "cities": { "type": "array", "items": schema_ref("CityNode") }
+                    }
+                },
+                "RegionNode": {
+                    "type": "object",
+                    "properties": {
+                        "region": { "type": "string" },
+                        "count": { "type": "integer" },
+                        "divisions": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "division": { "type": "string" },
+                                    "count": { "type": "integer" },
+                                    "states": { "type": "object", "additionalProperties": { "type": "integer" } }
+                                }
+                            }
+                        }
+                    }
+                },
+                "Metrics": {
+                    "type": "object",
+                    "properties": {
+                        "requests_total": { "type": "integer", "description": "Requests received, including refused ones." },
+                        "cache_hits_total": { "type": "integer", "description": "Requests answered from the data already in memory." },
+                        "cache_misses_total": { "type": "integer", "description": "Requests that waited for a load from the disk cache or sheet." },
+                        "fetch_failures_total": { "type": "integer", "description": "Loads that failed or fell back to the expired cache." },
+                        "records": { "type": "integer", "nullable": true },
+                        "cache_age_secs": { "type": "integer", "nullable": true, "description": "Age of the loaded data." },
+                        "refreshing": { "type": "boolean" },
+                        "in_flight_requests": { "type": "integer", "description": "Requests being handled right now, this one included." }
+                    }
+                },
+                "Health": {
+                    "type": "object",
+                    "properties": {
+                        "status": { "type": "string", "enum": ["ok", "starting", "degraded"] },
+                        "ready": { "type": "boolean", "description": "Whether data has been loaded; route traffic only once true." },
+                        "stale": { "type": "boolean", "description": "Whether the last refresh failed and the data is older than degraded_after_secs; it is still served." },
+                        "ttl_secs": { "type": "integer", "description": "The cache TTL (GKC_CACHE_TTL_SECS)." },
+                        "degraded_after_secs": { "type": "integer", "description": "Data age past which status is degraded (GKC_MAX_STALE_AGE_SECS, default the TTL or with GKC_REFRESH_CRON the time since its last run more than 10 minutes ago); 0 means never." },
+                        "records": { "type": "integer", "nullable": true },
+                        "data_age_secs": { "type": "integer", "nullable": true },
+                        "source": { "type": "string", "nullable": true },
+                        "refreshing": { "type": "boolean" },
+                        "last_error": {
+                            "type": "object",
+                            "nullable": true,
+                            "description": "Why the last load failed (URL credentials and query strings redacted), until one succeeds.",
+                            "properties": {
+                                "message": { "type": "string" },
+                                "at": { "type": "string", "format": "date-time" }
+                            }
+                        },
+                        "rejected_refresh": {
+                            "type": "object",
+                            "nullable": true,
+                            "description": "The last fetch refused for missing a required column or dropping too many records, until the sheet is accepted again.",
+                            "properties": {
+                                "reason": { "type": "string" },
+                                "detected_at": { "type": "string", "format": "date-time" }
+                            }
+                        },
+                        "schema_drift": {
+                            "type": "object",
+                            "nullable": true,
+                            "description": "The last change in column names between refreshes.",
+                            "properties": {
+                                "added": { "type": "array", "items": { "type": "string" } },
+                                "removed": { "type": "array", "items": { "type": "string" } },
+                                "detected_at": { "type": "string", "format": "date-time" }
+                            }
+                        }
+                    }
+                },
+                "CacheInfo": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "last_modified": { "type": "string", "nullable": true },
+                        "age_secs": { "type": "integer", "nullable": true },
+                        "size_bytes": { "type": "integer", "nullable": true },
+                        "record_count": { "type": "integer", "nullable": true },
+                        "search_cache_hits": { "type": "integer", "nullable": true },
+                        "response_cache_hits": { "type": "integer", "nullable": true },
+                        "refreshing": { "type": "boolean" },
+                        "sources": {
+                            "type": "array",
+                            "description": "Where the loaded records came from, with each source's freshness; empty until data is loaded.",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "name": { "type": "string", "enum": ["sheet", "cache", "seed", "embedded"] },
+                                    "fetched_at": { "type": "string", "format": "date-time" },
+                                    "age_secs": { "type": "integer" },
+                                    "record_count": { "type": "integer" }
+                                }
+                            }
+                        }
+                    }
+                },
+                "ParseInfo": {
+                    "type": "object",
+                    "properties": {
+                        "delimiter": { "type": "string" },
+                        "rows_scanned": { "type": "integer" },
+                        "header_row": { "type": "integer", "nullable": true },
+                        "preamble_rows": { "type": "integer" },
+                        "records": { "type": "integer" },
+                        "skipped_empty": { "type": "integer" },
+                        "skipped_comment": { "type": "integer" },
+                        "duplicate_rows": { "type": "integer" },
+                        "empty_columns_dropped": { "type": "integer" },
+                        "misaligned_rows": { "type": "integer", "description": "Data rows with more or fewer fields than the header." },
+                        "parsed_at": { "type": "string", "format": "date-time" }
+                    }
+                },
+                "Strings": {
+                    "type": "object",
+                    "properties": {
+                        "header": { "type": "string" },
+                        "disclaimer": { "type": "string" }
+                    }
+                }
+            }
+        }
+    })
+}
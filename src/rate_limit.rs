@@ -0,0 +1,153 @@
+use crate::errors::error_body;
+use crate::state::AppState;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// ---------------------------------------------------------------------------
+// Per-IP token-bucket rate limiting
+// ---------------------------------------------------------------------------
+
+/// Paths that are never rate limited (probes and scrapers must always get through).
+const EXEMPT_PATHS: [&str; 3] = ["/ping", "/health", "/metrics.json"];
+
+const SHARD_COUNT: usize = 16;
+
+/// Buckets per shard above which idle (fully refilled) buckets are pruned.
+const PRUNE_THRESHOLD: usize = 1024;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// In-memory token buckets keyed by client IP, split across mutex-guarded
+/// shards to keep lock contention low.
+pub struct RateLimiter {
+    /// Tokens added per second; zero disables limiting.
+    rate: f64,
+    /// Bucket capacity, i.e. how many requests may arrive back to back.
+    burst: f64,
+    shards: Vec<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        RateLimiter {
+            rate,
+            burst: burst.max(1.0),
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate > 0.0
+    }
+
+    /// Take a token for `ip`, or return how long until one becomes available.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let mut hasher = DefaultHasher::new();
+        ip.hash(&mut hasher);
+        let shard = &self.shards[hasher.finish() as usize % SHARD_COUNT];
+        let mut buckets = shard.lock().unwrap();
+        let now = Instant::now();
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets
+                .retain(|_, b| b.tokens + now.duration_since(b.last).as_secs_f64() * rate < burst);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            last: now,
+        });
+        let refill = now.duration_since(bucket.last).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.rate;
+            Err(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX))
+        }
+    }
+}
+
+/// Middleware answering 429 (with `Retry-After`) once a client IP exhausts its bucket.
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limited = match (req.app_data::<web::Data<AppState>>(), req.peer_addr()) {
+        (Some(state), Some(peer)) if !EXEMPT_PATHS.contains(&req.path()) => {
+            state.rate_limiter.check(peer.ip()).err()
+        }
+        _ => None,
+    };
+    if let Some(wait) = limited {
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .json(error_body(
+                "rate_limited",
+                "Too many requests; please slow down.",
+            ));
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 7));
+
+    #[test]
+    fn allows_a_burst_then_asks_clients_to_wait() {
+        let limiter = RateLimiter::new(2.0, 3.0);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(CLIENT), Ok(()));
+        }
+        let wait = limiter.check(CLIENT).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(500));
+        // Other clients have their own bucket.
+        assert_eq!(limiter.check(IpAddr::from([203, 0, 113, 8])), Ok(()));
+    }
+
+    #[test]
+    fn tiny_rates_ask_for_a_long_wait_instead_of_panicking() {
+        let limiter = RateLimiter::new(1e-20, 1.0);
+        assert_eq!(limiter.check(CLIENT), Ok(()));
+        for _ in 0..2 {
+            assert_eq!(limiter.check(CLIENT), Err(Duration::MAX));
+        }
+    }
+
+    #[test]
+    fn zero_rate_never_limits() {
+        let limiter = RateLimiter::new(0.0, 1.0);
+        assert!(!limiter.is_enabled());
+        for _ in 0..100 {
+            assert_eq!(limiter.check(CLIENT), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::cache::{read_cache_file, write_cache_file};
+use crate::dates::parse_iso_date;
+use crate::filter::field_str;
+use crate::sheet::FetchError;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use tokio::fs;
+
+// ---------------------------------------------------------------------------
+// When each record first appeared, and the newest records (/recent)
+// ---------------------------------------------------------------------------
+
+/// Record ID → RFC 3339 time it was first loaded, or `None` for the records
+/// already in the sheet when tracking started (saved as `null`).
+pub type FirstSeen = BTreeMap<String, Option<String>>;
+
+/// The saved first-seen times, or an empty map if there are none yet.
+pub async fn load(path: &str) -> Result<FirstSeen, FetchError> {
+    match read_cache_file(path).await {
+        Ok(raw) => Ok(serde_json::from_str(&raw)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FirstSeen::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Save the first-seen times, creating the directory if need be.
+pub async fn save(path: &str, first_seen: &FirstSeen) -> Result<(), FetchError> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    write_cache_file(path, &serde_json::to_string(first_seen)?).await?;
+    Ok(())
+}
+
+/// Mark the IDs among `records` that haven't been seen before as first seen
+/// at `now`. On the first load (nothing saved yet) there is no telling when
+/// records arrived, so they are marked as a baseline with no time instead.
+/// IDs that dropped out of the sheet keep their time, so a record that comes
+/// back isn't counted as new. Returns whether anything was added.
+pub fn record_new(first_seen: &mut FirstSeen, records: &[Value], now: &str) -> bool {
+    let seen = (!first_seen.is_empty()).then(|| now.to_string());
+    let mut added = false;
+    for record in records {
+        let id = field_str(record, "id");
+        if !id.is_empty() && !first_seen.contains_key(id) {
+            first_seen.insert(id.to_string(), seen.clone());
+            added = true;
+        }
+    }
+    added
+}
+
+/// Carry first-seen times across edits to the fields a record's ID is made
+/// from (City, County, Zip): such an edit swaps the old ID for a new one.
+/// Where a refresh from `previous` to `records` drops exactly one ID in a
+/// state and adds exactly one there, the new ID takes the old one's time.
+/// Returns whether anything was added.
+pub fn carry_over_edits(first_seen: &mut FirstSeen, previous: &[Value], records: &[Value]) -> bool {
+    let current: HashSet<&str> = records.iter().map(|r| field_str(r, "id")).collect();
+    let known: HashSet<&str> = previous.iter().map(|r| field_str(r, "id")).collect();
+    let mut by_state: HashMap<String, (Vec<&str>, Vec<&str>)> = HashMap::new();
+    for record in previous {
+        let id = field_str(record, "id");
+        if !current.contains(id) && first_seen.contains_key(id) {
+            let state = field_str(record, "State").trim().to_uppercase();
+            by_state.entry(state).or_default().0.push(id);
+        }
+    }
+    for record in records {
+        let id = field_str(record, "id");
+        if !id.is_empty() && !known.contains(id) && !first_seen.contains_key(id) {
+            let state = field_str(record, "State").trim().to_uppercase();
+            by_state.entry(state).or_default().1.push(id);
+        }
+    }
+    let mut added = false;
+    for (dropped, new) in by_state.into_values() {
+        if let ([old], [new]) = (dropped.as_slice(), new.as_slice()) {
+            let seen = first_seen[*old].clone();
+            first_seen.insert(new.to_string(), seen);
+            added = true;
+        }
+    }
+    added
+}
+
+/// The `limit` newest records: by first-seen time (then `effective_date`)
+/// when `first_seen` is tracked, each with a `first_seen` field added, and
+/// the baseline records after all those by `effective_date` alone;
+/// otherwise by `effective_date`, leaving out records without a valid one.
+/// Ties keep sheet order.
+pub fn newest(records: &[Value], first_seen: Option<&FirstSeen>, limit: usize) -> Vec<Value> {
+    let effective = |record: &Value| {
+        let date = field_str(record, "effective_date");
+        parse_iso_date(date).map(|_| date.to_string())
+    };
+    let mut ranked: Vec<(Option<&str>, Option<String>, &Value)> = records
+        .iter()
+        .map(|record| {
+            let seen = first_seen
+                .and_then(|seen| seen.get(field_str(record, "id")))
+                .and_then(Option::as_deref);
+            (seen, effective(record), record)
+        })
+        .filter(|(_, date, _)| first_seen.is_some() || date.is_some())
+        .collect();
+    ranked.sort_by_key(|(seen, date, _)| Reverse((*seen, date.clone())));
+    ranked
+        .into_iter()
+        .take(limit)
+        .map(|(seen, _, record)| {
+            let mut record = record.clone();
+            if let (Some(seen), Some(fields)) = (seen, record.as_object_mut()) {
+                fields.insert("first_seen".to_string(), Value::String(seen.to_string()));
+            }
+            record
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn records() -> Vec<Value> {
+        vec![
+            json!({ "id": "a", "effective_date": "2024-03-01" }),
+            json!({ "id": "b", "effective_date": "soon", "effective_date_valid": false }),
+            json!({ "id": "c", "effective_date": "2025-01-05" }),
+            json!({ "id": "d" }),
+        ]
+    }
+
+    #[test]
+    fn falls_back_to_effective_dates() {
+        let recent = newest(&records(), None, 10);
+        let ids: Vec<_> = recent.iter().map(|r| field_str(r, "id")).collect();
+        assert_eq!(ids, ["c", "a"]);
+        assert!(recent[0].get("first_seen").is_none());
+        assert_eq!(newest(&records(), None, 1).len(), 1);
+    }
+
+    #[test]
+    fn orders_by_first_seen_and_keeps_old_ids() {
+        let mut seen = FirstSeen::new();
+        let records = records();
+        assert!(record_new(&mut seen, &records[..2], "2025-01-01T00:00:00Z"));
+        assert!(record_new(&mut seen, &records, "2025-02-01T00:00:00Z"));
+        assert!(!record_new(
+            &mut seen,
+            &records[..1],
+            "2025-03-01T00:00:00Z"
+        ));
+        // The first load is the baseline, not "everything arrived now".
+        assert_eq!(seen["a"], None);
+        assert_eq!(seen["c"].as_deref(), Some("2025-02-01T00:00:00Z"));
+
+        let recent = newest(&records, Some(&seen), 10);
+        let ids: Vec<_> = recent.iter().map(|r| field_str(r, "id")).collect();
+        // c and d arrived together; c has the later effective date. The
+        // baseline follows by effective date.
+        assert_eq!(ids, ["c", "d", "a", "b"]);
+        assert_eq!(recent[0]["first_seen"], "2025-02-01T00:00:00Z");
+        assert!(recent[2].get("first_seen").is_none());
+    }
+
+    #[test]
+    fn baseline_sorts_by_effective_date() {
+        let mut seen = FirstSeen::new();
+        assert!(record_new(&mut seen, &records(), "2025-01-01T00:00:00Z"));
+        let recent = newest(&records(), Some(&seen), 10);
+        let ids: Vec<_> = recent.iter().map(|r| field_str(r, "id")).collect();
+        assert_eq!(ids, ["c", "a", "b", "d"]);
+    }
+
+    #[test]
+    fn carries_times_across_key_edits() {
+        let previous = vec![
+            json!({ "id": "fl-old", "State": "FL" }),
+            json!({ "id": "ma", "State": "MA" }),
+            json!({ "id": "tx-1", "State": "TX" }),
+            json!({ "id": "tx-2", "State": "TX" }),
+        ];
+        let mut seen = FirstSeen::from([
+            (
+                "fl-old".to_string(),
+                Some("2025-01-01T00:00:00Z".to_string()),
+            ),
+            ("ma".to_string(), None),
+            ("tx-1".to_string(), None),
+            ("tx-2".to_string(), None),
+        ]);
+        // A City fix in FL renames one ID; TX loses two rows and gains one,
+        // which can't be paired.
+        let records = vec![
+            json!({ "id": "fl-new", "State": "fl" }),
+            json!({ "id": "ma", "State": "MA" }),
+            json!({ "id": "tx-3", "State": "TX" }),
+            json!({ "id": "ga", "State": "GA" }),
+        ];
+        assert!(carry_over_edits(&mut seen, &previous, &records));
+        assert_eq!(seen["fl-new"].as_deref(), Some("2025-01-01T00:00:00Z"));
+        assert!(!seen.contains_key("tx-3"));
+        assert!(!seen.contains_key("ga"));
+        assert!(!carry_over_edits(&mut seen, &records, &records));
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+// ---------------------------------------------------------------------------
+// Typed view of one record, for Rust clients of the JSON endpoints
+// ---------------------------------------------------------------------------
+
+/// One banned area as served by `/data`, `/search` and the other record
+/// endpoints. The columns every sheet has, and the fields the server derives,
+/// are typed; any other field lands in `extra`. Converting a served record
+/// to a `BanRecord` and back gives the same JSON object, except that the
+/// keys may come out in a different order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanRecord {
+    /// Stable ID derived from the state, city, county and zip.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "Zip", default, skip_serializing_if = "Option::is_none")]
+    pub zip: Option<String>,
+    #[serde(rename = "City", default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(rename = "County", default, skip_serializing_if = "Option::is_none")]
+    pub county: Option<String>,
+    #[serde(rename = "State", default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    /// ISO-8601 when the sheet's date parsed; the raw text otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_date: Option<String>,
+    /// Present (`false`) only when `effective_date` didn't parse.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_date_valid: Option<bool>,
+    /// `banned`, `restricted`, `pending` or `unknown`, when the sheet has a
+    /// status column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ban_status: Option<String>,
+    /// Census region, with `GKC_REGIONS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// IANA time zone of most of the state, with `GKC_REGIONS`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Every other field by name: the sheet's other columns (e.g. `Country`,
+    /// `Status`, `City_raw`) as strings, and numbers some endpoints add
+    /// (`distance_mi`, `score`).
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+impl BanRecord {
+    /// Read a record from its JSON form.
+    pub fn from_value(value: &Value) -> Result<BanRecord, serde_json::Error> {
+        BanRecord::deserialize(value)
+    }
+
+    /// The record's JSON form, as the endpoints serve it.
+    pub fn to_value(&self) -> Result<Value, serde_json::Error> {
+        serde_json::to_value(self)
+    }
+
+    /// A text field by its name in the JSON form, typed or not. Numbers in
+    /// `extra` aren't text; read them from `extra` directly.
+    pub fn get(&self, field: &str) -> Option<&str> {
+        let typed = match field {
+            "id" => &self.id,
+            "Zip" => &self.zip,
+            "City" => &self.city,
+            "County" => &self.county,
+            "State" => &self.state,
+            "effective_date" => &self.effective_date,
+            "ban_status" => &self.ban_status,
+            "region" => &self.region,
+            "timezone" => &self.timezone,
+            _ => return self.extra.get(field).and_then(Value::as_str),
+        };
+        typed.as_deref()
+    }
+}
+
+/// Parse a `/data` (or `/search`) response body into typed records.
+pub fn parse_records(json: &str) -> Result<Vec<BanRecord>, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_typed_and_extra_columns() {
+        let value = json!({
+            "id": "0a1b2c3d4e5f",
+            "Zip": "33701",
+            "City": "St. Petersburg",
+            "County": "Pinellas",
+            "State": "FL",
+            "Status": "Banned",
+            "effective_date": "soon",
+            "effective_date_valid": false,
+            "ban_status": "banned",
+        });
+        let record = BanRecord::from_value(&value).unwrap();
+        assert_eq!(record.city.as_deref(), Some("St. Petersburg"));
+        assert_eq!(record.effective_date_valid, Some(false));
+        assert_eq!(record.get("Status"), Some("Banned"));
+        assert_eq!(record.get("State"), Some("FL"));
+        assert_eq!(record.get("Notes"), None);
+        assert_eq!(record.to_value().unwrap(), value);
+    }
+
+    #[test]
+    fn keeps_numeric_fields_in_extra() {
+        let value = json!({ "Zip": "33701", "distance_mi": 2.5, "score": 3 });
+        let record = BanRecord::from_value(&value).unwrap();
+        assert_eq!(record.extra["distance_mi"].as_f64(), Some(2.5));
+        assert_eq!(record.extra["score"], 3);
+        assert_eq!(record.get("score"), None);
+        assert_eq!(record.to_value().unwrap(), value);
+    }
+
+    #[test]
+    fn parses_a_response_body() {
+        let records = parse_records(r#"[{"Zip": "01850", "State": "MA"}, {}]"#).unwrap();
+        assert_eq!(records[0].zip.as_deref(), Some("01850"));
+        assert_eq!(records[1], BanRecord::default());
+        assert!(parse_records(r#"[{"Zip": 1850}]"#).is_err());
+    }
+}
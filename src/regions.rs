@@ -0,0 +1,182 @@
+use crate::tree::state_counts;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+// ---------------------------------------------------------------------------
+// Census regions/divisions and time zones per state (GKC_REGIONS, /regions)
+// ---------------------------------------------------------------------------
+
+/// The bundled mapping: `State,Region,Division,Timezone`, one row per state
+/// code, in Census order. Edit the CSV to change or extend it.
+const REGIONS_CSV: &str = include_str!("../assets/regions.csv");
+
+/// Where one state sits: its Census region and division, and the IANA time
+/// zone covering most of it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StateRegion {
+    pub state: &'static str,
+    pub region: &'static str,
+    pub division: &'static str,
+    pub timezone: &'static str,
+}
+
+/// The rows of the bundled mapping, parsed on first use.
+fn table() -> &'static [StateRegion] {
+    static TABLE: OnceLock<Vec<StateRegion>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        REGIONS_CSV
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut fields = line.split(',').map(str::trim);
+                Some(StateRegion {
+                    state: fields.next().filter(|s| !s.is_empty())?,
+                    region: fields.next()?,
+                    division: fields.next()?,
+                    timezone: fields.next()?,
+                })
+            })
+            .collect()
+    })
+}
+
+/// The region, division, and time zone of a state code (any case).
+pub fn lookup(state: &str) -> Option<&'static StateRegion> {
+    let state = state.trim();
+    table()
+        .iter()
+        .find(|row| row.state.eq_ignore_ascii_case(state))
+}
+
+/// Add `region` and `timezone` to a record from its `State` value. Records
+/// whose state isn't in the mapping are left untouched.
+pub fn annotate_region(record: &mut Map<String, Value>) {
+    let Some(row) = record.get("State").and_then(Value::as_str).and_then(lookup) else {
+        return;
+    };
+    record.insert("region".to_string(), Value::String(row.region.to_string()));
+    record.insert(
+        "timezone".to_string(),
+        Value::String(row.timezone.to_string()),
+    );
+}
+
+/// One division of a [`Region`], with record counts for each of its states.
+#[derive(Debug, Serialize)]
+pub struct Division {
+    pub division: &'static str,
+    pub count: usize,
+    pub states: BTreeMap<&'static str, usize>,
+}
+
+/// One Census region in `/regions`.
+#[derive(Debug, Serialize)]
+pub struct Region {
+    pub region: &'static str,
+    pub count: usize,
+    pub divisions: Vec<Division>,
+}
+
+/// `records` counted per region, division, and state, in the mapping's
+/// order. Every mapped state is listed (zero if need be); records from
+/// states outside the mapping aren't counted.
+pub fn group_by_region<'a>(records: impl IntoIterator<Item = &'a Value>) -> Vec<Region> {
+    let counts = state_counts(records);
+    let mut regions: Vec<Region> = Vec::new();
+    for row in table() {
+        let count = counts.get(row.state).copied().unwrap_or(0);
+        if regions.last().is_none_or(|r| r.region != row.region) {
+            regions.push(Region {
+                region: row.region,
+                count: 0,
+                divisions: Vec::new(),
+            });
+        }
+        let region = regions.last_mut().unwrap();
+        if region
+            .divisions
+            .last()
+            .is_none_or(|d| d.division != row.division)
+        {
+            region.divisions.push(Division {
+                division: row.division,
+                count: 0,
+                states: BTreeMap::new(),
+            });
+        }
+        let division = region.divisions.last_mut().unwrap();
+        division.states.insert(row.state, count);
+        division.count += count;
+        region.count += count;
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn maps_states_across_regions() {
+        let cases = [
+            ("FL", "South", "South Atlantic", "America/New_York"),
+            ("ma", "Northeast", "New England", "America/New_York"),
+            ("IL", "Midwest", "East North Central", "America/Chicago"),
+            (" CA ", "West", "Pacific", "America/Los_Angeles"),
+            ("AZ", "West", "Mountain", "America/Phoenix"),
+        ];
+        for (state, region, division, timezone) in cases {
+            let row = lookup(state).unwrap();
+            assert_eq!(
+                (row.region, row.division, row.timezone),
+                (region, division, timezone),
+                "{}",
+                state
+            );
+        }
+        assert_eq!(lookup("ZZ"), None);
+        assert_eq!(table().len(), 51);
+    }
+
+    #[test]
+    fn annotates_mapped_states_only() {
+        let mut record = json!({ "State": "tx" }).as_object().unwrap().clone();
+        annotate_region(&mut record);
+        assert_eq!(record["region"], "South");
+        assert_eq!(record["timezone"], "America/Chicago");
+
+        let mut record = json!({ "State": "ON" }).as_object().unwrap().clone();
+        annotate_region(&mut record);
+        assert!(record.get("region").is_none());
+    }
+
+    #[test]
+    fn groups_every_state_by_region_and_division() {
+        let records = [
+            json!({ "State": "FL" }),
+            json!({ "State": "FL" }),
+            json!({ "State": "MA" }),
+            json!({ "State": "ON" }),
+        ];
+        let regions = group_by_region(&records);
+        let names: Vec<_> = regions.iter().map(|r| r.region).collect();
+        assert_eq!(names, ["Northeast", "Midwest", "South", "West"]);
+        assert_eq!(regions[0].count, 1);
+        assert_eq!(regions[1].count, 0);
+        let south = &regions[2].divisions[0];
+        assert_eq!(south.division, "South Atlantic");
+        assert_eq!(
+            (south.count, south.states["FL"], south.states["GA"]),
+            (2, 2, 0)
+        );
+        let states: usize = regions
+            .iter()
+            .flat_map(|r| &r.divisions)
+            .map(|d| d.states.len())
+            .sum();
+        assert_eq!(states, 51);
+    }
+}
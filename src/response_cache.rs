@@ -0,0 +1,161 @@
+use crate::lru::LruCache;
+use crate::state::{self, fnv1a64, AppState};
+use actix_web::body::{self, BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes};
+use actix_web::{Error, HttpResponse};
+use std::sync::Arc;
+
+// ---------------------------------------------------------------------------
+// HTTP-layer cache of serialized list responses
+// ---------------------------------------------------------------------------
+
+/// The list endpoints whose responses depend only on the query and the data.
+const CACHED_PATHS: [&str; 10] = [
+    "/data",
+    "/search",
+    "/zips",
+    "/counties",
+    "/states",
+    "/cities",
+    "/tree",
+    "/choropleth",
+    "/regions",
+    "/recent",
+];
+
+/// Response header saying whether the body came from this cache (`hit`/`miss`).
+const RESPONSE_CACHE_HEADER: HeaderName = HeaderName::from_static("x-response-cache");
+
+/// Data freshness header, replaced with the current status on a hit.
+const CACHE_STATUS_HEADER: HeaderName = HeaderName::from_static("x-cache-status");
+
+/// A `200` response as sent, with the `ETag` of its body.
+pub struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+    etag: String,
+}
+
+/// Serialized responses for one dataset, keyed by [`cache_key`]. Like the
+/// search cache, each refresh starts with an empty one.
+pub type ResponseCache = LruCache<Arc<CachedResponse>>;
+
+/// The configuration generation, the path, and the query pairs in sorted
+/// order, so `?a=1&b=2` and `?b=2&a=1` share an entry but a config reload
+/// (which can change caps, aliases, ...) starts afresh.
+fn cache_key(req: &ServiceRequest, generation: u64) -> String {
+    let mut pairs = web::Query::<Vec<(String, String)>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    pairs.sort();
+    format!("{} {} {:?}", generation, req.path(), pairs)
+}
+
+/// The response for a hit: `304` when the client already has `etag`.
+fn replay(req: &ServiceRequest, cached: &CachedResponse) -> HttpResponse {
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .any(|tag| tag.trim() == cached.etag || tag.trim() == "*")
+        });
+    let mut response = if not_modified {
+        HttpResponse::NotModified().finish()
+    } else {
+        HttpResponse::Ok().body(cached.body.clone())
+    };
+    for (name, value) in &cached.headers {
+        response.headers_mut().append(name.clone(), value.clone());
+    }
+    response
+}
+
+/// Middleware memoizing the bytes of successful `GET`s to the list endpoints
+/// in the loaded dataset, when `GKC_RESPONSE_CACHE_SIZE` is set and the data
+/// has been loaded (the request that loads it isn't cached). Hits are
+/// served without running the handler, with an `ETag` honored by
+/// `If-None-Match`; a refresh replaces the dataset and so empties the cache.
+pub async fn response_cache(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody, BoxBody>>, Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+    let cacheable = req.method() == Method::GET && CACHED_PATHS.contains(&req.path());
+    let Some(state) = state.filter(|state| {
+        cacheable && state.config().response_cache_size > 0 && state.loaded_dataset().is_some()
+    }) else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+    // Goes through the usual freshness check, so hits still trigger refreshes.
+    // The handler's own lookup then isn't counted again in the metrics.
+    let Ok((dataset, cache_status)) = state.dataset_with_status().await else {
+        return state::uncounted(next.call(req))
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+    // Read before the handler takes its configuration, so an entry can only
+    // be filed under a generation as old as the settings that built it.
+    let generation = state.config_generation();
+    let key = cache_key(&req, generation);
+    if let Some(cached) = dataset.response_cache.get(&key) {
+        let mut response = replay(&req, &cached);
+        let headers = response.headers_mut();
+        if headers.contains_key(CACHE_STATUS_HEADER) {
+            headers.insert(
+                CACHE_STATUS_HEADER,
+                HeaderValue::from_static(cache_status.as_str()),
+            );
+        }
+        headers.insert(RESPONSE_CACHE_HEADER, HeaderValue::from_static("hit"));
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let response = state::uncounted(next.call(req)).await?;
+    // Only keep what the handler built from this same dataset and settings.
+    let same_data = state
+        .loaded_dataset()
+        .is_some_and(|current| Arc::ptr_eq(&current, &dataset));
+    let same_config = state.config_generation() == generation;
+    if response.status() != StatusCode::OK || !same_data || !same_config {
+        return Ok(response.map_into_left_body());
+    }
+    let (req, response) = response.into_parts();
+    let (mut response, body) = response.into_parts();
+    let body = body::to_bytes(body).await.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e.into();
+        actix_web::error::ErrorInternalServerError(e.to_string())
+    })?;
+    let etag = match response.headers().get(header::ETAG) {
+        Some(etag) => etag.to_str().unwrap_or_default().to_string(),
+        None => format!("\"{:016x}\"", fnv1a64(&body)),
+    };
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    let mut headers = response.headers().clone();
+    // Timings describe this request only.
+    headers.remove("server-timing");
+    headers.remove(header::CONTENT_LENGTH);
+    dataset.response_cache.insert(
+        key,
+        Arc::new(CachedResponse {
+            headers,
+            body: body.clone(),
+            etag,
+        }),
+    );
+    response
+        .headers_mut()
+        .insert(RESPONSE_CACHE_HEADER, HeaderValue::from_static("miss"));
+    let response = ServiceResponse::new(req, response.set_body(body));
+    Ok(response.map_into_boxed_body().map_into_right_body())
+}
@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+// ---------------------------------------------------------------------------
+// Refreshes at fixed times from a cron expression (GKC_REFRESH_CRON)
+// ---------------------------------------------------------------------------
+
+/// How long after a scheduled time a refresh may take (retries included)
+/// before the data counts as having missed it.
+pub const REFRESH_GRACE: Duration = Duration::from_secs(10 * 60);
+
+const WEEKDAYS: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+/// Parse a cron expression, in UTC. Takes the `cron` crate's form with
+/// seconds (`0 0 6,18 * * *`) as well as the classic five fields
+/// (`0 6,18 * * *`), which run at second 0. The two number weekdays
+/// differently: classic counts from Sunday = 0 (7 is Sunday too), the
+/// `cron` crate from Sunday = 1.
+pub fn parse(expr: &str) -> Result<Schedule, cron::error::Error> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    match fields.as_slice() {
+        [minute, hour, day, month, weekday] => Schedule::from_str(&format!(
+            "0 {} {} {} {} {}",
+            minute,
+            hour,
+            day,
+            month,
+            classic_weekdays(weekday)?
+        )),
+        _ => Schedule::from_str(expr.trim()),
+    }
+}
+
+/// A classic day-of-week field (numbers, names, ranges, steps and lists) as
+/// the list of day names the `cron` crate reads.
+fn classic_weekdays(field: &str) -> Result<String, cron::error::Error> {
+    if field == "*" || field == "?" {
+        return Ok(field.to_string());
+    }
+    let invalid = || -> cron::error::Error {
+        cron::error::ErrorKind::Expression(format!("'{}' is not a valid day of the week.", field))
+            .into()
+    };
+    let day = |token: &str| match token.parse::<usize>() {
+        Ok(n) if n <= 7 => Ok(n),
+        Ok(_) => Err(invalid()),
+        Err(_) => {
+            let token = token.to_lowercase();
+            WEEKDAYS
+                .iter()
+                .position(|name| token == *name || token == name[..3])
+                .ok_or_else(invalid)
+        }
+    };
+    let mut days = [false; 7];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&s| s > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(invalid)?;
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (0, 6),
+            Some((first, last)) => (day(first)?, day(last)?),
+            None if step == 1 => (day(range)?, day(range)?),
+            None => return Err(invalid()),
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for n in (first..=last).step_by(step) {
+            days[n % 7] = true;
+        }
+    }
+    let names: Vec<&str> = (0..7)
+        .filter(|&n| days[n])
+        .map(|n| &WEEKDAYS[n][..3])
+        .collect();
+    Ok(names.join(","))
+}
+
+/// The first scheduled time strictly after `time`, if the schedule has one.
+pub fn next_after(schedule: &Schedule, time: SystemTime) -> Option<SystemTime> {
+    schedule
+        .after(&DateTime::<Utc>::from(time))
+        .next()
+        .map(SystemTime::from)
+}
+
+/// The last scheduled time strictly before `time`, if the schedule has one.
+pub fn last_before(schedule: &Schedule, time: SystemTime) -> Option<SystemTime> {
+    schedule
+        .after(&DateTime::<Utc>::from(time))
+        .next_back()
+        .map(SystemTime::from)
+}
+
+/// How old a cache can be at `now` without missing a scheduled refresh: the
+/// time since the last scheduled one.
+pub fn cache_ttl(schedule: &Schedule, now: SystemTime) -> Option<Duration> {
+    let last = last_before(schedule, now)?;
+    Some(now.duration_since(last).unwrap_or_default())
+}
+
+/// How long data fetched at `fetched_at` (and loaded at `now`) stays fresh:
+/// until the next scheduled time.
+pub fn fresh_for(schedule: &Schedule, fetched_at: SystemTime, now: SystemTime) -> Option<Duration> {
+    let next = next_after(schedule, now)?;
+    Some(next.duration_since(fetched_at).unwrap_or_default())
+}
+
+/// How old data can be at `now` without having missed a scheduled refresh:
+/// the time since the last scheduled time more than `grace` ago. Data
+/// fetched before that time missed it.
+pub fn missed_after(schedule: &Schedule, now: SystemTime, grace: Duration) -> Option<Duration> {
+    let deadline = last_before(schedule, now.checked_sub(grace)?)?;
+    now.duration_since(deadline).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    /// 2025-01-01T00:00:00Z plus `hours`.
+    fn at(hours: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_735_689_600 + hours * 3600)
+    }
+
+    #[test]
+    fn accepts_five_and_six_fields() {
+        let five = parse("0 6,18 * * *").unwrap();
+        let six = parse(" 0 0 6,18 * * * ").unwrap();
+        assert_eq!(five, six);
+        assert!(parse("every morning").is_err());
+    }
+
+    #[test]
+    fn reads_classic_weekday_numbers() {
+        // The next week of runs, from 2025-01-01 (a Wednesday).
+        let week = |expr: &str| -> Vec<SystemTime> {
+            let schedule = parse(expr).unwrap();
+            let start = DateTime::<Utc>::from(at(0));
+            let end = DateTime::<Utc>::from(at(7 * 24));
+            let times = schedule.after(&start).take_while(|time| *time < end);
+            times.map(SystemTime::from).collect()
+        };
+        assert_eq!(week("0 6 * * 1"), [at(5 * 24 + 6)]);
+        assert_eq!(week("0 6 * * mon"), week("0 6 * * 1"));
+        assert_eq!(week("0 6 * * 0"), [at(4 * 24 + 6)]);
+        assert_eq!(week("0 6 * * 7"), week("0 6 * * 0"));
+        assert_eq!(week("0 6 * * Sunday"), week("0 6 * * 0"));
+        assert_eq!(week("0 6 * * 1-5"), week("0 0 6 * * Mon-Fri"));
+        assert_eq!(week("0 6 * * 5-7,*/3"), week("0 0 6 * * Sun,Wed,Fri,Sat"));
+        assert!(parse("0 6 * * 8").is_err());
+        assert!(parse("0 6 * * 5-1").is_err());
+    }
+
+    #[test]
+    fn finds_the_scheduled_times_around_now() {
+        let schedule = parse("0 6,18 * * *").unwrap();
+        let now = at(10);
+        assert_eq!(next_after(&schedule, now), Some(at(18)));
+        assert_eq!(last_before(&schedule, now), Some(at(6)));
+        assert_eq!(
+            cache_ttl(&schedule, now),
+            Some(Duration::from_secs(4 * 3600))
+        );
+        // Within the grace period the 6am run may still be under way, so
+        // only data from before 6pm yesterday has missed one.
+        let grace = Duration::from_secs(3600);
+        assert_eq!(
+            missed_after(&schedule, at(6) + grace / 2, grace),
+            Some(Duration::from_secs(12 * 3600 + 1800))
+        );
+        assert_eq!(
+            missed_after(&schedule, now, grace),
+            Some(Duration::from_secs(4 * 3600))
+        );
+        // Data fetched at 7am is fresh until 6pm.
+        assert_eq!(
+            fresh_for(&schedule, at(7), now),
+            Some(Duration::from_secs(11 * 3600))
+        );
+    }
+}
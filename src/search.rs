@@ -0,0 +1,656 @@
+use crate::lru::LruCache;
+use crate::text::snippet;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// ---------------------------------------------------------------------------
+// Substring search over banned-area records, backed by a trigram index
+// ---------------------------------------------------------------------------
+
+/// Record fields that free-text queries are matched against (same as the UI).
+pub const SEARCH_FIELDS: [&str; 4] = ["City", "County", "Zip", "State"];
+
+/// Lowercased value of a record field, or `None` when absent or empty.
+fn field_lower(record: &Value, field: &str) -> Option<String> {
+    record
+        .get(field)
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// How the whitespace-separated terms of a query combine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Every term must match (each may match a different field).
+    #[default]
+    All,
+    /// Any one term matching is enough.
+    Any,
+}
+
+impl MatchMode {
+    /// Parse the `mode` query parameter (`and`/`all` or `or`/`any`), returning
+    /// a 400-ready message for anything else.
+    pub fn parse(raw: Option<&str>) -> Result<Self, String> {
+        match raw.map(|m| m.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("and") | Some("all") => Ok(MatchMode::All),
+            Some("or") | Some("any") => Ok(MatchMode::Any),
+            Some(other) => Err(format!(
+                "Invalid mode \"{}\": expected and or or",
+                snippet(other)
+            )),
+        }
+    }
+}
+
+/// Whether any searchable field of `record` contains `query` (already lowercased).
+pub fn record_matches(record: &Value, query: &str) -> bool {
+    SEARCH_FIELDS
+        .iter()
+        .filter_map(|f| field_lower(record, f))
+        .any(|value| value.contains(query))
+}
+
+/// Split a query into lowercased (included, excluded) terms; a leading `-`
+/// marks an exclusion.
+pub fn split_terms(query: &str) -> (Vec<String>, Vec<String>) {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    for term in query.split_whitespace().map(str::to_lowercase) {
+        match term.strip_prefix('-') {
+            Some(negated) if !negated.is_empty() => exclude.push(negated.to_string()),
+            _ => include.push(term),
+        }
+    }
+    (include, exclude)
+}
+
+/// All three-character windows of `s`, by character rather than byte.
+fn trigrams(s: &str) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    chars.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+/// Maps each trigram found in the searchable fields to the (sorted, deduplicated)
+/// indices of the records containing it.
+#[derive(Debug, Default)]
+pub struct TrigramIndex {
+    postings: HashMap<String, Vec<usize>>,
+}
+
+impl TrigramIndex {
+    /// Build the index over `records`; call again whenever the data refreshes.
+    pub fn build(records: &[Value]) -> Self {
+        let mut postings: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            for value in SEARCH_FIELDS.iter().filter_map(|f| field_lower(record, f)) {
+                for gram in trigrams(&value) {
+                    let list = postings.entry(gram).or_default();
+                    // Records are visited in order, so checking the tail is enough to dedupe.
+                    if list.last() != Some(&i) {
+                        list.push(i);
+                    }
+                }
+            }
+        }
+        TrigramIndex { postings }
+    }
+
+    /// Indices of records that contain every trigram of `query`, or `None` when
+    /// the query is too short to use the index.
+    fn candidates(&self, query: &str) -> Option<Vec<usize>> {
+        let grams = trigrams(query);
+        if grams.is_empty() {
+            return None;
+        }
+        let mut lists: Vec<&Vec<usize>> = Vec::with_capacity(grams.len());
+        for gram in &grams {
+            match self.postings.get(gram) {
+                Some(list) => lists.push(list),
+                None => return Some(Vec::new()),
+            }
+        }
+        // Intersect starting from the shortest posting list.
+        lists.sort_by_key(|l| l.len());
+        let mut result = lists[0].clone();
+        for list in &lists[1..] {
+            result.retain(|i| list.binary_search(i).is_ok());
+            if result.is_empty() {
+                break;
+            }
+        }
+        Some(result)
+    }
+
+    /// Indices (in sheet order) of the records whose searchable fields contain
+    /// `term` (already lowercased). Terms shorter than three characters fall
+    /// back to a linear scan.
+    fn search_term(&self, records: &[Value], term: &str) -> Vec<usize> {
+        match self.candidates(term) {
+            Some(candidates) => candidates
+                .into_iter()
+                .filter(|&i| record_matches(&records[i], term))
+                .collect(),
+            None => (0..records.len())
+                .filter(|&i| record_matches(&records[i], term))
+                .collect(),
+        }
+    }
+
+    /// Indices (in sheet order) of the records matching the whitespace-separated
+    /// terms of `query`, combined according to `mode`. Terms prefixed with `-`
+    /// exclude the records they match. A query with no positive terms starts
+    /// from every record.
+    pub fn search(&self, records: &[Value], query: &str, mode: MatchMode) -> Vec<usize> {
+        let (include, exclude) = split_terms(query);
+        let mut result = match include.split_first() {
+            None => (0..records.len()).collect(),
+            Some((first, rest)) => {
+                let mut result = self.search_term(records, first);
+                match mode {
+                    MatchMode::All => {
+                        result
+                            .retain(|&i| rest.iter().all(|term| record_matches(&records[i], term)));
+                    }
+                    MatchMode::Any => {
+                        for term in rest {
+                            result.extend(self.search_term(records, term));
+                        }
+                        result.sort_unstable();
+                        result.dedup();
+                    }
+                }
+                result
+            }
+        };
+        result.retain(|&i| !exclude.iter().any(|term| record_matches(&records[i], term)));
+        result
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Relevance ranking of free-text results
+// ---------------------------------------------------------------------------
+
+/// How well a term matched a field, weakest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchLevel {
+    Substring = 1,
+    Prefix = 2,
+    Exact = 3,
+}
+
+/// Weight of a match in each searchable field: where someone lives is named
+/// by state and city more often than by county or zip.
+fn field_weight(field: &str) -> u32 {
+    match field {
+        "State" | "City" => 2,
+        _ => 1,
+    }
+}
+
+/// How relevant `record` is to the positive terms of `query`: for each term
+/// its best field match, where the kind of match (exact, prefix, substring)
+/// outweighs the field it was in. Terms that match nothing add zero.
+pub fn relevance(record: &Value, query: &str) -> u32 {
+    let (include, _) = split_terms(query);
+    include
+        .iter()
+        .map(|term| {
+            SEARCH_FIELDS
+                .iter()
+                .filter_map(|&field| {
+                    let value = field_lower(record, field)?;
+                    let level = if value == *term {
+                        MatchLevel::Exact
+                    } else if value.starts_with(term.as_str()) {
+                        MatchLevel::Prefix
+                    } else if value.contains(term.as_str()) {
+                        MatchLevel::Substring
+                    } else {
+                        return None;
+                    };
+                    Some(level as u32 * 10 + field_weight(field))
+                })
+                .max()
+                .unwrap_or(0)
+        })
+        .sum()
+}
+
+/// Order `records` most relevant to `query` first, breaking ties
+/// alphabetically by City, State, County, and Zip. Queries without positive
+/// terms leave the order alone.
+pub fn rank(records: &mut [&Value], query: &str) {
+    if split_terms(query).0.is_empty() {
+        return;
+    }
+    records.sort_by_cached_key(|record| {
+        let tiebreak: Vec<String> = ["City", "State", "County", "Zip"]
+            .iter()
+            .map(|field| field_lower(record, field).unwrap_or_default())
+            .collect();
+        (Reverse(relevance(record, query)), tiebreak)
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Match locations for client-side highlighting (`/search?highlight=true`)
+// ---------------------------------------------------------------------------
+
+/// One match inside a record field, as character (not byte) offsets into the
+/// field's original text so they line up with what the client displays.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FieldMatch {
+    pub field: &'static str,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Non-overlapping, case-insensitive occurrences of `needle` (already
+/// lowercased) in `haystack`, as character ranges of the original text.
+fn find_case_insensitive(haystack: &str, needle: &str) -> Vec<(usize, usize)> {
+    let hay: Vec<char> = haystack.chars().collect();
+    let needle: Vec<char> = needle.chars().collect();
+    let mut found = Vec::new();
+    let mut start = 0;
+    while start < hay.len() {
+        // Lowercasing can expand a character, so compare the lowercased
+        // stream and note which original character the needle ends in.
+        let mut matched = 0;
+        let mut end = None;
+        'chars: for (offset, c) in hay[start..].iter().enumerate() {
+            for lower in c.to_lowercase() {
+                if needle.get(matched) != Some(&lower) {
+                    break 'chars;
+                }
+                matched += 1;
+            }
+            if matched == needle.len() {
+                end = Some(start + offset + 1);
+                break;
+            }
+        }
+        match end {
+            Some(end) if !needle.is_empty() => {
+                found.push((start, end));
+                start = end;
+            }
+            _ => start += 1,
+        }
+    }
+    found
+}
+
+/// Where the positive terms of a substring `query` occur in `record`'s
+/// searchable fields (excluded terms never match a returned record).
+pub fn highlight_terms(record: &Value, query: &str) -> Vec<FieldMatch> {
+    let (include, _) = split_terms(query);
+    let mut matches = Vec::new();
+    for field in SEARCH_FIELDS {
+        let value = match record.get(field).and_then(Value::as_str) {
+            Some(value) => value,
+            None => continue,
+        };
+        let mut ranges: Vec<(usize, usize)> = include
+            .iter()
+            .flat_map(|term| find_case_insensitive(value, term))
+            .collect();
+        ranges.sort_unstable();
+        ranges.dedup();
+        matches.extend(
+            ranges
+                .into_iter()
+                .map(|(start, end)| FieldMatch { field, start, end }),
+        );
+    }
+    matches
+}
+
+/// Where `regex` matches `record` within `fields`.
+pub fn highlight_regex(record: &Value, regex: &Regex, fields: &[&'static str]) -> Vec<FieldMatch> {
+    let mut matches = Vec::new();
+    for &field in fields {
+        let value = match record.get(field).and_then(Value::as_str) {
+            Some(value) => value,
+            None => continue,
+        };
+        for m in regex.find_iter(value).filter(|m| !m.is_empty()) {
+            let start = value[..m.start()].chars().count();
+            matches.push(FieldMatch {
+                field,
+                start,
+                end: start + m.as_str().chars().count(),
+            });
+        }
+    }
+    matches
+}
+
+// ---------------------------------------------------------------------------
+// Opt-in regex search (`/search?regex=`), kept apart from substring search
+// ---------------------------------------------------------------------------
+
+/// Longest regex pattern accepted, in characters.
+pub const MAX_REGEX_LEN: usize = 200;
+
+/// Cap on the compiled program (and lazy DFA) size, which rejects patterns
+/// like `(a{100}){100}` that would blow up at compile or match time.
+const REGEX_SIZE_LIMIT: usize = 256 * 1024;
+
+/// Wall-clock budget for matching one regex against the whole dataset.
+pub const REGEX_TIME_BUDGET: Duration = Duration::from_millis(250);
+
+/// Records checked between budget checks.
+const BUDGET_CHECK_INTERVAL: usize = 256;
+
+/// Compile a user-supplied, case-insensitive pattern, returning a 400-ready
+/// message if it is too long, invalid, or too large.
+pub fn compile_regex(pattern: &str) -> Result<Regex, String> {
+    if pattern.chars().count() > MAX_REGEX_LEN {
+        return Err(format!(
+            "Invalid regex: patterns are limited to {} characters",
+            MAX_REGEX_LEN
+        ));
+    }
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("Invalid regex: {}", e))
+}
+
+/// Resolve a comma-separated `fields` parameter against [`SEARCH_FIELDS`]
+/// (case-insensitively); `None` or blank means all of them.
+pub fn resolve_fields(raw: Option<&str>) -> Result<Vec<&'static str>, String> {
+    let raw = match raw.map(str::trim).filter(|r| !r.is_empty()) {
+        Some(raw) => raw,
+        None => return Ok(SEARCH_FIELDS.to_vec()),
+    };
+    raw.split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|name| {
+            SEARCH_FIELDS
+                .iter()
+                .find(|f| f.eq_ignore_ascii_case(name))
+                .copied()
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid field \"{}\": expected one of {}",
+                        snippet(name),
+                        SEARCH_FIELDS.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Indices (in sheet order) of the records where `regex` matches one of
+/// `fields`. Gives up with an error once `budget` is spent.
+pub fn regex_search(
+    records: &[Value],
+    regex: &Regex,
+    fields: &[&str],
+    budget: Duration,
+) -> Result<Vec<usize>, String> {
+    let started = Instant::now();
+    let mut result = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        if i % BUDGET_CHECK_INTERVAL == 0 && i > 0 && started.elapsed() > budget {
+            return Err("Regex search took too long; try a simpler pattern".to_string());
+        }
+        let matched = fields
+            .iter()
+            .filter_map(|f| record.get(*f).and_then(Value::as_str))
+            .any(|value| regex.is_match(value));
+        if matched {
+            result.push(i);
+        }
+    }
+    Ok(result)
+}
+
+/// Recently computed `/search` matches for one dataset, keyed by the
+/// normalized query and filters. Each refresh builds a new dataset and, with
+/// it, an empty cache, so stale results are never served.
+pub type SearchCache = LruCache<Arc<Vec<usize>>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn search_cache_evicts_the_least_recently_used_entry() {
+        let cache = SearchCache::new(2);
+        cache.insert("a".to_string(), Arc::new(vec![1]));
+        cache.insert("b".to_string(), Arc::new(vec![2]));
+        assert_eq!(*cache.get("a").unwrap(), [1]);
+        cache.insert("c".to_string(), Arc::new(vec![3]));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.hits(), 3);
+
+        let disabled = SearchCache::new(0);
+        disabled.insert("a".to_string(), Arc::new(vec![1]));
+        assert!(disabled.get("a").is_none());
+    }
+
+    fn records() -> Vec<Value> {
+        vec![
+            json!({"City": "Clearwater", "County": "Pinellas", "Zip": "33755", "State": "FL"}),
+            json!({"City": "Clearwater", "County": "Pinellas", "Zip": "33756", "State": "FL"}),
+            json!({"City": "St. Petersburg", "County": "Pinellas", "Zip": "33701", "State": "FL"}),
+        ]
+    }
+
+    fn search(query: &str, mode: MatchMode) -> Vec<usize> {
+        let records = records();
+        TrigramIndex::build(&records).search(&records, query, mode)
+    }
+
+    #[test]
+    fn ranks_exact_over_prefix_over_substring_then_by_field() {
+        let records = [
+            json!({"City": "Salt Lake City", "County": "Salt Lake", "State": "UT"}),
+            json!({"City": "Eustis", "County": "Lake", "State": "FL"}),
+            json!({"City": "Lake City", "County": "Columbia", "State": "FL"}),
+            json!({"City": "Murray", "County": "Salt Lake", "State": "UT"}),
+            json!({"City": "Lake", "County": "Scott", "State": "MS"}),
+            json!({"City": "Lake Butler", "County": "Union", "State": "FL"}),
+            json!({"City": "Lakeland", "County": "Polk", "State": "FL"}),
+            json!({"City": "Tavares", "County": "Lakeside", "State": "FL"}),
+        ];
+        let mut ranked: Vec<&Value> = records.iter().collect();
+        rank(&mut ranked, "LAKE");
+        let cities: Vec<&str> = ranked.iter().map(|r| r["City"].as_str().unwrap()).collect();
+        assert_eq!(
+            cities,
+            [
+                "Lake",           // exact City
+                "Eustis",         // exact County
+                "Lake Butler",    // prefix City, alphabetically first
+                "Lake City",      // prefix City
+                "Lakeland",       // prefix City
+                "Tavares",        // prefix County
+                "Salt Lake City", // substring City
+                "Murray",         // substring County
+            ]
+        );
+        assert!(relevance(&records[4], "lake") > relevance(&records[1], "lake"));
+
+        // Only exclusions: nothing to rank by, so the order is kept.
+        let mut unranked: Vec<&Value> = records.iter().collect();
+        rank(&mut unranked, "-lake");
+        assert_eq!(unranked[0], &records[0]);
+    }
+
+    #[test]
+    fn single_term_matches_any_field() {
+        assert_eq!(search("clearwater", MatchMode::All), vec![0, 1]);
+        assert_eq!(search("337", MatchMode::All), vec![0, 1, 2]);
+        assert_eq!(search("fl", MatchMode::All), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn all_terms_must_match_across_fields() {
+        assert_eq!(search("clearwater 33756", MatchMode::All), vec![1]);
+        assert_eq!(
+            search("  Clearwater   33701 ", MatchMode::All),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn any_term_may_match_in_or_mode() {
+        assert_eq!(search("33701 33755", MatchMode::Any), vec![0, 2]);
+        assert_eq!(search("clearwater 33701", MatchMode::Any), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn negated_terms_exclude_matches() {
+        assert_eq!(search("clearwater -33755", MatchMode::All), vec![1]);
+        assert_eq!(search("pinellas -clearwater", MatchMode::All), vec![2]);
+        assert_eq!(search("33755 33701 -st.", MatchMode::Any), vec![0]);
+        // Only exclusions: everything else is kept.
+        assert_eq!(search("-clearwater", MatchMode::All), vec![2]);
+        // A lone dash is an ordinary term.
+        assert_eq!(search("-", MatchMode::All), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn regex_matches_chosen_fields() {
+        let records = records();
+        let regex = compile_regex("^3375[56]$").unwrap();
+        let fields = resolve_fields(Some("zip")).unwrap();
+        assert_eq!(
+            regex_search(&records, &regex, &fields, REGEX_TIME_BUDGET),
+            Ok(vec![0, 1])
+        );
+        let fields = resolve_fields(Some("City,County")).unwrap();
+        assert_eq!(
+            regex_search(&records, &regex, &fields, REGEX_TIME_BUDGET),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn regex_safeguards_reject_bad_patterns() {
+        assert!(compile_regex("(unclosed").is_err());
+        assert!(compile_regex(&"a".repeat(MAX_REGEX_LEN + 1)).is_err());
+        assert!(compile_regex("((a{100}){100}){100}").is_err());
+        assert!(resolve_fields(Some("Country")).is_err());
+    }
+
+    #[test]
+    fn highlight_offsets_are_in_characters_of_the_original_text() {
+        let record = json!({"City": "Ñandú Ñandú", "County": "Pinellas"});
+        assert_eq!(
+            highlight_terms(&record, "ñandú -pinellas"),
+            vec![
+                FieldMatch {
+                    field: "City",
+                    start: 0,
+                    end: 5
+                },
+                FieldMatch {
+                    field: "City",
+                    start: 6,
+                    end: 11
+                },
+            ]
+        );
+        let regex = compile_regex("dú$").unwrap();
+        assert_eq!(
+            highlight_regex(&record, &regex, &SEARCH_FIELDS),
+            vec![FieldMatch {
+                field: "City",
+                start: 9,
+                end: 11
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_the_mode_parameter() {
+        assert_eq!(MatchMode::parse(None), Ok(MatchMode::All));
+        assert_eq!(MatchMode::parse(Some("OR")), Ok(MatchMode::Any));
+        assert!(MatchMode::parse(Some("xor")).is_err());
+    }
+
+    /// `n` records over a few hundred distinct cities, counties and zips.
+    fn synthetic(n: usize) -> Vec<Value> {
+        const STATES: [&str; 6] = ["FL", "MA", "AL", "MS", "TN", "OH"];
+        (0..n)
+            .map(|i| {
+                json!({
+                    "City": format!("Town {} Springs", i % 997),
+                    "County": format!("County {}", i % 211),
+                    "Zip": format!("{:05}", (i * 7919) % 100_000),
+                    "State": STATES[i % STATES.len()],
+                })
+            })
+            .collect()
+    }
+
+    /// What the index replaces for a single term: check every record.
+    fn linear(records: &[Value], term: &str) -> Vec<usize> {
+        (0..records.len())
+            .filter(|&i| record_matches(&records[i], term))
+            .collect()
+    }
+
+    #[test]
+    fn index_finds_exactly_what_a_linear_scan_does() {
+        let records = synthetic(5_000);
+        let index = TrigramIndex::build(&records);
+        for term in ["springs", "nty", "120", "fl", "t", "0000", "ms", "nowhere"] {
+            assert_eq!(
+                index.search(&records, term, MatchMode::All),
+                linear(&records, term),
+                "{}",
+                term
+            );
+        }
+    }
+
+    /// Times the index against a linear scan on a large synthetic sheet. Run
+    /// with `cargo test --release -- --ignored --nocapture bench`.
+    #[test]
+    #[ignore]
+    fn bench_index_against_linear_scan() {
+        let records = synthetic(200_000);
+        let started = Instant::now();
+        let index = TrigramIndex::build(&records);
+        println!(
+            "Indexed {} records in {:?}",
+            records.len(),
+            started.elapsed()
+        );
+        for term in ["33701", "4242", "springs", "996"] {
+            let started = Instant::now();
+            let scanned = linear(&records, term);
+            let scan_time = started.elapsed();
+            let started = Instant::now();
+            let indexed = index.search(&records, term, MatchMode::All);
+            let index_time = started.elapsed();
+            assert_eq!(indexed, scanned);
+            println!(
+                "{:>10}: {:>6} matches, scan {:?}, index {:?}",
+                term,
+                indexed.len(),
+                scan_time,
+                index_time
+            );
+        }
+    }
+}
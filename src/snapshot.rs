@@ -0,0 +1,171 @@
+use crate::cache::{read_cache_file, write_cache_file};
+use crate::dates::{format_rfc3339, parse_iso_date};
+use crate::sheet::FetchError;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+
+// ---------------------------------------------------------------------------
+// Dated snapshots of the data, for comparing today's records with the past
+// ---------------------------------------------------------------------------
+
+const SUFFIX: &str = ".json.gz";
+
+fn snapshot_path(dir: &str, date: &str) -> String {
+    Path::new(dir)
+        .join(format!("{}{}", date, SUFFIX))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The UTC date of `time` as `YYYY-MM-DD`.
+fn date_of(time: SystemTime) -> String {
+    format_rfc3339(time)[..10].to_string()
+}
+
+/// Save `records` as the snapshot for `date`, replacing an earlier one from
+/// the same day.
+pub async fn save(dir: &str, date: &str, records: &[Value]) -> Result<(), FetchError> {
+    fs::create_dir_all(dir).await?;
+    let json = serde_json::to_string(records)?;
+    write_cache_file(&snapshot_path(dir, date), &json).await?;
+    Ok(())
+}
+
+/// Archive today's snapshot and delete the ones older than `retention_days`.
+pub async fn archive(dir: &str, records: &[Value], retention_days: u64) -> Result<(), FetchError> {
+    let now = SystemTime::now();
+    save(dir, &date_of(now), records).await?;
+    // A retention reaching back past the epoch keeps everything.
+    let Some(cutoff) = retention_days
+        .checked_mul(86_400)
+        .and_then(|secs| now.checked_sub(Duration::from_secs(secs)))
+    else {
+        return Ok(());
+    };
+    let cutoff = date_of(cutoff);
+    for date in list(dir).await {
+        if date < cutoff {
+            fs::remove_file(snapshot_path(dir, &date)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Dates that have a snapshot, oldest first.
+pub async fn list(dir: &str) -> Vec<String> {
+    let mut dates = Vec::new();
+    if let Ok(mut entries) = fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(date) = name.strip_suffix(SUFFIX) {
+                if parse_iso_date(date).as_deref() == Some(date) {
+                    dates.push(date.to_string());
+                }
+            }
+        }
+    }
+    dates.sort();
+    dates
+}
+
+/// The records in the snapshot for `date`, or `None` if there is none.
+pub async fn load(dir: &str, date: &str) -> Result<Option<Vec<Value>>, FetchError> {
+    match read_cache_file(&snapshot_path(dir, date)).await {
+        Ok(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A record's identity for diffing: its fields in sorted order, so a column
+/// moving in the sheet doesn't count as a change. The `id` is left out, as
+/// snapshots from before IDs existed don't have one.
+fn record_key(record: &Value) -> String {
+    match record.as_object() {
+        Some(fields) => {
+            let sorted: BTreeMap<&String, &Value> =
+                fields.iter().filter(|(key, _)| *key != "id").collect();
+            serde_json::to_string(&sorted).unwrap_or_default()
+        }
+        None => record.to_string(),
+    }
+}
+
+/// The records in `current` but not `old` (added) and in `old` but not
+/// `current` (removed). Duplicates count separately; an edited record shows
+/// up as one removal plus one addition.
+pub fn diff<'a>(old: &'a [Value], current: &'a [Value]) -> (Vec<&'a Value>, Vec<&'a Value>) {
+    let mut unmatched: HashMap<String, usize> = HashMap::new();
+    for record in old {
+        *unmatched.entry(record_key(record)).or_default() += 1;
+    }
+    let mut added = Vec::new();
+    for record in current {
+        match unmatched.get_mut(&record_key(record)) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => added.push(record),
+        }
+    }
+    let mut removed = Vec::new();
+    for record in old {
+        if let Some(count) = unmatched.get_mut(&record_key(record)) {
+            if *count > 0 {
+                *count -= 1;
+                removed.push(record);
+            }
+        }
+    }
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diffs_ignore_field_order_and_count_duplicates() {
+        let old = vec![
+            json!({"Zip": "33701", "City": "St. Petersburg"}),
+            json!({"Zip": "01850", "City": "Lowell"}),
+            json!({"Zip": "01850", "City": "Lowell"}),
+        ];
+        let current = vec![
+            json!({"City": "St. Petersburg", "Zip": "33701"}),
+            json!({"Zip": "01850", "City": "Lowell"}),
+            json!({"Zip": "36602", "City": "Mobile"}),
+        ];
+        let (added, removed) = diff(&old, &current);
+        assert_eq!(added, [&current[2]]);
+        assert_eq!(removed, [&old[2]]);
+    }
+
+    #[actix_web::test]
+    async fn archiving_prunes_old_snapshots() {
+        let dir = std::env::temp_dir().join(format!("gkcsearch-snapshots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir = dir.to_string_lossy().into_owned();
+        save(&dir, "2000-01-01", &[json!({"Zip": "33701"})])
+            .await
+            .unwrap();
+        std::fs::write(Path::new(&dir).join("notes.txt"), "not a snapshot").unwrap();
+
+        // Retention too long to subtract from now keeps every snapshot.
+        archive(&dir, &[json!({"Zip": "01850"})], u64::MAX)
+            .await
+            .unwrap();
+        assert_eq!(list(&dir).await.len(), 2);
+
+        archive(&dir, &[json!({"Zip": "01850"})], 30).await.unwrap();
+        let today = date_of(SystemTime::now());
+        assert_eq!(list(&dir).await, std::slice::from_ref(&today));
+        assert_eq!(
+            load(&dir, &today).await.unwrap(),
+            Some(vec![json!({"Zip": "01850"})])
+        );
+        assert_eq!(load(&dir, "2000-01-01").await.unwrap(), None);
+    }
+}
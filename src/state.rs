@@ -0,0 +1,953 @@
+use crate::cache::{check_cache, fetch_sheet_data, CacheCheck, DataSource, LoadTiming, Seed};
+use crate::config::Config;
+use crate::dates::format_rfc3339;
+#[cfg(feature = "sqlite")]
+use crate::db::SqlIndex;
+use crate::filter::{field_str, FilterParams, RecordFilter};
+use crate::ids;
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimiter;
+use crate::recent::{self, FirstSeen};
+use crate::response_cache::ResponseCache;
+use crate::schedule;
+use crate::search::{MatchMode, SearchCache, TrigramIndex};
+use crate::sheet::{FetchError, GoogleSheetSource, LimitedSource, ParseStats, SheetSource};
+use crate::snapshot;
+use crate::supplemental::SupplementalCache;
+use actix_web::web::Bytes;
+use cron::Schedule;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{Mutex, Semaphore};
+
+// ---------------------------------------------------------------------------
+// Shared application state: configuration plus the in-memory dataset
+// ---------------------------------------------------------------------------
+
+/// One loaded snapshot of the banned-area data and everything derived from it.
+pub struct Dataset {
+    pub records: Vec<Value>,
+    /// Every field name that appears in at least one record.
+    pub fields: BTreeSet<String>,
+    /// Every state code in the records, uppercased.
+    pub states: BTreeSet<String>,
+    /// The records' keys in sheet order, when known (empty otherwise).
+    pub columns: Vec<String>,
+    /// `(from, to)` renames applied to record keys on the way out
+    /// (`GKC_COLUMN_ALIASES`); `records` keep the sheet's names.
+    aliases: Vec<(String, String)>,
+    pub index: TrigramIndex,
+    /// The `/data` response body, serialized once per refresh.
+    pub body: Bytes,
+    /// Strong validator for `body`, already quoted for the `ETag` header.
+    pub etag: String,
+    /// When the underlying data was fetched from the sheet.
+    fetched_at: SystemTime,
+    /// How long this snapshot stays fresh (the configured TTL plus jitter).
+    pub ttl: Duration,
+    /// Where the records came from.
+    pub source: DataSource,
+    /// How long the load that produced this snapshot took, step by step.
+    pub timing: LoadTiming,
+    /// Recent `/search` results against these records.
+    pub search_cache: SearchCache,
+    /// Serialized list responses built from these records.
+    pub response_cache: ResponseCache,
+    /// When each record ID first appeared, if tracked (`GKC_FIRST_SEEN`).
+    pub first_seen: Option<FirstSeen>,
+    /// SQLite mirror of the records, when enabled with `GKC_SQLITE`.
+    #[cfg(feature = "sqlite")]
+    pub sql: Option<SqlIndex>,
+}
+
+impl Dataset {
+    /// Wrap freshly loaded records, building the search index and the
+    /// serialized `/data` body (with keys renamed per `aliases`) alongside
+    /// them.
+    pub fn new(
+        mut records: Vec<Value>,
+        fetched_at: SystemTime,
+        ttl: Duration,
+        source: DataSource,
+        aliases: Vec<(String, String)>,
+    ) -> Result<Self, serde_json::Error> {
+        ids::assign_ids(&mut records);
+        let index = TrigramIndex::build(&records);
+        let fields = records
+            .iter()
+            .filter_map(Value::as_object)
+            .flat_map(|record| record.keys().cloned())
+            .collect();
+        let states = records
+            .iter()
+            .map(|record| field_str(record, "State").trim().to_ascii_uppercase())
+            .filter(|code| !code.is_empty())
+            .collect();
+        let mut dataset = Dataset {
+            records,
+            fields,
+            states,
+            columns: Vec::new(),
+            aliases,
+            index,
+            body: Bytes::new(),
+            etag: String::new(),
+            fetched_at,
+            ttl,
+            source,
+            timing: LoadTiming::default(),
+            search_cache: SearchCache::default(),
+            response_cache: ResponseCache::default(),
+            first_seen: None,
+            #[cfg(feature = "sqlite")]
+            sql: None,
+        };
+        dataset.body = Bytes::from(serde_json::to_vec(&dataset.served_all(&dataset.records))?);
+        dataset.etag = format!("\"{:016x}\"", fnv1a64(&dataset.body));
+        Ok(dataset)
+    }
+
+    /// A record key's name in served records.
+    pub fn served_key<'a>(&'a self, key: &'a str) -> &'a str {
+        self.aliases
+            .iter()
+            .find(|(from, _)| from == key)
+            .map_or(key, |(_, to)| to)
+    }
+
+    /// A record as the endpoints serve it, with its keys renamed per
+    /// `GKC_COLUMN_ALIASES`. Lookups and filters use `records` as they are.
+    pub fn served<'a>(&self, record: &'a Value) -> Cow<'a, Value> {
+        match record.as_object() {
+            Some(fields) if !self.aliases.is_empty() => Cow::Owned(Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| (self.served_key(key).to_string(), value.clone()))
+                    .collect(),
+            )),
+            _ => Cow::Borrowed(record),
+        }
+    }
+
+    /// [`Dataset::served`] for each of `records`.
+    pub fn served_all<'a>(
+        &self,
+        records: impl IntoIterator<Item = &'a Value>,
+    ) -> Vec<Cow<'a, Value>> {
+        records
+            .into_iter()
+            .map(|record| self.served(record))
+            .collect()
+    }
+
+    /// When the data was fetched from the sheet.
+    pub fn fetched_at(&self) -> SystemTime {
+        self.fetched_at
+    }
+
+    /// Time since the data was fetched (zero if the clock went backwards).
+    pub fn age(&self) -> Duration {
+        self.fetched_at.elapsed().unwrap_or_default()
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.age() < self.ttl
+    }
+
+    /// Whether the data is older than `threshold` ([`Config::degraded_after`]);
+    /// never when that is zero. See [`AppState::is_degraded`].
+    pub fn is_older_than(&self, threshold: Duration) -> bool {
+        !threshold.is_zero() && self.age() > threshold
+    }
+
+    /// `params` resolved against these records' state codes.
+    pub fn filter(&self, params: &FilterParams, config: &Config) -> RecordFilter {
+        params.resolve(&self.states, config)
+    }
+
+    /// Indices (in sheet order) of the records matching the substring `query`
+    /// (if any) and `filter`, answered from SQLite when it is enabled.
+    pub async fn matching(
+        &self,
+        query: Option<(&str, MatchMode)>,
+        filter: &RecordFilter,
+    ) -> Vec<usize> {
+        #[cfg(feature = "sqlite")]
+        if let Some(sql) = &self.sql {
+            match sql.query(query, filter).await {
+                Ok(indices) => return indices,
+                Err(e) => println!("SQLite query failed, using the in-memory index: {}", e),
+            }
+        }
+        let candidates = match query {
+            Some((query, mode)) => self.index.search(&self.records, query, mode),
+            None => (0..self.records.len()).collect(),
+        };
+        candidates
+            .into_iter()
+            .filter(|&i| filter.matches(&self.records[i]))
+            .collect()
+    }
+}
+
+/// How the dataset handed to a request relates to the cache TTL, reported to
+/// clients in the `X-Cache-Status` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// Within the TTL (or just loaded).
+    Fresh,
+    /// Past the TTL; served while an already-running refresh completes.
+    Stale,
+    /// Past the TTL; served while this request started a background refresh.
+    Revalidating,
+}
+
+impl CacheStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CacheStatus::Fresh => "fresh",
+            CacheStatus::Stale => "stale",
+            CacheStatus::Revalidating => "revalidating",
+        }
+    }
+}
+
+/// Fields that changed between two consecutive refreshes.
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaDrift {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// RFC 3339 time of the refresh that changed the fields.
+    pub detected_at: String,
+}
+
+impl SchemaDrift {
+    /// The drift from `previous` to `current`, or `None` if the fields match.
+    fn between(previous: &BTreeSet<String>, current: &BTreeSet<String>) -> Option<Self> {
+        if previous == current {
+            return None;
+        }
+        Some(SchemaDrift {
+            added: current.difference(previous).cloned().collect(),
+            removed: previous.difference(current).cloned().collect(),
+            detected_at: format_rfc3339(SystemTime::now()),
+        })
+    }
+}
+
+/// A fetch that was refused, leaving the previous data in place: it lost a
+/// required column or most of the records.
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedRefresh {
+    pub reason: String,
+    /// RFC 3339 time of the refused fetch.
+    pub detected_at: String,
+}
+
+/// The most recent failed load.
+#[derive(Debug, Clone, Serialize)]
+pub struct LastError {
+    pub message: String,
+    /// RFC 3339 time of the failure.
+    pub at: String,
+}
+
+/// An error message with anything secret-looking in URLs (credentials, query
+/// strings such as `?key=...`) blanked out, so it is safe to show on `/health`.
+pub fn sanitize_error(message: &str) -> String {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    // scheme, optional `user:pass@`, host and path, optional query/fragment.
+    let url = URL.get_or_init(|| {
+        Regex::new(r"([a-zA-Z][a-zA-Z0-9+.-]*://)(?:[^\s/@]*@)?([^\s?#)]*)([?#][^\s)]*)?").unwrap()
+    });
+    url.replace_all(message, |caps: &regex::Captures| {
+        let query = if caps.get(3).is_some() {
+            "?[redacted]"
+        } else {
+            ""
+        };
+        format!("{}{}{}", &caps[1], &caps[2], query)
+    })
+    .into_owned()
+}
+
+/// Statistics from the last time the sheet itself was parsed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseInfo {
+    #[serde(flatten)]
+    pub stats: ParseStats,
+    /// RFC 3339 time of that parse.
+    pub parsed_at: String,
+}
+
+/// How long seed data is served before the sheet is tried again (sooner if
+/// the TTL is shorter).
+const SEED_RETRY_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long after a failed load the first background retry waits; each
+/// further failure in a row doubles it, up to `MAX_RETRY_BACKOFF`.
+const RETRY_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// The wait before retrying after `failures` failed loads in a row.
+fn retry_backoff(failures: u32) -> Duration {
+    RETRY_BACKOFF
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(MAX_RETRY_BACKOFF)
+}
+
+/// Failed loads in a row, and when a background retry may next start.
+#[derive(Debug, Default, Clone, Copy)]
+struct Retry {
+    failures: u32,
+    next_at: Option<Instant>,
+}
+
+/// Owns the sheet source and the current dataset. Kept behind an `Arc` so
+/// background refreshes can outlive the request that started them.
+struct Loader {
+    /// Replaced whole when the configuration is reloaded.
+    config: RwLock<Arc<Config>>,
+    /// Bumped each time `config` is replaced.
+    config_generation: AtomicU64,
+    source: Box<dyn SheetSource>,
+    current: RwLock<Option<Arc<Dataset>>>,
+    /// Held while a load runs, so at most one fetch is in flight at a time.
+    refresh_lock: Arc<Mutex<()>>,
+    /// Per-instance RNG for TTL jitter, so instances drift apart.
+    rng: std::sync::Mutex<StdRng>,
+    seed: Seed,
+    /// The most recent change in field names between refreshes.
+    drift: RwLock<Option<SchemaDrift>>,
+    /// The last refused fetch, cleared once the sheet is accepted again.
+    rejected: RwLock<Option<RejectedRefresh>>,
+    /// Replaced whole on each sheet fetch, so readers never see a mix.
+    parse_info: RwLock<Option<ParseInfo>>,
+    /// Why the last load failed, cleared by the next successful one.
+    last_error: RwLock<Option<LastError>>,
+    /// Reset by the next successful load, like `last_error`.
+    retry: RwLock<Retry>,
+    /// The result of the last periodic check of the disk cache.
+    cache_check: RwLock<Option<CacheCheck>>,
+    metrics: Metrics,
+}
+
+impl Loader {
+    fn current(&self) -> Option<Arc<Dataset>> {
+        self.current.read().unwrap().clone()
+    }
+
+    fn config(&self) -> Arc<Config> {
+        Arc::clone(&self.config.read().unwrap())
+    }
+
+    /// The configured TTL randomly stretched or shrunk by up to the
+    /// configured jitter percentage.
+    fn jittered_ttl(&self) -> Duration {
+        let config = self.config();
+        jitter(
+            config.cache_ttl,
+            config.cache_ttl_jitter_pct,
+            &mut *self.rng.lock().unwrap(),
+        )
+    }
+
+    /// Remember a refused refresh for `/health`.
+    fn reject(&self, reason: String) {
+        *self.rejected.write().unwrap() = Some(RejectedRefresh {
+            reason,
+            detected_at: format_rfc3339(SystemTime::now()),
+        });
+    }
+
+    /// Load from the disk cache or the sheet and publish the result,
+    /// remembering the error if it fails (or fell back to the expired cache).
+    async fn load(&self) -> Result<Arc<Dataset>, FetchError> {
+        let result = self.fetch_and_publish().await;
+        let error = match &result {
+            Ok((_, fetch_error)) => fetch_error.clone(),
+            Err(e) => Some(e.to_string()),
+        };
+        let mut retry = self.retry.write().unwrap();
+        *retry = match error {
+            Some(_) => {
+                self.metrics.count_fetch_failure();
+                let failures = retry.failures + 1;
+                Retry {
+                    failures,
+                    next_at: Some(Instant::now() + retry_backoff(failures)),
+                }
+            }
+            None => Retry::default(),
+        };
+        drop(retry);
+        *self.last_error.write().unwrap() = error.map(|message| LastError {
+            message: sanitize_error(&message),
+            at: format_rfc3339(SystemTime::now()),
+        });
+        result.map(|(dataset, _)| dataset)
+    }
+
+    /// Publish a fresh dataset, along with why the sheet couldn't be fetched
+    /// if the expired cache stood in for it.
+    async fn fetch_and_publish(&self) -> Result<(Arc<Dataset>, Option<String>), FetchError> {
+        let config = self.config();
+        let now = SystemTime::now();
+        // On a schedule, a cache written before the last scheduled time is due.
+        let ttl = match &config.refresh_cron {
+            Some(cron) => schedule::cache_ttl(cron, now).unwrap_or(config.cache_ttl),
+            None => self.jittered_ttl(),
+        };
+        let fetched = match fetch_sheet_data(self.source.as_ref(), &config, ttl, &self.seed).await {
+            Ok(fetched) => fetched,
+            Err(FetchError::Schema(reason)) => {
+                // The sheet's columns are unusable; whatever is loaded stays.
+                println!("Warning: refresh refused: {}", reason);
+                self.reject(reason.clone());
+                return Err(FetchError::Schema(reason));
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(stats) = fetched.parse_stats.clone() {
+            *self.parse_info.write().unwrap() = Some(ParseInfo {
+                stats,
+                parsed_at: format_rfc3339(SystemTime::now()),
+            });
+        }
+        match (&fetched.rejected, fetched.source) {
+            (Some(reason), _) => self.reject(reason.clone()),
+            (None, DataSource::Sheet) => *self.rejected.write().unwrap() = None,
+            (None, _) => {}
+        }
+        let mut records = match fetched.data {
+            Value::Array(records) => records,
+            other => vec![other],
+        };
+        // The expired cache standing in again for the records already
+        // served: keep them, rather than rebuilding the same dataset.
+        if fetched.fetch_error.is_some() {
+            if let Some(current) = self.current() {
+                ids::assign_ids(&mut records);
+                if current.records == records {
+                    return Ok((current, fetched.fetch_error));
+                }
+            }
+        }
+        // ...and what it loads stays fresh until the next one. Seed data only
+        // stands in until the sheet can be fetched again.
+        let ttl = match (&config.refresh_cron, fetched.source) {
+            (_, DataSource::Seed) => ttl.min(SEED_RETRY_TTL),
+            (Some(cron), _) => schedule::fresh_for(cron, fetched.fetched_at, now).unwrap_or(ttl),
+            (None, _) => ttl,
+        };
+        let started = Instant::now();
+        let mut dataset = Dataset::new(
+            records,
+            fetched.fetched_at,
+            ttl,
+            fetched.source,
+            config.column_aliases.clone(),
+        )?;
+        dataset.columns = fetched.columns;
+        #[cfg(feature = "sqlite")]
+        if config.sqlite {
+            let (records, sql_config) = (dataset.records.clone(), Arc::clone(&config));
+            match actix_web::web::block(move || SqlIndex::build(&records, &sql_config)).await {
+                Ok(Ok(sql)) => dataset.sql = Some(sql),
+                Ok(Err(e)) => println!("Failed to build the SQLite mirror: {}", e),
+                Err(e) => println!("Failed to build the SQLite mirror: {}", e),
+            }
+        }
+        dataset.search_cache = SearchCache::new(config.search_cache_size);
+        dataset.response_cache = ResponseCache::new(config.response_cache_size);
+        dataset.timing = LoadTiming {
+            serialize: started.elapsed(),
+            ..fetched.timing
+        };
+        if config.first_seen {
+            let previous = self.current();
+            let previous = previous.as_ref().map(|d| d.records.as_slice());
+            dataset.first_seen = Some(track_first_seen(&config, previous, &dataset.records).await);
+        }
+        if let Some(previous) = self.current() {
+            if let Some(drift) = SchemaDrift::between(&previous.fields, &dataset.fields) {
+                println!(
+                    "Warning: sheet columns changed (added: {:?}, removed: {:?})",
+                    drift.added, drift.removed
+                );
+                *self.drift.write().unwrap() = Some(drift);
+            }
+        }
+        let fresh = Arc::new(dataset);
+        *self.current.write().unwrap() = Some(Arc::clone(&fresh));
+        if fetched.source == DataSource::Sheet
+            && fetched.rejected.is_none()
+            && config.snapshot_retention_days > 0
+        {
+            let dir = config.snapshot_dir();
+            let retention = config.snapshot_retention_days;
+            if let Err(e) = snapshot::archive(&dir, &fresh.records, retention).await {
+                println!("Failed to archive a snapshot in {}: {}", dir, e);
+            }
+        }
+        Ok((fresh, fetched.fetch_error))
+    }
+
+    /// Compare the disk cache with the served records, unless a load is
+    /// running (and may be rewriting it) or the records didn't come from it.
+    /// Returns the result, which `/health` reports, if the check ran.
+    async fn check_cache(&self) -> Option<CacheCheck> {
+        let _guard = self.refresh_lock.try_lock().ok()?;
+        let dataset = self.current()?;
+        if !matches!(dataset.source, DataSource::Sheet | DataSource::Cache) {
+            return None;
+        }
+        let check = check_cache(&self.config(), &dataset.records, dataset.fetched_at).await;
+        *self.cache_check.write().unwrap() = Some(check.clone());
+        Some(check)
+    }
+
+    /// Whether a background retry may start: no load has failed since the
+    /// last success, or the backoff after the last failure has passed.
+    fn retry_due(&self) -> bool {
+        let next_at = self.retry.read().unwrap().next_at;
+        next_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    /// Start a background refresh unless a load is already running. Returns
+    /// whether this call started it.
+    fn spawn_refresh(self: &Arc<Self>) -> bool {
+        let guard = match Arc::clone(&self.refresh_lock).try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => return false,
+        };
+        let loader = Arc::clone(self);
+        actix_web::rt::spawn(async move {
+            let _guard = guard;
+            println!("Refreshing stale data in the background...");
+            if let Err(e) = loader.load().await {
+                println!("Background refresh failed: {}", e);
+            }
+        });
+        true
+    }
+}
+
+/// The saved first-seen times plus the IDs new in `records` (carried over
+/// from `previous` where a key edit renamed them), saved back when there are
+/// any. An unreadable file is left alone rather than overwritten.
+async fn track_first_seen(
+    config: &Config,
+    previous: Option<&[Value]>,
+    records: &[Value],
+) -> FirstSeen {
+    let path = config.first_seen_path();
+    let (mut first_seen, readable) = match recent::load(&path).await {
+        Ok(first_seen) => (first_seen, true),
+        Err(e) => {
+            println!("Failed to read first-seen times from {}: {}", path, e);
+            (FirstSeen::new(), false)
+        }
+    };
+    let carried = previous
+        .is_some_and(|previous| recent::carry_over_edits(&mut first_seen, previous, records));
+    let now = format_rfc3339(SystemTime::now());
+    if (recent::record_new(&mut first_seen, records, &now) | carried) && readable {
+        if let Err(e) = recent::save(&path, &first_seen).await {
+            println!("Failed to save first-seen times in {}: {}", path, e);
+        }
+    }
+    first_seen
+}
+
+pub struct AppState {
+    pub supplemental: SupplementalCache,
+    pub rate_limiter: RateLimiter,
+    /// The `index.html` in `GKC_STATIC_DIR` replacing the built-in page,
+    /// read once at startup like the rest of that setting.
+    pub custom_index: Option<String>,
+    loader: Arc<Loader>,
+}
+
+impl AppState {
+    /// State that fetches from the Google Sheet named in `config`.
+    pub fn new(config: Config) -> Self {
+        let source = GoogleSheetSource::new(
+            config.sheet_url.clone(),
+            &config.user_agent,
+            config.proxy.as_deref(),
+            config.max_response_bytes,
+        );
+        AppState::with_source(config, Box::new(source))
+    }
+
+    /// State that fetches from an arbitrary source (e.g. a fake in tests).
+    pub fn with_source(config: Config, source: Box<dyn SheetSource>) -> Self {
+        if config.sqlite && cfg!(not(feature = "sqlite")) {
+            println!("GKC_SQLITE is set but this build lacks the sqlite feature; ignoring it.");
+        }
+        let permits = Arc::new(Semaphore::new(config.max_concurrent_fetches.max(1)));
+        let source = Box::new(LimitedSource::new(source, permits));
+        let custom_index = config.static_dir.as_ref().and_then(|dir| {
+            std::fs::read_to_string(std::path::Path::new(dir).join("index.html")).ok()
+        });
+        AppState {
+            rate_limiter: RateLimiter::new(config.rate_limit, config.rate_burst),
+            supplemental: SupplementalCache::default(),
+            custom_index,
+            loader: Arc::new(Loader {
+                config: RwLock::new(Arc::new(config)),
+                config_generation: AtomicU64::new(0),
+                source,
+                current: RwLock::new(None),
+                refresh_lock: Arc::new(Mutex::new(())),
+                rng: std::sync::Mutex::new(StdRng::from_entropy()),
+                seed: Seed::default(),
+                drift: RwLock::new(None),
+                rejected: RwLock::new(None),
+                parse_info: RwLock::new(None),
+                last_error: RwLock::new(None),
+                retry: RwLock::new(Retry::default()),
+                cache_check: RwLock::new(None),
+                metrics: Metrics::default(),
+            }),
+        }
+    }
+
+    /// The configuration in effect. Take it once per request, so a reload
+    /// midway can't mix old and new settings.
+    pub fn config(&self) -> Arc<Config> {
+        self.loader.config()
+    }
+
+    /// Switch to `config` for every request and refresh from now on. The
+    /// loaded dataset is kept; its TTL and processing settings are the ones it
+    /// was loaded with until the next refresh.
+    pub fn replace_config(&self, config: Config) {
+        *self.loader.config.write().unwrap() = Arc::new(config);
+        self.loader.config_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// How many times the configuration has been replaced, so responses
+    /// cached under one configuration aren't replayed under the next.
+    pub fn config_generation(&self) -> u64 {
+        self.loader.config_generation.load(Ordering::SeqCst)
+    }
+
+    /// Request, cache and fetch counters since startup.
+    pub fn metrics(&self) -> &Metrics {
+        &self.loader.metrics
+    }
+
+    /// The dataset currently in memory, if any, without triggering a load.
+    pub fn loaded_dataset(&self) -> Option<Arc<Dataset>> {
+        self.loader.current()
+    }
+
+    /// The last change in sheet columns seen between refreshes, if any.
+    pub fn schema_drift(&self) -> Option<SchemaDrift> {
+        self.loader.drift.read().unwrap().clone()
+    }
+
+    /// Start loading the data in the background, e.g. at startup.
+    pub fn start_background_load(&self) {
+        self.loader.spawn_refresh();
+    }
+
+    /// Check the disk cache against the served records every `interval`,
+    /// rewriting it if it turns out missing or corrupt.
+    pub fn start_cache_checks(&self, interval: Duration) {
+        let loader = Arc::clone(&self.loader);
+        actix_web::rt::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            // The first tick is immediate; there is nothing to check yet.
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                loader.check_cache().await;
+            }
+        });
+    }
+
+    /// Run one check of the disk cache now, as [`AppState::start_cache_checks`]
+    /// does on each tick, and return what it found. `None` if it was
+    /// skipped: a load is running, or the records didn't come from the cache.
+    #[cfg(test)]
+    pub async fn check_cache_now(&self) -> Option<CacheCheck> {
+        self.loader.check_cache().await
+    }
+
+    /// Refresh from the sheet at each time in `cron`, whether or not
+    /// requests come in. Data loaded in between stays fresh until the next
+    /// one (see `Config::refresh_cron`), so the TTL never triggers a refresh.
+    pub fn start_scheduled_refreshes(&self, cron: Schedule) {
+        let loader = Arc::clone(&self.loader);
+        actix_web::rt::spawn(async move {
+            while let Some(next) = schedule::next_after(&cron, SystemTime::now()) {
+                // Wake past the scheduled time, so the load sees the cache as due.
+                while let Ok(wait) = next.duration_since(SystemTime::now()) {
+                    tokio::time::sleep(wait + Duration::from_millis(1)).await;
+                }
+                loader.spawn_refresh();
+                if let Some(after) = schedule::next_after(&cron, SystemTime::now()) {
+                    println!("Next scheduled refresh at {}", format_rfc3339(after));
+                }
+            }
+        });
+    }
+
+    /// The result of the last cache check, if one has run.
+    pub fn cache_check(&self) -> Option<CacheCheck> {
+        self.loader.cache_check.read().unwrap().clone()
+    }
+
+    /// Whether `GKC_BACKGROUND_LOAD` is on and no load has succeeded yet.
+    /// Restarts the background load if an earlier attempt failed.
+    pub fn awaiting_first_load(&self) -> bool {
+        if !self.config().background_load || self.loader.current().is_some() {
+            return false;
+        }
+        self.loader.spawn_refresh();
+        true
+    }
+
+    /// The last fetch refused as a suspicious drop in records, if the sheet
+    /// hasn't been accepted since.
+    pub fn rejected_refresh(&self) -> Option<RejectedRefresh> {
+        self.loader.rejected.read().unwrap().clone()
+    }
+
+    /// The last failed load, if no load has succeeded since.
+    pub fn last_error(&self) -> Option<LastError> {
+        self.loader.last_error.read().unwrap().clone()
+    }
+
+    /// Whether `dataset` is degraded: the last load failed and the data is
+    /// older than [`Config::degraded_after`]. Old data that nothing has
+    /// failed to replace (e.g. a long TTL) is just old.
+    pub fn is_degraded(&self, dataset: &Dataset) -> bool {
+        self.last_error().is_some() && dataset.is_older_than(self.config().degraded_after())
+    }
+
+    /// What the last parse of the sheet saw; `None` until the sheet itself
+    /// has been fetched (not just the disk cache read).
+    pub fn parse_info(&self) -> Option<ParseInfo> {
+        self.loader.parse_info.read().unwrap().clone()
+    }
+
+    /// Whether a load (foreground or background) is running right now.
+    pub fn is_refreshing(&self) -> bool {
+        self.loader.refresh_lock.try_lock().is_err()
+    }
+
+    /// Count a data lookup for `/metrics.json`, unless it repeats one the
+    /// response cache already counted (see [`uncounted`]).
+    fn count_lookup(&self, hit: bool) {
+        if LOOKUP_COUNTED.try_with(|_| ()).is_ok() {
+            return;
+        }
+        if hit {
+            self.metrics().count_cache_hit();
+        } else {
+            self.metrics().count_cache_miss();
+        }
+    }
+
+    /// Return the current dataset, reloading it (from the disk cache or the
+    /// sheet) once it is older than the cache duration.
+    pub async fn dataset(&self) -> Result<Arc<Dataset>, FetchError> {
+        self.dataset_with_status().await.map(|(dataset, _)| dataset)
+    }
+
+    /// Like [`AppState::dataset`], but within the stale-while-revalidate
+    /// window past the TTL the stale dataset is returned immediately while a
+    /// single background refresh runs.
+    pub async fn dataset_with_status(&self) -> Result<(Arc<Dataset>, CacheStatus), FetchError> {
+        self.dataset_timed()
+            .await
+            .map(|(dataset, status, _)| (dataset, status))
+    }
+
+    /// Like [`AppState::dataset_with_status`], plus the timing of the load
+    /// this call performed, or `None` if it was served from memory.
+    pub async fn dataset_timed(
+        &self,
+    ) -> Result<(Arc<Dataset>, CacheStatus, Option<LoadTiming>), FetchError> {
+        if let Some(current) = self.loader.current() {
+            if current.is_fresh() {
+                self.count_lookup(true);
+                return Ok((current, CacheStatus::Fresh, None));
+            }
+            // Once a refresh has failed, keep serving the last good data
+            // while retrying in the background, backing off between
+            // retries, rather than making every request wait on (or start
+            // another fetch from) an upstream that is down.
+            let in_window = current.age() < current.ttl + self.config().stale_while_revalidate;
+            let failing = self.last_error().is_some() && self.rejected_refresh().is_none();
+            if in_window || failing {
+                let status = if self.loader.retry_due() && self.loader.spawn_refresh() {
+                    CacheStatus::Revalidating
+                } else {
+                    CacheStatus::Stale
+                };
+                self.count_lookup(true);
+                return Ok((current, status, None));
+            }
+        }
+
+        // Nothing usable in memory: load in the foreground, letting whoever
+        // gets the lock first do the fetch for everyone waiting.
+        let _guard = self.loader.refresh_lock.lock().await;
+        if let Some(current) = self.loader.current() {
+            if current.is_fresh() {
+                self.count_lookup(true);
+                return Ok((current, CacheStatus::Fresh, None));
+            }
+        }
+        self.count_lookup(false);
+        let dataset = match self.loader.load().await {
+            Ok(dataset) => dataset,
+            // A refused refresh (lost columns) stays an error, as before.
+            Err(e @ FetchError::Schema(_)) => return Err(e),
+            Err(e) => match self.loader.current() {
+                Some(current) => {
+                    println!("Refresh failed ({}); serving the last good data.", e);
+                    return Ok((current, CacheStatus::Stale, None));
+                }
+                None => return Err(e),
+            },
+        };
+        let timing = dataset.timing;
+        Ok((dataset, CacheStatus::Fresh, Some(timing)))
+    }
+}
+
+tokio::task_local! {
+    /// Set while the response cache runs a handler for a request whose data
+    /// lookup it has already counted.
+    static LOOKUP_COUNTED: ();
+}
+
+/// Run `handler`, for a request whose data lookup was already counted,
+/// without counting the handler's own lookups in the cache metrics.
+pub async fn uncounted<F: Future>(handler: F) -> F::Output {
+    LOOKUP_COUNTED.scope((), handler).await
+}
+
+/// `ttl` stretched or shrunk by a random amount of up to `pct` percent
+/// (clamped to 0–100) either way. A `pct` that isn't a number, or a result
+/// too long for a `Duration`, leaves `ttl` as it is.
+fn jitter(ttl: Duration, pct: f64, rng: &mut impl Rng) -> Duration {
+    if !pct.is_finite() {
+        return ttl;
+    }
+    let pct = pct.clamp(0.0, 100.0);
+    if pct == 0.0 {
+        return ttl;
+    }
+    let factor = 1.0 + rng.gen_range(-pct..=pct) / 100.0;
+    Duration::try_from_secs_f64(ttl.as_secs_f64() * factor).unwrap_or(ttl)
+}
+
+/// 64-bit FNV-1a hash; stable across builds, unlike `DefaultHasher`.
+pub fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitized_errors_keep_no_url_secrets() {
+        assert_eq!(
+            sanitize_error(
+                "error sending request for url (https://user:pw@example.com/sheet?key=abc123&gid=1)"
+            ),
+            "error sending request for url (https://example.com/sheet?[redacted])"
+        );
+        assert_eq!(
+            sanitize_error("timed out reaching https://example.com/export"),
+            "timed out reaching https://example.com/export"
+        );
+    }
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_a_cap() {
+        assert_eq!(retry_backoff(1), RETRY_BACKOFF);
+        assert_eq!(retry_backoff(2), RETRY_BACKOFF * 2);
+        assert_eq!(retry_backoff(4), RETRY_BACKOFF * 8);
+        assert_eq!(retry_backoff(7), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(u32::MAX), MAX_RETRY_BACKOFF);
+    }
+
+    #[actix_web::test]
+    async fn an_unchanged_fallback_is_not_republished() {
+        struct DownSource;
+
+        impl SheetSource for DownSource {
+            fn fetch_raw(&self) -> crate::sheet::FetchFuture<'_> {
+                Box::pin(async { Err(FetchError::Io(std::io::Error::other("down"))) })
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("gkcsearch-retry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = dir.join("data_cache.json");
+        std::fs::write(&cache, r#"[{"Zip": "33701", "State": "FL"}]"#).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&cache)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(100 * 60 * 60))
+            .unwrap();
+        let config = Config {
+            cache_file: cache.to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let state = AppState::with_source(config, Box::new(DownSource));
+
+        let first = state.loader.load().await.unwrap();
+        assert!(!state.loader.retry_due());
+        let again = state.loader.load().await.unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        assert_eq!(state.loader.retry.read().unwrap().failures, 2);
+        assert_eq!(state.last_error().unwrap().message, "down");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jitter_spreads_the_ttl_within_the_percentage() {
+        let ttl = Duration::from_secs(1000);
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(jitter(ttl, 0.0, &mut rng), ttl);
+
+        let ttls: BTreeSet<Duration> = (0..200).map(|_| jitter(ttl, 10.0, &mut rng)).collect();
+        assert!(ttls.len() > 100);
+        assert!(*ttls.first().unwrap() >= Duration::from_secs(900));
+        assert!(*ttls.last().unwrap() <= Duration::from_secs(1100));
+
+        // Percentages past 100 are clamped: between zero and double the TTL.
+        for _ in 0..200 {
+            assert!(jitter(ttl, 250.0, &mut rng) <= Duration::from_secs(2000));
+        }
+        // Neither an unusable percentage nor an overlong TTL panics.
+        for pct in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(jitter(ttl, pct, &mut rng), ttl);
+        }
+        for _ in 0..200 {
+            assert!(jitter(Duration::MAX, 50.0, &mut rng) <= Duration::MAX);
+        }
+    }
+}
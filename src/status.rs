@@ -0,0 +1,159 @@
+use serde_json::{Map, Value};
+
+// ---------------------------------------------------------------------------
+// Normalized ban status from the sheet's status column
+// ---------------------------------------------------------------------------
+
+/// How strictly an area restricts kratom, as `ban_status` on each record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BanStatus {
+    Banned,
+    Restricted,
+    Pending,
+    Unknown,
+}
+
+impl BanStatus {
+    pub const ALL: [BanStatus; 4] = [
+        BanStatus::Banned,
+        BanStatus::Restricted,
+        BanStatus::Pending,
+        BanStatus::Unknown,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BanStatus::Banned => "banned",
+            BanStatus::Restricted => "restricted",
+            BanStatus::Pending => "pending",
+            BanStatus::Unknown => "unknown",
+        }
+    }
+
+    /// Parse a `?status=` value (the names above, any case).
+    pub fn from_name(name: &str) -> Option<BanStatus> {
+        BanStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Classify free-form sheet text by its words, so "urban area" or
+    /// "Bandera" isn't read as a ban. Pending wins over the others
+    /// ("proposed ban"), and restricted over banned ("restricted, not
+    /// banned"); blank or unrecognized text is `Unknown`.
+    pub fn classify(raw: &str) -> BanStatus {
+        const PENDING: [&str; 8] = [
+            "pending",
+            "proposed",
+            "proposal",
+            "introduced",
+            "upcoming",
+            "considered",
+            "considering",
+            "under consideration",
+        ];
+        const RESTRICTED: [&str; 11] = [
+            "restrict",
+            "restricts",
+            "restricted",
+            "restriction",
+            "restrictions",
+            "regulated",
+            "regulation",
+            "regulations",
+            "minimum age",
+            "21+",
+            "18+",
+        ];
+        const BANNED: [&str; 10] = [
+            "ban",
+            "bans",
+            "banned",
+            "illegal",
+            "prohibit",
+            "prohibited",
+            "prohibition",
+            "schedule",
+            "scheduled",
+            "controlled",
+        ];
+        let raw = raw.trim().to_lowercase();
+        let words: Vec<&str> = raw
+            .split(|c: char| !c.is_alphanumeric() && c != '+')
+            .filter(|word| !word.is_empty())
+            .collect();
+        // Each entry is a word, or several that must appear in a row.
+        let has_any = |phrases: &[&str]| {
+            phrases.iter().any(|phrase| {
+                let phrase: Vec<&str> = phrase.split(' ').collect();
+                words
+                    .windows(phrase.len())
+                    .any(|run| run == phrase.as_slice())
+            })
+        };
+        if words.is_empty() {
+            BanStatus::Unknown
+        } else if has_any(&PENDING) {
+            BanStatus::Pending
+        } else if has_any(&RESTRICTED) {
+            BanStatus::Restricted
+        } else if has_any(&BANNED) {
+            BanStatus::Banned
+        } else {
+            BanStatus::Unknown
+        }
+    }
+
+    /// A record's status. Records without `ban_status` (the sheet has no
+    /// status column) count as banned, as every row did before.
+    pub fn of_record(record: &Value) -> BanStatus {
+        record
+            .get("ban_status")
+            .and_then(Value::as_str)
+            .and_then(BanStatus::from_name)
+            .unwrap_or(BanStatus::Banned)
+    }
+}
+
+/// Add `ban_status` to a record from its `column` value, which is kept as is.
+/// Records without that column are left untouched.
+pub fn annotate_ban_status(record: &mut Map<String, Value>, column: &str) {
+    let Some(raw) = record.get(column).and_then(Value::as_str) else {
+        return;
+    };
+    let status = BanStatus::classify(raw);
+    record.insert(
+        "ban_status".to_string(),
+        Value::String(status.as_str().to_string()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_free_form_statuses() {
+        let cases = [
+            ("Banned", BanStatus::Banned),
+            ("BAN", BanStatus::Banned),
+            ("Illegal (Schedule I)", BanStatus::Banned),
+            ("Restricted to 21+", BanStatus::Restricted),
+            ("Regulated", BanStatus::Restricted),
+            ("Restricted, not banned", BanStatus::Restricted),
+            ("Proposed ban", BanStatus::Pending),
+            ("pending", BanStatus::Pending),
+            ("Minimum age 21", BanStatus::Restricted),
+            ("Bill under consideration", BanStatus::Pending),
+            ("", BanStatus::Unknown),
+            ("see notes", BanStatus::Unknown),
+            // Words that merely contain a keyword don't count.
+            ("Urban area only", BanStatus::Unknown),
+            ("Bandera County", BanStatus::Unknown),
+            ("Unregulated", BanStatus::Unknown),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(BanStatus::classify(raw), expected, "{:?}", raw);
+        }
+    }
+}
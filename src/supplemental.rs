@@ -0,0 +1,202 @@
+use crate::state::fnv1a64;
+use actix_web::web::Bytes;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::fs;
+
+// ---------------------------------------------------------------------------
+// Supplemental info (links, previews, tags) loaded from a local JSON file
+// ---------------------------------------------------------------------------
+
+/// The serialized supplemental file, tied to the mtime it was read at.
+pub struct Supplemental {
+    pub data: Value,
+    pub body: Bytes,
+    pub etag: String,
+    modified: SystemTime,
+    img_hosts: Option<Vec<String>>,
+}
+
+/// Why the supplemental file could not be served.
+#[derive(Debug)]
+pub enum SupplementalError {
+    Read(std::io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for SupplementalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SupplementalError::Read(e) => write!(f, "Error reading supplemental JSON file: {}", e),
+            SupplementalError::Parse(e) => write!(f, "Error parsing supplemental JSON: {}", e),
+        }
+    }
+}
+
+/// Caches the supplemental file in memory, re-reading it only when its
+/// modification time changes.
+#[derive(Default)]
+pub struct SupplementalCache {
+    current: RwLock<Option<Arc<Supplemental>>>,
+}
+
+impl SupplementalCache {
+    /// Return the contents of the supplemental file at `path`, without the
+    /// image previews hosted outside `img_hosts` (when set).
+    pub async fn get(
+        &self,
+        path: &str,
+        img_hosts: Option<&[String]>,
+    ) -> Result<Arc<Supplemental>, SupplementalError> {
+        let modified = fs::metadata(path)
+            .await
+            .and_then(|m| m.modified())
+            .map_err(SupplementalError::Read)?;
+        if let Some(current) = self.current.read().unwrap().as_ref() {
+            if current.modified == modified && current.img_hosts.as_deref() == img_hosts {
+                return Ok(Arc::clone(current));
+            }
+        }
+
+        let raw = fs::read_to_string(path)
+            .await
+            .map_err(SupplementalError::Read)?;
+        let mut data: Value = serde_json::from_str(&raw).map_err(SupplementalError::Parse)?;
+        if let Some(hosts) = img_hosts {
+            drop_foreign_previews(&mut data, hosts);
+        }
+        let body = Bytes::from(serde_json::to_vec(&data).map_err(SupplementalError::Parse)?);
+        let etag = format!("\"{:016x}\"", fnv1a64(&body));
+        println!("Loaded supplemental data from {}", path);
+
+        let fresh = Arc::new(Supplemental {
+            data,
+            body,
+            etag,
+            modified,
+            img_hosts: img_hosts.map(<[String]>::to_vec),
+        });
+        *self.current.write().unwrap() = Some(Arc::clone(&fresh));
+        Ok(fresh)
+    }
+}
+
+/// The host of `preview` when the page would show it as an image: an
+/// `http(s)` URL ending in a picture extension, like the page's own check.
+pub fn image_host(preview: &str) -> Option<String> {
+    let lower = preview.to_ascii_lowercase();
+    let is_image = lower.starts_with("http")
+        && [".png", ".jpg", ".jpeg", ".gif"]
+            .iter()
+            .any(|ext| lower.ends_with(ext));
+    if !is_image {
+        return None;
+    }
+    let url = reqwest::Url::parse(preview).ok()?;
+    url.host_str().map(str::to_ascii_lowercase)
+}
+
+/// Whether `host` is in `allowed`, where `*.example.com` covers every
+/// subdomain of example.com, as in a CSP source list.
+pub fn host_allowed(host: &str, allowed: &[String]) -> bool {
+    allowed
+        .iter()
+        .any(|pattern| match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => host == pattern,
+        })
+}
+
+/// Remove image previews whose host isn't allowed, so the page never asks
+/// for an image its CSP would block.
+fn drop_foreign_previews(data: &mut Value, allowed: &[String]) {
+    let Some(entries) = data.as_array_mut() else {
+        return;
+    };
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let Some(object) = entry.as_object_mut() else {
+            continue;
+        };
+        let host = object
+            .get("preview")
+            .and_then(Value::as_str)
+            .and_then(image_host);
+        if let Some(host) = host.filter(|host| !host_allowed(host, allowed)) {
+            println!(
+                "Dropping the preview of supplemental entry {}: {} is not in GKC_IMG_HOSTS",
+                i, host
+            );
+            object.remove("preview");
+        }
+    }
+}
+
+/// The `tags` of a supplemental entry, or none if it has no tag list.
+fn tags(entry: &Value) -> impl Iterator<Item = &str> {
+    entry
+        .get("tags")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+}
+
+fn entries(data: &Value) -> &[Value] {
+    data.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+impl Supplemental {
+    /// Every distinct tag (compared case-insensitively, spelled as first seen)
+    /// with the number of entries carrying it, most used first.
+    pub fn tag_counts(&self) -> Vec<TagCount> {
+        let mut counts: Vec<TagCount> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for entry in entries(&self.data) {
+            let mut seen = Vec::new();
+            for tag in tags(entry) {
+                let key = tag.to_lowercase();
+                if seen.contains(&key) {
+                    continue;
+                }
+                let i = *positions.entry(key.clone()).or_insert_with(|| {
+                    counts.push(TagCount {
+                        tag: tag.to_string(),
+                        count: 0,
+                    });
+                    counts.len() - 1
+                });
+                counts[i].count += 1;
+                seen.push(key);
+            }
+        }
+        counts.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.tag.to_lowercase().cmp(&b.tag.to_lowercase()))
+        });
+        counts
+    }
+
+    /// The entries carrying `tag`, case-insensitively, in file order.
+    pub fn with_tag(&self, tag: &str) -> Vec<&Value> {
+        let wanted = tag.trim().to_lowercase();
+        entries(&self.data)
+            .iter()
+            .filter(|entry| tags(entry).any(|t| t.to_lowercase() == wanted))
+            .collect()
+    }
+}
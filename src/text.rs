@@ -0,0 +1,42 @@
+use std::borrow::Cow;
+
+// ---------------------------------------------------------------------------
+// Shortening text for logs and error messages
+// ---------------------------------------------------------------------------
+
+/// Characters of a user- or sheet-supplied value quoted back in a message.
+pub const SNIPPET_CHARS: usize = 60;
+
+/// The first `n` characters of `s`, followed by "…" if anything was cut.
+/// Cuts on character boundaries, so multi-byte text never panics.
+pub fn truncate_chars(s: &str, n: usize) -> Cow<'_, str> {
+    match s.char_indices().nth(n) {
+        Some((end, _)) => Cow::Owned(format!("{}…", &s[..end])),
+        None => Cow::Borrowed(s),
+    }
+}
+
+/// [`truncate_chars`] at [`SNIPPET_CHARS`], for quoting a value in a message.
+pub fn snippet(s: &str) -> Cow<'_, str> {
+    truncate_chars(s, SNIPPET_CHARS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_on_char_boundaries() {
+        assert_eq!(truncate_chars("Lowell", 10), "Lowell");
+        assert_eq!(truncate_chars("Lowell", 6), "Lowell");
+        assert_eq!(truncate_chars("Lowell", 3), "Low…");
+        assert_eq!(truncate_chars("", 0), "");
+        assert_eq!(truncate_chars("abc", 0), "…");
+        // Two-, three- and four-byte characters.
+        assert_eq!(truncate_chars("Ñandú", 4), "Ñand…");
+        assert_eq!(truncate_chars("東京都港区", 2), "東京…");
+        assert_eq!(truncate_chars("🌿🌿🌿", 1), "🌿…");
+        assert_eq!(truncate_chars("e\u{301}e\u{301}", 1), "e…");
+        assert!(matches!(truncate_chars("short", 60), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::config::Config;
+use crate::filter::{canonical_county, field_str};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// ---------------------------------------------------------------------------
+// Per-state counts and state → city grouping (/states, /choropleth, /tree,
+// /cities)
+// ---------------------------------------------------------------------------
+
+/// The 50 states plus DC, so per-state maps can render every state.
+pub const US_STATE_CODES: [&str; 51] = [
+    "AK", "AL", "AR", "AZ", "CA", "CO", "CT", "DC", "DE", "FL", "GA", "HI", "IA", "ID", "IL", "IN",
+    "KS", "KY", "LA", "MA", "MD", "ME", "MI", "MN", "MO", "MS", "MT", "NC", "ND", "NE", "NH", "NJ",
+    "NM", "NV", "NY", "OH", "OK", "OR", "PA", "RI", "SC", "SD", "TN", "TX", "UT", "VA", "VT", "WA",
+    "WI", "WV", "WY",
+];
+
+/// Record counts per (uppercased) state code, skipping records without one.
+pub fn state_counts<'a>(records: impl IntoIterator<Item = &'a Value>) -> BTreeMap<String, usize> {
+    country_state_counts(records).into_iter().fold(
+        BTreeMap::new(),
+        |mut counts, ((_, state), count)| {
+            *counts.entry(state).or_default() += count;
+            counts
+        },
+    )
+}
+
+/// `/data?group_by=` values and the field each groups records by.
+pub const GROUP_FIELDS: [(&str, &str); 2] = [("state", "State"), ("city", "City")];
+
+/// `records` keyed by their trimmed `field` (uppercased for `State`, like
+/// [`state_counts`]), in key order and sheet order within each key. Records
+/// with a blank value are left out.
+pub fn group_by<'a>(
+    records: impl IntoIterator<Item = &'a Value>,
+    field: &str,
+) -> BTreeMap<String, Vec<&'a Value>> {
+    let mut groups: BTreeMap<String, Vec<&Value>> = BTreeMap::new();
+    for record in records {
+        let mut key = field_str(record, field).trim().to_string();
+        if field == "State" {
+            key.make_ascii_uppercase();
+        }
+        if !key.is_empty() {
+            groups.entry(key).or_default().push(record);
+        }
+    }
+    groups
+}
+
+/// Like [`state_counts`], keyed by (uppercased country, state) so the same
+/// code in different countries stays apart. The country is empty when the
+/// record has none (e.g. when the `Country` column is dropped).
+pub fn country_state_counts<'a>(
+    records: impl IntoIterator<Item = &'a Value>,
+) -> BTreeMap<(String, String), usize> {
+    let mut counts = BTreeMap::new();
+    for record in records {
+        let state = field_str(record, "State").trim().to_ascii_uppercase();
+        if !state.is_empty() {
+            let country = field_str(record, "Country").trim().to_ascii_uppercase();
+            *counts.entry((country, state)).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// One city within a state, or a synthesized "All of X County" node for
+/// records that name a county but no city (county-wide bans).
+#[derive(Debug, Serialize)]
+pub struct CityNode {
+    pub name: String,
+    pub county: String,
+    /// Whether this node stands for a whole county rather than one city.
+    pub county_wide: bool,
+    pub count: usize,
+    /// Distinct zip codes of the grouped records, sorted.
+    pub zips: Vec<String>,
+}
+
+/// The cities (and county-wide nodes) of one state.
+#[derive(Debug, Serialize)]
+pub struct StateNode {
+    pub state: String,
+    pub count: usize,
+    pub cities: Vec<CityNode>,
+}
+
+/// Group `records` into city nodes, county-wide nodes first and then cities
+/// by name. Records with neither a city nor a county are left out. Names are
+/// grouped case-insensitively; a node shows the spelling that sorts first,
+/// so the output doesn't depend on the order of the rows.
+pub fn city_nodes<'a>(
+    records: impl IntoIterator<Item = &'a Value>,
+    config: &Config,
+) -> Vec<CityNode> {
+    let mut nodes: BTreeMap<(bool, String, String), CityNode> = BTreeMap::new();
+    for record in records {
+        let city = field_str(record, "City").trim();
+        let raw_county = field_str(record, "County");
+        let county = canonical_county(raw_county, config.normalize_county);
+        let (county_wide, name) = match (city.is_empty(), county.is_empty()) {
+            (false, _) => (false, city.to_string()),
+            (true, false) => (
+                true,
+                format!("All of {}", canonical_county(raw_county, true)),
+            ),
+            (true, true) => continue,
+        };
+        let key = (!county_wide, name.to_lowercase(), county.to_lowercase());
+        let node = nodes.entry(key).or_insert_with(|| CityNode {
+            name: name.clone(),
+            county: county.clone(),
+            county_wide,
+            count: 0,
+            zips: Vec::new(),
+        });
+        if name < node.name {
+            node.name = name;
+        }
+        if county < node.county {
+            node.county = county;
+        }
+        node.count += 1;
+        let zip = field_str(record, "Zip").trim();
+        if !zip.is_empty() && !node.zips.iter().any(|z| z == zip) {
+            node.zips.push(zip.to_string());
+        }
+    }
+    nodes
+        .into_values()
+        .map(|mut node| {
+            node.zips.sort();
+            node
+        })
+        .collect()
+}
+
+/// Group `records` by state (sorted, case-insensitively) and then by city.
+pub fn state_tree<'a>(
+    records: impl IntoIterator<Item = &'a Value>,
+    config: &Config,
+) -> Vec<StateNode> {
+    let mut states: BTreeMap<String, Vec<&Value>> = BTreeMap::new();
+    for record in records {
+        let state = field_str(record, "State").trim().to_ascii_uppercase();
+        states.entry(state).or_default().push(record);
+    }
+    states
+        .into_iter()
+        .map(|(state, records)| {
+            let cities = city_nodes(records.iter().copied(), config);
+            StateNode {
+                state,
+                count: cities.iter().map(|c| c.count).sum(),
+                cities,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn counts_records_per_state_case_insensitively() {
+        let records = vec![
+            json!({"State": "FL"}),
+            json!({"State": " fl "}),
+            json!({"State": "MA"}),
+            json!({"City": "Nowhere"}),
+        ];
+        let counts = state_counts(&records);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["FL"], 2);
+        assert_eq!(counts["MA"], 1);
+    }
+
+    #[test]
+    fn country_counts_keep_same_codes_apart() {
+        let records = vec![
+            json!({"Country": "US", "State": "NL"}),
+            json!({"Country": "ca", "State": "NL"}),
+            json!({"Country": "CA", "State": "ON"}),
+        ];
+        let counts = country_state_counts(&records);
+        assert_eq!(counts[&("CA".to_string(), "NL".to_string())], 1);
+        assert_eq!(counts[&("US".to_string(), "NL".to_string())], 1);
+        assert_eq!(state_counts(&records)["NL"], 2);
+    }
+
+    #[test]
+    fn county_only_records_get_a_county_wide_node() {
+        let records = vec![
+            json!({"City": "Clearwater", "County": "Pinellas", "State": "FL", "Zip": "33755"}),
+            json!({"City": "", "County": "Pinellas", "State": "FL", "Zip": ""}),
+            json!({"City": "clearwater", "County": "Pinellas", "State": "fl", "Zip": "33756"}),
+            json!({"County": "Sarasota County", "State": "FL"}),
+            json!({"State": "FL", "Zip": "33000"}),
+        ];
+        let tree = state_tree(&records, &Config::default());
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].state, "FL");
+        assert_eq!(tree[0].count, 4);
+
+        let names: Vec<(&str, bool)> = tree[0]
+            .cities
+            .iter()
+            .map(|c| (c.name.as_str(), c.county_wide))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("All of Pinellas County", true),
+                ("All of Sarasota County", true),
+                ("Clearwater", false)
+            ]
+        );
+        assert_eq!(tree[0].cities[0].county, "Pinellas");
+        assert_eq!(tree[0].cities[2].count, 2);
+        assert_eq!(tree[0].cities[2].zips, ["33755", "33756"]);
+    }
+
+    #[test]
+    fn order_and_spelling_do_not_depend_on_row_order() {
+        let records = vec![
+            json!({"City": "ST. PETERSBURG", "County": "pinellas", "State": "fl", "Zip": "33701"}),
+            json!({"City": "Lowell", "County": "Middlesex", "State": "MA", "Zip": "01850"}),
+            json!({"City": "St. Petersburg", "County": "Pinellas", "State": "FL", "Zip": "33702"}),
+            json!({"City": "st. petersburg", "County": "Pinellas", "State": "Fl", "Zip": "33701"}),
+            json!({"City": "", "County": "Pinellas", "State": "FL"}),
+            json!({"City": "Largo", "County": "Pinellas", "State": "FL", "Zip": "33770"}),
+        ];
+        let config = Config::default();
+        let render = |records: &[Value]| {
+            (
+                serde_json::to_string(&state_tree(records, &config)).unwrap(),
+                format!("{:?}", country_state_counts(records)),
+                serde_json::to_string(&state_counts(records)).unwrap(),
+            )
+        };
+        let expected = render(&records);
+        let mut shuffled = records.clone();
+        for _ in 0..records.len() {
+            shuffled.rotate_left(1);
+            shuffled.swap(0, 3);
+            assert_eq!(render(&shuffled), expected);
+        }
+        let tree = state_tree(&records, &config);
+        assert_eq!(tree[0].cities[2].name, "ST. PETERSBURG");
+        assert_eq!(tree[0].cities[2].county, "Pinellas");
+    }
+}